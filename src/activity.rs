@@ -0,0 +1,229 @@
+use crate::piece::{PieceKind, Player, N_PIECES};
+use crate::board::Board;
+use crate::movegen::MoveGen;
+use crate::eval::{Score, Term, Trace};
+use crate::pawns::{adjacent_files, file_mask, relative_rank, rows_ahead};
+
+/*
+ * Mobility and piece placement terms that the piece-square tables can not see, because they
+ * depend on where the other pieces are. All of it is computed from attack bitboards.
+ *
+ * Scores are from white's point of view.
+ */
+
+// Per safe square, counted from a typical number of squares for the piece so that an average
+// piece scores around zero
+pub const MOBILITY: [Score; N_PIECES] = [
+    Score::new(0, 0),
+    Score::new(4, 4),
+    Score::new(5, 5),
+    Score::new(2, 4),
+    Score::new(1, 2),
+    Score::new(0, 0),
+];
+const MOBILITY_BASE: [i32; N_PIECES] = [0, 4, 6, 7, 13, 0];
+
+pub const ROOK_OPEN_FILE: Score = Score::new(25, 10);
+pub const ROOK_SEMI_OPEN_FILE: Score = Score::new(12, 5);
+pub const ROOK_ON_SEVENTH: Score = Score::new(20, 30);
+pub const BISHOP_PAIR: Score = Score::new(30, 50);
+pub const KNIGHT_OUTPOST: Score = Score::new(20, 10);
+pub const TRAPPED_BISHOP: Score = Score::new(-100, -100);
+pub const TRAPPED_ROOK: Score = Score::new(-40, -5);
+
+pub struct Activity {
+}
+
+impl Activity {
+    fn boards(board: &Board, player: Player) -> &[u64; N_PIECES] {
+        match player {
+            Player::White => &board.white,
+            Player::Black => &board.black,
+        }
+    }
+
+    fn mobility<T: Trace>(board: &Board, player: Player, trace: &mut T) -> Score {
+        let ours = board.get_occupied(player);
+        let occupied = !board.get_empty();
+        let enemy_pawns = Self::boards(board, Board::opponent(player))[PieceKind::Pawn as usize];
+
+        // Squares attacked by enemy pawns are not worth counting, a piece going there is lost
+        let safe = !ours & !MoveGen::pawn_attack_mask(enemy_pawns, Board::opponent(player));
+
+        let mut score = Score::default();
+        for kind in [PieceKind::Knight, PieceKind::Bishop, PieceKind::Rook, PieceKind::Queen] {
+            let mut bits = Self::boards(board, player)[kind as usize];
+            while bits != 0 {
+                let index = bits.trailing_zeros() as i32;
+                bits &= bits - 1;
+
+                let squares = (MoveGen::piece_attack_mask(kind, player, index, occupied) & safe).count_ones() as i32;
+                score += MOBILITY[kind as usize] * (squares - MOBILITY_BASE[kind as usize]);
+                trace.add(Term::MOBILITY + kind as usize, player, squares - MOBILITY_BASE[kind as usize]);
+            }
+        }
+
+        score
+    }
+
+    fn rooks<T: Trace>(board: &Board, player: Player, trace: &mut T) -> Score {
+        let ours = Self::boards(board, player);
+        let theirs = Self::boards(board, Board::opponent(player));
+        let mut score = Score::default();
+
+        let mut bits = ours[PieceKind::Rook as usize];
+        while bits != 0 {
+            let index = bits.trailing_zeros() as i32;
+            let (row, col) = Board::index_to_row_col(index);
+            bits &= bits - 1;
+
+            let file = file_mask(col);
+            if ours[PieceKind::Pawn as usize] & file == 0 {
+                if theirs[PieceKind::Pawn as usize] & file == 0 {
+                    score += ROOK_OPEN_FILE;
+                    trace.add(Term::ROOK_OPEN_FILE, player, 1);
+                } else {
+                    score += ROOK_SEMI_OPEN_FILE;
+                    trace.add(Term::ROOK_SEMI_OPEN_FILE, player, 1);
+                }
+            }
+
+            // Only good if there is something to attack there or the king is cut off behind it
+            if relative_rank(player, row) == 6 {
+                let seventh = 0xff_u64 << (row * 8);
+                let eighth = match player {
+                    Player::White => 0xff_u64,
+                    Player::Black => 0xff_u64 << 56,
+                };
+                if theirs[PieceKind::Pawn as usize] & seventh != 0 || theirs[PieceKind::King as usize] & eighth != 0 {
+                    score += ROOK_ON_SEVENTH;
+                    trace.add(Term::ROOK_ON_SEVENTH, player, 1);
+                }
+            }
+        }
+
+        score
+    }
+
+    // A knight on the fourth to sixth rank, defended by a pawn, that no enemy pawn can chase away
+    fn outposts<T: Trace>(board: &Board, player: Player, trace: &mut T) -> Score {
+        let ours = Self::boards(board, player);
+        let enemy_pawns = Self::boards(board, Board::opponent(player))[PieceKind::Pawn as usize];
+        let defended = MoveGen::pawn_attack_mask(ours[PieceKind::Pawn as usize], player);
+        let mut score = Score::default();
+
+        let mut bits = ours[PieceKind::Knight as usize] & defended;
+        while bits != 0 {
+            let index = bits.trailing_zeros() as i32;
+            let (row, col) = Board::index_to_row_col(index);
+            bits &= bits - 1;
+
+            let rank = relative_rank(player, row);
+            if (3..=5).contains(&rank) && enemy_pawns & adjacent_files(col) & rows_ahead(player, row) == 0 {
+                score += KNIGHT_OUTPOST;
+                trace.add(Term::KNIGHT_OUTPOST, player, 1);
+            }
+        }
+
+        score
+    }
+
+    fn trapped<T: Trace>(board: &Board, player: Player, trace: &mut T) -> Score {
+        let ours = Self::boards(board, player);
+        let enemy_pawns = Self::boards(board, Board::opponent(player))[PieceKind::Pawn as usize];
+        let occupied = !board.get_empty();
+        let mut score = Score::default();
+
+        // Bishop that took the pawn on a7 or h7 and got shut in by b6 or g6
+        let traps = match player {
+            Player::White => [((1, 0), (2, 1)), ((1, 7), (2, 6))],
+            Player::Black => [((6, 0), (5, 1)), ((6, 7), (5, 6))],
+        };
+        for ((bishop_row, bishop_col), (pawn_row, pawn_col)) in traps {
+            if ours[PieceKind::Bishop as usize] & Board::row_col_to_u64(bishop_row, bishop_col) != 0
+                && enemy_pawns & Board::row_col_to_u64(pawn_row, pawn_col) != 0 {
+                score += TRAPPED_BISHOP;
+                trace.add(Term::TRAPPED_BISHOP, player, 1);
+            }
+        }
+
+        // Rook stuck in the corner by a king that moved without castling
+        let kings = ours[PieceKind::King as usize];
+        if kings != 0 {
+            let (king_row, king_col) = Board::u64_to_row_col(kings);
+            let mut bits = ours[PieceKind::Rook as usize] & (0xff_u64 << (king_row * 8));
+            while bits != 0 {
+                let index = bits.trailing_zeros() as i32;
+                let (_, rook_col) = Board::index_to_row_col(index);
+                bits &= bits - 1;
+
+                let cornered = (king_col >= 4 && rook_col > king_col) || (king_col < 4 && rook_col < king_col);
+                let mobility = (MoveGen::rook_attack_mask(index, occupied) & !board.get_occupied(player)).count_ones();
+                // While the king is on e1 it can still castle the rook out
+                if relative_rank(player, king_row) == 0 && king_col != 4 && cornered && mobility <= 3 {
+                    score += TRAPPED_ROOK;
+                    trace.add(Term::TRAPPED_ROOK, player, 1);
+                }
+            }
+        }
+
+        score
+    }
+
+    fn side<T: Trace>(board: &Board, player: Player, trace: &mut T) -> Score {
+        let mut score = Self::mobility(board, player, trace)
+            + Self::rooks(board, player, trace)
+            + Self::outposts(board, player, trace)
+            + Self::trapped(board, player, trace);
+
+        if Self::boards(board, player)[PieceKind::Bishop as usize].count_ones() >= 2 {
+            score += BISHOP_PAIR;
+            trace.add(Term::BISHOP_PAIR, player, 1);
+        }
+
+        score
+    }
+
+    pub fn evaluate<T: Trace>(board: &Board, trace: &mut T) -> Score {
+        Self::side(board, Player::White, trace) - Self::side(board, Player::Black, trace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.apply_fen(fen.to_string());
+        board
+    }
+
+    #[test]
+    fn rook_files_and_seventh() {
+        assert_eq!(Activity::rooks(&board("4k3/p7/8/8/8/8/1P6/R3K3 w - - 0 1"), Player::White, &mut ()), ROOK_SEMI_OPEN_FILE);
+        assert_eq!(Activity::rooks(&board("4k3/1p6/8/8/8/8/1P6/R3K3 w - - 0 1"), Player::White, &mut ()), ROOK_OPEN_FILE);
+        // Cuts the king off on the eighth
+        assert_eq!(Activity::rooks(&board("4k3/R7/8/8/8/8/1P6/4K3 w - - 0 1"), Player::White, &mut ()), ROOK_OPEN_FILE + ROOK_ON_SEVENTH);
+    }
+
+    #[test]
+    fn knight_outpost() {
+        assert_eq!(Activity::outposts(&board("4k3/8/8/3N4/4P3/8/8/4K3 w - - 0 1"), Player::White, &mut ()), KNIGHT_OUTPOST);
+        // c7c6 would chase it away
+        assert_eq!(Activity::outposts(&board("4k3/2p5/8/3N4/4P3/8/8/4K3 w - - 0 1"), Player::White, &mut ()), Score::default());
+    }
+
+    #[test]
+    fn trapped_bishop() {
+        assert_eq!(Activity::trapped(&board("4k3/B7/1p6/8/8/8/8/4K3 w - - 0 1"), Player::White, &mut ()), TRAPPED_BISHOP);
+        assert_eq!(Activity::trapped(&board("4k3/8/1p6/8/8/8/b7/4K3 w - - 0 1"), Player::Black, &mut ()), Score::default());
+    }
+
+    #[test]
+    fn flipped_position_negates_the_score() {
+        let original = board("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let flipped = board("r3k2r/pppbbppp/2n2q1P/1P2p3/3pn3/BN2PNP1/P1PPQPB1/R3K2R b KQkq - 0 1");
+        assert_eq!(Activity::evaluate(&flipped, &mut ()), -Activity::evaluate(&original, &mut ()));
+    }
+}
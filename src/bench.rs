@@ -0,0 +1,48 @@
+use crate::board::Board;
+use crate::search::{Search, SearchConfig, SearchLimits, SearchStats};
+use crate::nnue::Network;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+// A mix of opening, middlegame and endgame positions
+const POSITIONS: [&str; 6] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP1PPP/R1BQ1RK1 w - - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "8/8/4k3/8/2p5/8/B2P2K1/8 w - - 0 1",
+];
+
+// Searches every position to a fixed depth and prints the node counts, so that changes to
+// the search can be compared. Evaluates with the network when there is one
+pub fn run(depth: i32, config: SearchConfig, threads: usize, network: Option<Arc<Network>>) {
+    let mut total_nodes = 0;
+    let mut stats = SearchStats::default();
+    let start = Instant::now();
+
+    for fen in POSITIONS {
+        let mut board = Board::new();
+        board.apply_fen(fen.to_string());
+
+        let mut search = Search::new(16);
+        search.config = config;
+        search.set_threads(threads);
+        search.set_network(network.clone());
+        let result = search.think(&board, &[board.hash], SearchLimits { depth: Some(depth), ..Default::default() });
+
+        println!("{:>10} nodes  score {:>6}  {}", result.nodes, result.score, fen);
+        total_nodes += result.nodes;
+        stats.pvs_researches += result.stats.pvs_researches;
+        stats.aspiration_fail_lows += result.stats.aspiration_fail_lows;
+        stats.aspiration_fail_highs += result.stats.aspiration_fail_highs;
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "{} PVS re-searches, {} aspiration fail lows, {} aspiration fail highs",
+        stats.pvs_researches, stats.aspiration_fail_lows, stats.aspiration_fail_highs
+    );
+    println!("{} nodes in {:.2}s ({:.0} nps)", total_nodes, elapsed, total_nodes as f64 / elapsed.max(0.001));
+}
@@ -1,9 +1,12 @@
 use crate::piece::{Piece, PieceKind, N_PIECES, Player};
 use crate::movegen::{ChessMove, MoveGen};
+use crate::zobrist::Zobrist;
+use crate::eval::{Eval, Score};
 use std::collections::HashMap;
 use std::fmt;
 
 // Use the PieceKind enum to index the correct boards
+#[derive(Clone)]
 pub struct Board {
     pub black: [u64; N_PIECES],
     pub white: [u64; N_PIECES],
@@ -15,6 +18,9 @@ pub struct Board {
     pub en_passant_target: Option<i32>, // Board index
     pub halfmove_clock: i32,
     pub fullmove_number: i32,
+    pub hash: u64, // Zobrist key of the position
+    pub pawn_hash: u64, // Zobrist key of the pawns only
+    pub psqt: Score, // Material and piece-square score for white, see Eval::psqt
 
     // For drawing
     selected_piece: Option<Piece>,
@@ -36,6 +42,9 @@ impl Board {
             en_passant_target: None,
             halfmove_clock: 0,
             fullmove_number: 0,
+            hash: 0,
+            pawn_hash: 0,
+            psqt: Score::default(),
             selected_piece: None,
             bits: None,
             promoting: None
@@ -127,10 +136,7 @@ impl Board {
             self.en_passant_target = None;
             index += 2;
         } else {
-            let square = &fen[index..index + 1];
-            let rank =  8 - (square.chars().nth(1).unwrap() as i32 - 'a' as i32);
-            let file = square.chars().nth(0).unwrap() as i32 - 'a' as i32;
-            self.en_passant_target = Some(Self::row_col_to_index(rank, file));
+            self.en_passant_target = Self::parse_square(&fen[index..index + 2]);
             index += 3;
         }
 
@@ -142,6 +148,48 @@ impl Board {
         // Fullmove number
         let end = fen[index..].find(' ').unwrap_or(fen.len() - index) + index;
         self.fullmove_number = fen[index..end].parse().unwrap_or(0);
+
+        self.hash = Zobrist::hash(self);
+        self.pawn_hash = Zobrist::pawns(self);
+        self.psqt = Eval::psqt(self);
+    }
+
+    // The position in Forsyth-Edwards Notation, the other way around from apply_fen
+    pub fn to_fen(&self) -> String {
+        const LETTERS: [char; N_PIECES] = ['p', 'n', 'b', 'r', 'q', 'k'];
+        let mut fen = String::new();
+
+        for row in 0..8 {
+            let mut empty = 0;
+            for col in 0..8 {
+                match self.at((row, col)) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        let letter = LETTERS[piece.kind as usize];
+                        fen.push(if piece.player == Player::White { letter.to_ascii_uppercase() } else { letter });
+                    },
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if row < 7 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.turn == Player::White { " w " } else { " b " });
+
+        let rights = [(self.white_castling_k, 'K'), (self.white_castling_q, 'Q'), (self.black_castling_k, 'k'), (self.black_castling_q, 'q')];
+        let castling: String = rights.iter().filter(|(right, _)| *right).map(|&(_, letter)| letter).collect();
+        fen.push_str(if castling.is_empty() { "-" } else { &castling });
+
+        let en_passant = self.en_passant_target.map_or(String::from("-"), Self::square_name);
+        format!("{} {} {} {}", fen, en_passant, self.halfmove_clock, self.fullmove_number.max(1))
     }
 
     pub fn winner(&self) -> Option<Player> {
@@ -189,6 +237,29 @@ impl Board {
         None
     }
 
+    // The player's pieces the way endgame tables are named, the king first, e.g. KRP
+    pub fn material_name(&self, player: Player) -> String {
+        const LETTERS: [(PieceKind, char); 5] = [
+            (PieceKind::Queen, 'Q'),
+            (PieceKind::Rook, 'R'),
+            (PieceKind::Bishop, 'B'),
+            (PieceKind::Knight, 'N'),
+            (PieceKind::Pawn, 'P'),
+        ];
+        let pieces = match player {
+            Player::White => &self.white,
+            Player::Black => &self.black,
+        };
+
+        let mut name = String::from("K");
+        for (kind, letter) in LETTERS {
+            for _ in 0..pieces[kind as usize].count_ones() {
+                name.push(letter);
+            }
+        }
+        name
+    }
+
     pub fn get_occupied(&self, player: Player) -> u64 {
         let mut pieces: u64 = 0;
 
@@ -241,6 +312,9 @@ impl Board {
         }
 
         self.promoting = None;
+        self.hash = Zobrist::hash(self);
+        self.pawn_hash = Zobrist::pawns(self);
+        self.psqt = Eval::psqt(self);
     }
 
     pub fn opponent(player: Player) -> Player {
@@ -268,6 +342,18 @@ impl Board {
         return res;
     }
 
+    // The indices of the bits that are set, lowest first
+    pub fn squares(mut bitboard: u64) -> impl Iterator<Item = i32> {
+        std::iter::from_fn(move || {
+            if bitboard == 0 {
+                return None;
+            }
+            let index = bitboard.trailing_zeros() as i32;
+            bitboard &= bitboard - 1;
+            Some(index)
+        })
+    }
+
     pub fn index_to_u64(index: i32) -> u64 {
         1_u64 << index
     }
@@ -284,10 +370,34 @@ impl Board {
         Self::index_to_u64(Self::row_col_to_index(row, col))
     }
 
+    // Algebraic name of a board index, e.g. 0 is a8
+    pub fn square_name(index: i32) -> String {
+        let (row, col) = Self::index_to_row_col(index);
+        format!("{}{}", (b'a' + col as u8) as char, 8 - row)
+    }
+
+    pub fn parse_square(name: &str) -> Option<i32> {
+        let mut chars = name.chars();
+        let file = chars.next()?;
+        let rank = chars.next()?.to_digit(10)? as i32;
+        if !('a'..='h').contains(&file) || !(1..=8).contains(&rank) {
+            return None;
+        }
+
+        Some(Self::row_col_to_index(8 - rank, file as i32 - 'a' as i32))
+    }
+
     pub fn get_turn(&self) -> Player {
         self.turn
     }
 
+    pub fn set_turn(&mut self, player: Player) {
+        if player != self.turn {
+            self.hash ^= Zobrist::side();
+            self.turn = player;
+        }
+    }
+
     pub fn swap_turns(&mut self) {
         match self.turn {
             Player::White => self.turn = Player::Black,
@@ -296,6 +406,11 @@ impl Board {
     }
 
     pub fn apply_move(&mut self, m: ChessMove) {
+        // Used to update the hash and psqt score at the end
+        let (white_before, black_before) = (self.white, self.black);
+        let castling_before = Zobrist::castling(self);
+        let en_passant_before = Zobrist::en_passant(self.en_passant_target);
+
         self.en_passant_target = None;
 
         // Handle kills
@@ -390,9 +505,26 @@ impl Board {
             }
         }
 
-        // Promotions
+        // Promotions, the GUI picks the piece afterwards through promote_to()
         match (m.player, m.kind, Board::index_to_row_col(m.to).0) {
-            (Player::White, PieceKind::Pawn, 0) | (Player::Black, PieceKind::Pawn, 7) => self.promoting = Some(Board::index_to_u64(m.to)),
+            (Player::White, PieceKind::Pawn, 0) | (Player::Black, PieceKind::Pawn, 7) => {
+                match m.promotion {
+                    Some(kind) => {
+                        let bit = Board::index_to_u64(m.to);
+                        match m.player {
+                            Player::White => {
+                                self.white[PieceKind::Pawn as usize] &= !bit;
+                                self.white[kind as usize] |= bit;
+                            },
+                            Player::Black => {
+                                self.black[PieceKind::Pawn as usize] &= !bit;
+                                self.black[kind as usize] |= bit;
+                            }
+                        }
+                    },
+                    None => self.promoting = Some(Board::index_to_u64(m.to))
+                }
+            },
             _  => ()
         }
 
@@ -436,7 +568,66 @@ impl Board {
             }
         }
 
+        // Fifty-move rule counter and move number
+        if m.kind == PieceKind::Pawn || victim.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if m.player == Player::Black {
+            self.fullmove_number += 1;
+        }
+
         self.swap_turns();
+        self.update_incremental(white_before, black_before, castling_before, en_passant_before);
+    }
+
+    // Passes the turn without moving, used by null move pruning in the search
+    pub fn apply_null_move(&mut self) {
+        self.hash ^= Zobrist::en_passant(self.en_passant_target) ^ Zobrist::side();
+        self.en_passant_target = None;
+        // Positions before the null move can not be repeated through it
+        self.halfmove_clock = 0;
+        self.swap_turns();
+    }
+
+    // Takes back a move that did not capture or promote, for walking backwards from the mates
+    // when endgame tables are generated
+    pub fn retract_move(&mut self, m: ChessMove) {
+        let (white_before, black_before) = (self.white, self.black);
+        let castling_before = Zobrist::castling(self);
+        let en_passant_before = Zobrist::en_passant(self.en_passant_target);
+
+        self.en_passant_target = None;
+        let pieces = match m.player {
+            Player::White => &mut self.white,
+            Player::Black => &mut self.black,
+        };
+        pieces[m.kind as usize] ^= Self::index_to_u64(m.to) | Self::index_to_u64(m.from);
+
+        self.swap_turns();
+        self.update_incremental(white_before, black_before, castling_before, en_passant_before);
+    }
+
+    // Only the bits that changed in each bitboard need to be looked at
+    fn update_incremental(&mut self, white_before: [u64; N_PIECES], black_before: [u64; N_PIECES], castling_before: u64, en_passant_before: u64) {
+        let mut white_changed = [0; N_PIECES];
+        let mut black_changed = [0; N_PIECES];
+        for i in 0..N_PIECES {
+            white_changed[i] = white_before[i] ^ self.white[i];
+            black_changed[i] = black_before[i] ^ self.black[i];
+        }
+
+        self.hash ^= Zobrist::pieces(&white_changed, Player::White) ^ Zobrist::pieces(&black_changed, Player::Black);
+        self.hash ^= castling_before ^ Zobrist::castling(self);
+        self.hash ^= en_passant_before ^ Zobrist::en_passant(self.en_passant_target);
+        self.hash ^= Zobrist::side();
+
+        let pawn = PieceKind::Pawn as usize;
+        self.pawn_hash ^= Zobrist::pieces(&[white_changed[pawn], 0, 0, 0, 0, 0], Player::White)
+            ^ Zobrist::pieces(&[black_changed[pawn], 0, 0, 0, 0, 0], Player::Black);
+
+        self.psqt += Eval::psqt_delta(&white_before, &black_before, self);
     }
 
     pub fn is_valid(&self, (x, y): (i32, i32), friends: u64) -> bool {
@@ -519,24 +710,26 @@ impl Board {
         (all & path_mask) == 0
     }
 
-    fn is_king_in_check(&mut self, player: Player) -> bool {
+    pub fn is_king_in_check(&self, player: Player) -> bool {
         let king_board = match player {
             Player::White => self.white[PieceKind::King as usize],
             Player::Black => self.black[PieceKind::King as usize],
         };
 
+        // The GUI lets kings get captured
+        if king_board == 0 {
+            return true;
+        }
+
         let king = Self::u64_to_row_col(king_board);
 
         self.is_square_attacked_by(king, Self::opponent(player))
     }
 
-    // NOTE: Generates all available moves - can be optimised
-    fn is_square_attacked_by(&mut self, (row, col): (i32, i32), player: Player) -> bool {
-        let mut all = MoveGen::all_no_castling(self, player);
-        all.extend(MoveGen::pawn_attacks(self, player));
+    pub fn is_square_attacked_by(&self, (row, col): (i32, i32), player: Player) -> bool {
+        let attackers = MoveGen::attackers_to(self, Self::row_col_to_index(row, col), !self.get_empty());
 
-        all.iter()
-            .any(|x| x.to == Self::row_col_to_index(row, col))
+        attackers & self.get_occupied(player) != 0
     }
 }
 
@@ -568,6 +761,7 @@ impl fmt::Display for Board {
         write!(f, "en_passant_target: {}\n", self.en_passant_target.unwrap_or(-1))?;
         write!(f, "halfmove_clock: {}\n", self.halfmove_clock)?;
         write!(f, "fullmove_number: {}\n", self.fullmove_number)?;
+        write!(f, "hash: {:016x}\n", self.hash)?;
 
         write!(f, "\nBlack bitboards")?;
         for (i, bb) in self.black.iter().enumerate() {
@@ -0,0 +1,118 @@
+use crate::board::Board;
+use crate::movegen::MoveGen;
+use crate::search::{Search, SearchInfo, SearchLimits, SearchObserver, SearchResult, StopHandle};
+use crate::skill::Skill;
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+/*
+ * ----- BOT -----
+ * The bot in the window, as an opponent or to analyse the position. It thinks on its own
+ * thread so that the window keeps drawing, and is asked every frame whether it is done yet.
+ * A result for a position that is no longer on the board, after a reset or a change of mode,
+ * is thrown away. The lines found so far come back over a channel after every iteration.
+ */
+
+const HASH_MB: usize = 16;
+
+pub struct Bot {
+    // Lent to the worker thread while it thinks
+    search: Option<Search>,
+    worker: Option<JoinHandle<(Search, SearchResult)>>,
+    stop: StopHandle,
+    // Hash of the position the worker is thinking about
+    thinking_on: u64,
+    infos: Receiver<SearchInfo>,
+    // The latest info for each line of the current search
+    progress: Vec<SearchInfo>,
+}
+
+// Passes the iterations on to the window's thread
+struct Forward(Sender<SearchInfo>);
+
+impl SearchObserver for Forward {
+    fn iteration(&mut self, info: &SearchInfo) {
+        // Nobody is listening once the bot is gone
+        let _ = self.0.send(info.clone());
+    }
+}
+
+impl Bot {
+    pub fn new() -> Self {
+        let (sender, infos) = mpsc::channel();
+        let mut search = Search::new(HASH_MB);
+        search.set_observer(Some(Box::new(Forward(sender))));
+        Self { stop: search.stop_handle(), search: Some(search), worker: None, thinking_on: 0, infos, progress: Vec::new() }
+    }
+
+    pub fn set_skill(&mut self, skill: Skill) {
+        self.cancel();
+        self.search.as_mut().unwrap().set_skill(skill);
+    }
+
+    pub fn set_multi_pv(&mut self, lines: usize) {
+        self.cancel();
+        self.search.as_mut().unwrap().set_multi_pv(lines);
+    }
+
+    // Stops thinking, and forgets what it found
+    pub fn cancel(&mut self) {
+        self.stop.stop();
+        self.join();
+        self.forget_progress();
+    }
+
+    // The lines of the current or last search so far, best first
+    pub fn progress(&mut self) -> &[SearchInfo] {
+        for info in self.infos.try_iter() {
+            match self.progress.get_mut(info.multi_pv - 1) {
+                Some(line) => *line = info,
+                None => self.progress.push(info),
+            }
+        }
+        &self.progress
+    }
+
+    // Starts thinking about the position if it is not already, and returns the result once it
+    // is done. `history` holds the hashes of the positions played so far in the game
+    pub fn poll(&mut self, board: &Board, history: &[u64], limits: SearchLimits) -> Option<SearchResult> {
+        if self.worker.is_some() && self.thinking_on != board.hash {
+            self.cancel();
+        }
+
+        match &self.worker {
+            Some(worker) if worker.is_finished() => self.join(),
+            Some(_) => None,
+            None => {
+                if MoveGen::legal(&mut board.clone()).is_empty() {
+                    return None;
+                }
+
+                let mut search = self.search.take().unwrap();
+                let board = board.clone();
+                let history = history.to_vec();
+                self.forget_progress();
+                self.stop.clear();
+                self.thinking_on = board.hash;
+
+                self.worker = Some(thread::spawn(move || {
+                    let result = search.think(&board, &history, limits);
+                    (search, result)
+                }));
+                None
+            },
+        }
+    }
+
+    fn forget_progress(&mut self) {
+        self.infos.try_iter().for_each(drop);
+        self.progress.clear();
+    }
+
+    fn join(&mut self) -> Option<SearchResult> {
+        let (search, result) = self.worker.take()?.join().expect("bot thread panicked");
+        self.search = Some(search);
+        Some(result)
+    }
+}
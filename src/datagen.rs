@@ -0,0 +1,322 @@
+use crate::piece::{PieceKind, Player};
+use crate::board::Board;
+use crate::movegen::MoveGen;
+use crate::ordering::MoveOrdering;
+use crate::search::{Search, SearchLimits};
+use crate::game::Game;
+use crate::pgn::GameResult;
+use crate::random::Random;
+use crate::tt::MATE_BOUND;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+/*
+ * ----- SELF-PLAY DATA -----
+ * Plays engine against engine from random openings, searching a fixed number of nodes per
+ * move, and records the positions with the search's score and the game's result. That is what
+ * the tuner and NNUE training learn from.
+ *
+ * Every game gets its own seed, derived from the main one, and a search cleared before it
+ * starts, so a game does not depend on which thread plays it or what it played before. Games
+ * are written in order, so the same seed and options always give the same file.
+ *
+ * Positions in check, right after the random moves, with a mate score, or where the best move
+ * is a capture or promotion are left out, their scores say little about the position itself.
+ *
+ * The file is a list of 32 byte records in marlinformat, which bullet reads directly:
+ *   - occupancy: u64, bit 0 is a1
+ *   - pieces: u128, four bits per occupied square in the order of the occupancy bits. The kind
+ *     (pawn 0 to king 5, or 6 for a rook that can still castle), plus 8 for black
+ *   - side to move in the top bit, en passant square in the low seven, 64 for none: u8
+ *   - halfmove clock: u8, fullmove number: u16
+ *   - score from white's side, in centipawns: i16
+ *   - result from white's side, 0 lost, 1 drawn, 2 won: u8
+ *   - unused: u8
+ * all little endian. The text export has a line per record, `<fen> | <score> | <result>` with
+ * the result as 1.0, 0.5 or 0.0 from white's side, which the tuner reads as well.
+ */
+
+const RECORD_SIZE: usize = 32;
+const UNMOVED_ROOK: u8 = 6;
+const NO_SQUARE: u8 = 64;
+
+// Random openings that come out further ahead than this are played again
+const MAX_OPENING_SCORE: i32 = 1000;
+// Games still going this long are called drawn
+const MAX_GAME_PLIES: usize = 600;
+
+const PROGRESS_EVERY: usize = 100;
+
+pub struct DatagenOptions {
+    pub games: usize,
+    pub nodes: u64,
+    // Random moves played before the engines take over
+    pub random_plies: usize,
+    pub seed: u64,
+    pub threads: usize,
+    pub hash_mb: usize,
+}
+
+impl Default for DatagenOptions {
+    fn default() -> Self {
+        Self { games: 1000, nodes: 5000, random_plies: 8, seed: 0, threads: 1, hash_mb: 16 }
+    }
+}
+
+pub fn run(output: &str, options: DatagenOptions) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(output)?);
+    let start = Instant::now();
+    let next_game = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel::<(usize, Vec<u8>)>();
+
+    thread::scope(|scope| -> io::Result<()> {
+        for _ in 0..options.threads.max(1) {
+            let sender = sender.clone();
+            let (next_game, options) = (&next_game, &options);
+            scope.spawn(move || {
+                let mut search = Search::new(options.hash_mb);
+                loop {
+                    let index = next_game.fetch_add(1, Ordering::Relaxed);
+                    if index >= options.games {
+                        break;
+                    }
+                    let records = play_game(&mut search, index, options);
+                    if sender.send((index, records)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // Games finish out of order, they wait here until the ones before them are written
+        let mut waiting = BTreeMap::new();
+        let (mut written, mut positions) = (0, 0);
+        for (index, records) in receiver {
+            waiting.insert(index, records);
+            while let Some(records) = waiting.remove(&written) {
+                file.write_all(&records)?;
+                positions += records.len() / RECORD_SIZE;
+                written += 1;
+
+                if written % PROGRESS_EVERY == 0 || written == options.games {
+                    let elapsed = start.elapsed().as_secs_f64();
+                    println!("{} games, {} positions, {:.0} positions/s", written, positions, positions as f64 / elapsed.max(0.001));
+                }
+            }
+        }
+
+        Ok(())
+    })?;
+
+    file.flush()
+}
+
+// The records of one game, packed
+fn play_game(search: &mut Search, index: usize, options: &DatagenOptions) -> Vec<u8> {
+    // Seeds next to each other should not give related games
+    let mut random = Random::new(options.seed ^ Random::new(index as u64).next());
+    let limits = SearchLimits { nodes: Some(options.nodes), ..Default::default() };
+    search.new_game();
+
+    let mut game = random_opening(search, &mut random, options);
+    let mut positions: Vec<(Board, i32)> = Vec::new();
+
+    let result = loop {
+        if let Some((result, _)) = game.outcome() {
+            break result;
+        }
+        if game.moves.len() >= MAX_GAME_PLIES {
+            break GameResult::Draw;
+        }
+
+        let result = search.think(&game.board, &game.history, limits);
+        let Some(best) = result.best_move else {
+            break GameResult::Draw;
+        };
+
+        let board = &game.board;
+        let noisy = best.promotion.is_some() || MoveOrdering::is_capture(board, best);
+        if !noisy && result.score.abs() < MATE_BOUND && !board.is_king_in_check(board.get_turn()) {
+            let score = if board.get_turn() == Player::White { result.score } else { -result.score };
+            positions.push((board.clone(), score));
+        }
+
+        game.play(best);
+    };
+
+    let wdl = match result {
+        GameResult::WhiteWins => 2,
+        GameResult::BlackWins => 0,
+        _ => 1,
+    };
+
+    let mut records = Vec::with_capacity(positions.len() * RECORD_SIZE);
+    for (board, score) in positions {
+        records.extend_from_slice(&pack(&board, score, wdl));
+    }
+    records
+}
+
+// Random legal moves, tried again until they leave a game that is still going and not lost
+// already for either side
+fn random_opening(search: &mut Search, random: &mut Random, options: &DatagenOptions) -> Game {
+    let mut start = Board::new();
+    start.apply_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string());
+
+    loop {
+        let mut game = Game::new(start.clone());
+        for _ in 0..options.random_plies {
+            let moves = MoveGen::legal(&mut game.board);
+            if moves.is_empty() {
+                break;
+            }
+            game.play(moves[random.below(moves.len())]);
+        }
+
+        if game.outcome().is_some() {
+            continue;
+        }
+
+        let limits = SearchLimits { nodes: Some(options.nodes), ..Default::default() };
+        if search.think(&game.board, &game.history, limits).score.abs() <= MAX_OPENING_SCORE {
+            return game;
+        }
+    }
+}
+
+fn pack(board: &Board, score: i32, wdl: u8) -> [u8; RECORD_SIZE] {
+    let mut occupancy = 0u64;
+    let mut pieces = 0u128;
+    let mut count = 0;
+
+    // In the order of the occupancy bits, a1 first
+    for square in 0..64 {
+        let index = square ^ 56;
+        let Some(piece) = board.at(Board::index_to_row_col(index)) else {
+            continue;
+        };
+
+        let mut kind = piece.kind as u8;
+        let castling_rook = match (piece.player, Board::square_name(index).as_str()) {
+            (Player::White, "h1") => board.white_castling_k,
+            (Player::White, "a1") => board.white_castling_q,
+            (Player::Black, "h8") => board.black_castling_k,
+            (Player::Black, "a8") => board.black_castling_q,
+            _ => false,
+        };
+        if piece.kind == PieceKind::Rook && castling_rook {
+            kind = UNMOVED_ROOK;
+        }
+        if piece.player == Player::Black {
+            kind |= 8;
+        }
+
+        occupancy |= 1 << square;
+        pieces |= (kind as u128) << (4 * count);
+        count += 1;
+    }
+
+    let en_passant = board.en_passant_target.map_or(NO_SQUARE, |index| (index ^ 56) as u8);
+    let turn = if board.get_turn() == Player::Black { 0x80 } else { 0 };
+
+    let mut record = [0; RECORD_SIZE];
+    record[0..8].copy_from_slice(&occupancy.to_le_bytes());
+    record[8..24].copy_from_slice(&pieces.to_le_bytes());
+    record[24] = turn | en_passant;
+    record[25] = board.halfmove_clock.clamp(0, 255) as u8;
+    record[26..28].copy_from_slice(&(board.fullmove_number.clamp(0, u16::MAX as i32) as u16).to_le_bytes());
+    record[28..30].copy_from_slice(&(score.clamp(i16::MIN as i32, i16::MAX as i32) as i16).to_le_bytes());
+    record[30] = wdl;
+    record
+}
+
+// The position, score and result of a record
+fn unpack(record: &[u8]) -> (Board, i32, u8) {
+    let occupancy = u64::from_le_bytes(record[0..8].try_into().unwrap());
+    let pieces = u128::from_le_bytes(record[8..24].try_into().unwrap());
+
+    let mut board = Board::new();
+    let mut bits = occupancy;
+    let mut count = 0;
+    while bits != 0 {
+        let index = bits.trailing_zeros() as i32 ^ 56;
+        bits &= bits - 1;
+
+        let nibble = (pieces >> (4 * count)) as u8 & 0xf;
+        count += 1;
+
+        let white = nibble & 8 == 0;
+        let kind = match nibble & 7 {
+            UNMOVED_ROOK => {
+                match Board::square_name(index).as_str() {
+                    "h1" => board.white_castling_k = true,
+                    "a1" => board.white_castling_q = true,
+                    "h8" => board.black_castling_k = true,
+                    "a8" => board.black_castling_q = true,
+                    _ => (),
+                }
+                PieceKind::Rook
+            },
+            kind => PieceKind::iterator().nth(kind as usize).copied().unwrap_or(PieceKind::Pawn),
+        };
+        board.set(kind, white, Board::index_to_u64(index));
+    }
+
+    let en_passant = record[24] & 0x7f;
+    board.en_passant_target = (en_passant < NO_SQUARE).then_some(en_passant as i32 ^ 56);
+    board.set_turn(if record[24] & 0x80 != 0 { Player::Black } else { Player::White });
+    board.halfmove_clock = record[25] as i32;
+    board.fullmove_number = u16::from_le_bytes([record[26], record[27]]) as i32;
+
+    let score = i16::from_le_bytes([record[28], record[29]]) as i32;
+    (board, score, record[30])
+}
+
+// Writes the records as text, a line each
+pub fn export(input: &str, output: &str) -> io::Result<()> {
+    let bytes = fs::read(input)?;
+    if bytes.len() % RECORD_SIZE != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a whole number of records"));
+    }
+
+    let mut file = BufWriter::new(File::create(output)?);
+    for record in bytes.chunks_exact(RECORD_SIZE) {
+        let (board, score, wdl) = unpack(record);
+        let result = ["0.0", "0.5", "1.0"][wdl.min(2) as usize];
+        writeln!(file, "{} | {} | {}", board.to_fen(), score, result)?;
+    }
+
+    println!("{} positions", bytes.len() / RECORD_SIZE);
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_round_trip() {
+        let positions = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 15, 1),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b Kq - 3 17", -250, 0),
+            ("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", 40, 2),
+            ("8/8/3k4/8/8/4K3/8/7q b - - 99 300", -29990, 0),
+        ];
+
+        for (fen, score, wdl) in positions {
+            let mut board = Board::new();
+            board.apply_fen(fen.to_string());
+
+            let (unpacked, unpacked_score, unpacked_wdl) = unpack(&pack(&board, score, wdl));
+            assert_eq!(unpacked.to_fen(), fen);
+            assert_eq!((unpacked_score, unpacked_wdl), (score, wdl), "{}", fen);
+        }
+    }
+}
@@ -2,6 +2,8 @@ use crate::{WIDTH, HEIGHT};
 use crate::board::Board;
 use crate::piece::{Player, PieceKind, N_PIECES};
 use crate::movegen::MoveGen;
+use crate::skill;
+use crate::see::hanging_pieces;
 
 use raylib::prelude::*;
 
@@ -131,18 +133,157 @@ fn draw_promotion_ui(d: &mut RaylibDrawHandle, board: &mut Board, mouse: Vector2
     }
 }
 
-pub fn draw_menu(rl: &mut RaylibHandle, thread: &RaylibThread, board: &mut Board) {
-    todo!()
+// Who moves the pieces
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+    PlayerVsPlayer,
+    // The colour the player has
+    PlayerVsBot(Player),
+    BotVsBot,
 }
 
-pub fn draw(rl: &mut RaylibHandle, thread: &RaylibThread, board: &mut Board, mouse: Vector2, black_textures: &Vec<Texture2D>, white_textures: &Vec<Texture2D>, show_bits: bool) {
+pub struct Menu {
+    pub open: bool,
+    pub mode: Mode,
+    pub skill_level: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MenuItem {
+    Mode(Mode),
+    SkillDown,
+    SkillUp,
+}
+
+const MENU_WIDTH: i32 = 400;
+const MENU_ROW: i32 = 60;
+const MENU_MODES: [(Mode, &str); 4] = [
+    (Mode::PlayerVsPlayer, "Player vs player"),
+    (Mode::PlayerVsBot(Player::White), "Play white vs bot"),
+    (Mode::PlayerVsBot(Player::Black), "Play black vs bot"),
+    (Mode::BotVsBot, "Bot vs bot"),
+];
+
+impl Menu {
+    pub fn new() -> Self {
+        Self { open: false, mode: Mode::PlayerVsPlayer, skill_level: skill::MAX_LEVEL }
+    }
+
+    // Where everything that can be clicked is, the modes in a column and the skill level's
+    // buttons on either side of it below them
+    pub fn items() -> Vec<(MenuItem, Rectangle)> {
+        let x = (WIDTH - MENU_WIDTH) / 2;
+        let y = (HEIGHT - MENU_ROW * (MENU_MODES.len() as i32 + 2)) / 2;
+        let mut items: Vec<(MenuItem, Rectangle)> = MENU_MODES
+            .iter()
+            .enumerate()
+            .map(|(i, &(mode, _))| {
+                let rect = Rectangle::new(x as f32, (y + MENU_ROW * (i as i32 + 1)) as f32, MENU_WIDTH as f32, MENU_ROW as f32);
+                (MenuItem::Mode(mode), rect)
+            })
+            .collect();
+
+        let skill_y = (y + MENU_ROW * (MENU_MODES.len() as i32 + 1)) as f32;
+        items.push((MenuItem::SkillDown, Rectangle::new(x as f32, skill_y, MENU_ROW as f32, MENU_ROW as f32)));
+        items.push((MenuItem::SkillUp, Rectangle::new((x + MENU_WIDTH - MENU_ROW) as f32, skill_y, MENU_ROW as f32, MENU_ROW as f32)));
+        items
+    }
+
+    pub fn clicked(mouse: Vector2) -> Option<MenuItem> {
+        Self::items().into_iter().find(|&(_, rect)| check_collision_point_rec(mouse, rect)).map(|(item, _)| item)
+    }
+}
+
+pub fn draw_menu(d: &mut RaylibDrawHandle, menu: &Menu, mouse: Vector2) {
+    let size = 30;
+    let x = (WIDTH - MENU_WIDTH) / 2;
+    let height = MENU_ROW * (MENU_MODES.len() as i32 + 2);
+    let y = (HEIGHT - height) / 2;
+
+    d.draw_rectangle(0, 0, WIDTH, HEIGHT, Color::BLACK.fade(0.5));
+    d.draw_rectangle(x, y, MENU_WIDTH, height, Color::WHITESMOKE);
+    d.draw_text("Mode", x + 15, y + (MENU_ROW - size) / 2, size, Color::DARKGRAY);
+
+    for (item, rect) in Menu::items() {
+        if check_collision_point_rec(mouse, rect) {
+            d.draw_rectangle_rec(rect, Color::PLUM);
+        }
+
+        let text = match item {
+            MenuItem::Mode(mode) => {
+                if mode == menu.mode {
+                    d.draw_rectangle_lines_ex(rect, 3.0, Color::BLACK);
+                }
+                MENU_MODES.iter().find(|&&(other, _)| other == mode).unwrap().1
+            },
+            MenuItem::SkillDown => "-",
+            MenuItem::SkillUp => "+",
+        };
+        let width = measure_text(text, size);
+        d.draw_text(text, rect.x as i32 + (rect.width as i32 - width) / 2, rect.y as i32 + (MENU_ROW - size) / 2, size, Color::BLACK);
+    }
+
+    let skill = format!("Skill level {}", menu.skill_level);
+    let width = measure_text(&skill, size);
+    let skill_y = y + MENU_ROW * (MENU_MODES.len() as i32 + 1);
+    d.draw_text(&skill, (WIDTH - width) / 2, skill_y + (MENU_ROW - size) / 2, size, Color::BLACK);
+}
+
+// A line of text along the bottom of the board
+fn draw_status(d: &mut RaylibDrawHandle, text: &str) {
+    let size = 20;
+    let margin = 5;
+    let width = measure_text(text, size);
+    d.draw_rectangle(0, HEIGHT - size - margin * 2, width + margin * 2, size + margin * 2, Color::BLACK.fade(0.7));
+    d.draw_text(text, margin, HEIGHT - size - margin, size, Color::WHITE);
+}
+
+// Pieces that lose material if they are taken, orange for the player to move and green for the
+// ones the player can win
+fn draw_hanging(d: &mut RaylibDrawHandle, board: &Board) {
+    for player in [Player::White, Player::Black] {
+        let colour = if player == board.get_turn() { Color::ORANGE } else { Color::LIME };
+        for (index, _) in hanging_pieces(board, player) {
+            let (row, col) = Board::index_to_row_col(index);
+            mark_tile(d, row, col, colour.fade(0.6));
+        }
+    }
+}
+
+// Lines of text along the top of the board
+fn draw_analysis(d: &mut RaylibDrawHandle, lines: &[String]) {
+    let size = 20;
+    let margin = 5;
+    let width = lines.iter().map(|line| measure_text(line, size)).max().unwrap_or(0);
+    let height = (size + margin) * lines.len() as i32 + margin;
+    d.draw_rectangle(0, 0, width + margin * 2, height, Color::BLACK.fade(0.7));
+    for (i, line) in lines.iter().enumerate() {
+        d.draw_text(line, margin, margin + (size + margin) * i as i32, size, Color::WHITE);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn draw(rl: &mut RaylibHandle, thread: &RaylibThread, board: &mut Board, mouse: Vector2, black_textures: &Vec<Texture2D>, white_textures: &Vec<Texture2D>, show_bits: bool, show_hanging: bool, status: Option<&str>, analysis: Option<&[String]>, menu: &Menu) {
     let mut d = rl.begin_drawing(thread);
     draw_tiles(&mut d, board);
+    // Halfway through a promotion the pawn is still on the last row
+    if show_hanging && board.promoting.is_none() {
+        draw_hanging(&mut d, board);
+    }
     draw_pieces(&mut d, board, black_textures, white_textures);
     if show_bits {
         draw_bits(&mut d, board);
     }
+    if let Some(text) = status {
+        draw_status(&mut d, text);
+    }
+    if let Some(lines) = analysis {
+        draw_analysis(&mut d, lines);
+    }
     if board.promoting.is_some() {
         draw_promotion_ui(&mut d, board, mouse, black_textures, white_textures);
     }
+    if menu.open {
+        draw_menu(&mut d, menu, mouse);
+    }
 }
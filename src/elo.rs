@@ -0,0 +1,214 @@
+/*
+ * ----- ELO -----
+ * What a match says about the difference in strength between two engines. Games are played in
+ * pairs, the same opening once with each colour, and once both games of a pair are done the
+ * pair is counted by how many half points it gave: 0 to 4, the pentanomial. Pairs vary less
+ * than single games because a lopsided opening cancels out, which gives tighter error bars.
+ * Until a pair is done the single games are used.
+ *
+ * The sequential probability ratio test decides between two hypotheses, that the difference
+ * is elo0 (H0) or elo1 (H1), and stops the match as soon as the log likelihood ratio crosses
+ * one of its bounds. It uses the usual normal approximation:
+ *   LLR = N (s1 - s0) (2 mean - s0 - s1) / (2 variance)
+ * where s0 and s1 are the expected scores at elo0 and elo1, and mean and variance are those of
+ * a pair's (or game's) score.
+ */
+
+// 95% of a normal distribution lies within this many standard deviations
+const Z_95: f64 = 1.959964;
+// Below any real match's, so that a test whose results are all the same so far still ends,
+// but not after a single pair, and its error bars are not 0
+const MIN_VARIANCE: f64 = 0.01;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    // False positive and false negative rates
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+// Games from the first engine's side
+#[derive(Clone, Debug, Default)]
+pub struct MatchScore {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    // Finished pairs by the half points the first engine scored in them
+    pub pairs: [u32; 5],
+}
+
+// Expected score of the stronger side
+pub fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+// The difference that gives this score, infinite for 0 or 1
+pub fn elo_from_score(score: f64) -> f64 {
+    400.0 * (score / (1.0 - score)).log10()
+}
+
+impl Sprt {
+    // H0 is accepted below the lower bound, H1 above the upper one
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    // Some(true) once H1 is accepted, Some(false) for H0
+    pub fn verdict(&self, llr: f64) -> Option<bool> {
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            Some(true)
+        } else if llr <= lower {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+impl MatchScore {
+    // `points` in half points, 2 for a win
+    pub fn add_game(&mut self, points: u32) {
+        match points {
+            2 => self.wins += 1,
+            1 => self.draws += 1,
+            _ => self.losses += 1,
+        }
+    }
+
+    pub fn add_pair(&mut self, points: u32) {
+        self.pairs[points.min(4) as usize] += 1;
+    }
+
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    // Fraction of the points the first engine scored
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    // Number of samples, their mean score and its variance, from pairs once there are any
+    fn samples(&self) -> (f64, f64, f64) {
+        let counts: Vec<(f64, u32)> = if self.pairs.iter().any(|&count| count > 0) {
+            self.pairs.iter().enumerate().map(|(points, &count)| (points as f64 / 4.0, count)).collect()
+        } else {
+            vec![(0.0, self.losses), (0.5, self.draws), (1.0, self.wins)]
+        };
+
+        let n = counts.iter().map(|&(_, count)| count as f64).sum::<f64>();
+        if n == 0.0 {
+            return (0.0, 0.5, 0.0);
+        }
+        let mean = counts.iter().map(|&(score, count)| score * count as f64).sum::<f64>() / n;
+        let variance = counts.iter().map(|&(score, count)| (score - mean).powi(2) * count as f64).sum::<f64>() / n;
+        (n, mean, variance)
+    }
+
+    // The estimated difference and how far either side of it the real one is, 95% of the time
+    pub fn elo(&self) -> (f64, f64) {
+        let (n, mean, variance) = self.samples();
+        if n == 0.0 {
+            return (0.0, f64::INFINITY);
+        }
+
+        let margin = Z_95 * (variance.max(MIN_VARIANCE) / n).sqrt();
+        let low = elo_from_score((mean - margin).max(0.0));
+        let high = elo_from_score((mean + margin).min(1.0));
+        // Nothing but wins or losses says nothing about how far apart the engines are
+        let margin = if low.is_finite() && high.is_finite() { (high - low) / 2.0 } else { f64::INFINITY };
+        (elo_from_score(mean), margin)
+    }
+
+    // How likely it is that the first engine is the stronger one
+    pub fn likelihood_of_superiority(&self) -> f64 {
+        let (n, mean, variance) = self.samples();
+        if n == 0.0 || variance == 0.0 {
+            return if mean > 0.5 { 1.0 } else if mean < 0.5 { 0.0 } else { 0.5 };
+        }
+        let z = (mean - 0.5) / (variance / n).sqrt();
+        0.5 * (1.0 + erf(z / 2f64.sqrt()))
+    }
+
+    pub fn llr(&self, sprt: &Sprt) -> f64 {
+        let (n, mean, variance) = self.samples();
+        if n == 0.0 {
+            return 0.0;
+        }
+        let variance = variance.max(MIN_VARIANCE);
+
+        let (s0, s1) = (expected_score(sprt.elo0), expected_score(sprt.elo1));
+        n * (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * variance)
+    }
+}
+
+// Abramowitz and Stegun 7.1.26, good to about 1e-7
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    (1.0 - poly * (-x * x).exp()).copysign(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() < tolerance
+    }
+
+    #[test]
+    fn scores_and_differences() {
+        assert_eq!(expected_score(0.0), 0.5);
+        assert!(close(expected_score(190.848), 0.75, 1e-6));
+        assert!(close(elo_from_score(0.75), 190.848, 1e-3));
+        assert!(close(elo_from_score(0.25), -190.848, 1e-3));
+        assert!(elo_from_score(1.0).is_infinite());
+    }
+
+    #[test]
+    fn erf_values() {
+        assert!(close(erf(0.0), 0.0, 1e-7));
+        assert!(close(erf(0.5), 0.5204999, 1e-6));
+        assert!(close(erf(1.0), 0.8427008, 1e-6));
+        assert!(close(erf(-1.0), -0.8427008, 1e-6));
+    }
+
+    #[test]
+    fn wald_bounds() {
+        let sprt = Sprt { elo0: 0.0, elo1: 10.0, alpha: 0.05, beta: 0.05 };
+        let (lower, upper) = sprt.bounds();
+        assert!(close(lower, -2.944439, 1e-6));
+        assert!(close(upper, 2.944439, 1e-6));
+        assert_eq!(sprt.verdict(3.0), Some(true));
+        assert_eq!(sprt.verdict(-3.0), Some(false));
+        assert_eq!(sprt.verdict(0.0), None);
+    }
+
+    #[test]
+    fn llr_follows_the_results() {
+        let sprt = Sprt { elo0: 0.0, elo1: 10.0, alpha: 0.05, beta: 0.05 };
+        let winning = MatchScore { wins: 120, draws: 100, losses: 80, pairs: [5, 25, 60, 40, 20] };
+        let losing = MatchScore { wins: 80, draws: 100, losses: 120, pairs: [20, 40, 60, 25, 5] };
+
+        assert!(winning.llr(&sprt) > 0.0);
+        assert!(losing.llr(&sprt) < 0.0);
+        assert!(winning.likelihood_of_superiority() > 0.99);
+        assert_eq!(MatchScore::default().llr(&sprt), 0.0);
+    }
+
+    #[test]
+    fn error_bars() {
+        // Single games, no pairs yet: a variance of 0.15 over 100 games
+        let even = MatchScore { wins: 30, draws: 40, losses: 30, pairs: [0; 5] };
+        let (elo, margin) = even.elo();
+        assert!(close(elo, 0.0, 1e-9));
+        assert!(close(margin, 53.158, 1e-3));
+
+        let sweep = MatchScore { wins: 10, draws: 0, losses: 0, pairs: [0; 5] };
+        assert!(sweep.elo().1.is_infinite());
+    }
+}
@@ -0,0 +1,388 @@
+use crate::piece::Player;
+use crate::movegen::{ChessMove, MoveGen};
+use crate::search::{Search, SearchConfig, SearchLimits};
+use crate::game::Game;
+use crate::syzygy::Tablebases;
+use crate::retrograde::EndgameTables;
+use crate::nnue::Network;
+use crate::tt::MATE;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/*
+ * ----- ENGINES -----
+ * The players in matches and tournaments: our own search, or any engine that speaks UCI, run
+ * as a child process. Both are given the game so far and the clocks, and answer with a move
+ * and the score they gave it.
+ *
+ * An engine is described by the command that runs it, or `builtin` for our own, followed by
+ * options separated by commas:
+ *   builtin,name=No LMR,LMR=false,Hash=64
+ *   ./stockfish,Threads=1,Hash=16
+ *   ./target/release/chess --uci,name=Master
+ * `name` is what results and PGN files call it. Other engines get the remaining options through
+ * setoption. Ours takes Hash, Threads, EvalFile, SyzygyPath and EndgameTables like over UCI,
+ * and turns the search features MoveOrdering, NullMove, LMR, Futility, ReverseFutility,
+ * CheckExtensions, PVS and Aspiration on and off with true or false.
+ */
+
+const BUILTIN_NAME: &str = "Chess";
+const DEFAULT_HASH: usize = 16;
+
+// How long an engine gets to answer uci and isready
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// How long past its clock an engine is waited for before it is given up on
+const MOVE_TIMEOUT_MARGIN: Duration = Duration::from_secs(1);
+
+// What an engine is told to think with, as in UCI's go. Times are in milliseconds
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GoLimits {
+    pub white_time: Option<u64>,
+    pub black_time: Option<u64>,
+    pub increment: u64,
+    pub moves_to_go: Option<u64>,
+    pub depth: Option<i32>,
+    pub nodes: Option<u64>,
+    pub move_time: Option<u64>,
+}
+
+pub struct EngineMove {
+    pub m: ChessMove,
+    // From the engine's side, with mates scored like our search does. Not every engine says
+    pub score: Option<i32>,
+}
+
+pub trait Engine: Send {
+    fn new_game(&mut self) -> io::Result<()>;
+
+    // An illegal move is an error, as is not answering in time
+    fn go(&mut self, game: &Game, limits: &GoLimits) -> io::Result<EngineMove>;
+}
+
+#[derive(Clone)]
+pub struct EngineSpec {
+    pub name: String,
+    kind: EngineKind,
+}
+
+#[derive(Clone)]
+enum EngineKind {
+    Builtin(BuiltinSetup),
+    External { command: String, options: Vec<(String, String)> },
+}
+
+// Loaded once and shared by every copy of the engine
+#[derive(Clone)]
+struct BuiltinSetup {
+    config: SearchConfig,
+    hash_mb: usize,
+    threads: usize,
+    network: Option<Arc<Network>>,
+    tablebases: Option<Arc<Tablebases>>,
+    endgames: Option<Arc<EndgameTables>>,
+}
+
+impl GoLimits {
+    fn for_player(&self, player: Player) -> SearchLimits {
+        SearchLimits {
+            depth: self.depth,
+            nodes: self.nodes,
+            move_time: self.move_time,
+            time: match player {
+                Player::White => self.white_time,
+                Player::Black => self.black_time,
+            },
+            increment: self.increment,
+            moves_to_go: self.moves_to_go,
+            infinite: false,
+        }
+    }
+
+    // As long as an engine can take before something is wrong, if there is a limit
+    fn timeout(&self, player: Player) -> Option<Duration> {
+        let time = self.move_time.or(self.for_player(player).time)?;
+        Some(Duration::from_millis(time) + MOVE_TIMEOUT_MARGIN)
+    }
+}
+
+impl EngineSpec {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parts = text.split(',');
+        let command = parts.next().unwrap_or("").trim().to_string();
+        if command.is_empty() {
+            return Err("no engine given".to_string());
+        }
+
+        let mut name = None;
+        let mut options = Vec::new();
+        for part in parts {
+            let Some((option, value)) = part.split_once('=') else {
+                return Err(format!("expected <option>=<value>, got {}", part));
+            };
+            if option.trim().eq_ignore_ascii_case("name") {
+                name = Some(value.trim().to_string());
+            } else {
+                options.push((option.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        if command == "builtin" {
+            let setup = BuiltinSetup::new(&options)?;
+            Ok(Self { name: name.unwrap_or_else(|| BUILTIN_NAME.to_string()), kind: EngineKind::Builtin(setup) })
+        } else {
+            let program = command.split_whitespace().next().unwrap_or("");
+            let stem = Path::new(program).file_stem().map_or(program.to_string(), |stem| stem.to_string_lossy().into_owned());
+            Ok(Self { name: name.unwrap_or(stem), kind: EngineKind::External { command, options } })
+        }
+    }
+
+    // A fresh copy of the engine, every game being played at the same time needs its own
+    pub fn start(&self) -> io::Result<Box<dyn Engine>> {
+        match &self.kind {
+            EngineKind::Builtin(setup) => Ok(Box::new(Builtin::new(setup))),
+            EngineKind::External { command, options } => Ok(Box::new(External::start(command, options)?)),
+        }
+    }
+}
+
+impl BuiltinSetup {
+    fn new(options: &[(String, String)]) -> Result<Self, String> {
+        let mut setup = Self {
+            config: SearchConfig::default(),
+            hash_mb: DEFAULT_HASH,
+            threads: 1,
+            network: None,
+            tablebases: None,
+            endgames: None,
+        };
+
+        for (option, value) in options {
+            let number = || value.parse::<usize>().map_err(|_| format!("{} takes a number, got {}", option, value));
+            let flag = || match value.as_str() {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(format!("{} takes true or false, got {}", option, value)),
+            };
+
+            match option.to_ascii_lowercase().as_str() {
+                "hash" => setup.hash_mb = number()?.max(1),
+                "threads" => setup.threads = number()?.max(1),
+                "evalfile" => {
+                    let network = Network::open(value).map_err(|err| format!("could not load network {}: {}", value, err))?;
+                    setup.network = Some(Arc::new(network));
+                },
+                "syzygypath" => setup.tablebases = Some(Arc::new(Tablebases::open(value))),
+                "endgametables" => {
+                    let tables = EndgameTables::open(value).map_err(|err| format!("could not open endgame tables {}: {}", value, err))?;
+                    setup.endgames = Some(Arc::new(tables));
+                },
+                "moveordering" => setup.config.move_ordering = flag()?,
+                "nullmove" => setup.config.null_move = flag()?,
+                "lmr" => setup.config.late_move_reductions = flag()?,
+                "futility" => setup.config.futility = flag()?,
+                "reversefutility" => setup.config.reverse_futility = flag()?,
+                "checkextensions" => setup.config.check_extensions = flag()?,
+                "pvs" => setup.config.pvs = flag()?,
+                "aspiration" => setup.config.aspiration = flag()?,
+                "syzygy50moverule" => setup.config.syzygy_50_move_rule = flag()?,
+                _ => return Err(format!("unknown option {}", option)),
+            }
+        }
+
+        Ok(setup)
+    }
+}
+
+struct Builtin {
+    search: Search,
+}
+
+impl Builtin {
+    fn new(setup: &BuiltinSetup) -> Self {
+        let mut search = Search::new(setup.hash_mb);
+        search.config = setup.config;
+        search.set_threads(setup.threads);
+        search.set_network(setup.network.clone());
+        search.set_tablebases(setup.tablebases.clone());
+        search.set_endgames(setup.endgames.clone());
+        Self { search }
+    }
+}
+
+impl Engine for Builtin {
+    fn new_game(&mut self) -> io::Result<()> {
+        self.search.new_game();
+        Ok(())
+    }
+
+    fn go(&mut self, game: &Game, limits: &GoLimits) -> io::Result<EngineMove> {
+        let result = self.search.think(&game.board, &game.history, limits.for_player(game.board.get_turn()));
+        match result.best_move {
+            Some(m) => Ok(EngineMove { m, score: Some(result.score) }),
+            None => Err(io::Error::other("no move to play")),
+        }
+    }
+}
+
+// Another engine, talked to over its stdin and stdout. Its output is read on a thread of its
+// own, so that an engine that hangs can be noticed
+struct External {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl External {
+    fn start(command: &str, options: &[(String, String)]) -> io::Result<Self> {
+        let mut words = command.split_whitespace();
+        let mut child = Command::new(words.next().unwrap_or(""))
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = Self { child, stdin, lines };
+        engine.send("uci")?;
+        engine.wait_for("uciok", Some(HANDSHAKE_TIMEOUT))?;
+        for (option, value) in options {
+            engine.send(&format!("setoption name {} value {}", option, value))?;
+        }
+        engine.ready()?;
+
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()
+    }
+
+    fn ready(&mut self) -> io::Result<()> {
+        self.send("isready")?;
+        self.wait_for("readyok", Some(HANDSHAKE_TIMEOUT)).map(|_| ())
+    }
+
+    // Lines up to and including the first one starting with `token`
+    fn wait_for(&mut self, token: &str, timeout: Option<Duration>) -> io::Result<Vec<String>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut lines = Vec::new();
+
+        loop {
+            let line = match deadline {
+                Some(deadline) => match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(line) => line,
+                    Err(RecvTimeoutError::Timeout) => return Err(io::Error::new(io::ErrorKind::TimedOut, format!("no {} in time", token))),
+                    Err(RecvTimeoutError::Disconnected) => return Err(io::ErrorKind::BrokenPipe.into()),
+                },
+                None => self.lines.recv().map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?,
+            };
+
+            let done = line.split_whitespace().next() == Some(token);
+            lines.push(line);
+            if done {
+                return Ok(lines);
+            }
+        }
+    }
+}
+
+// The last score in the engine's info lines, in our search's terms
+fn parse_score(lines: &[String]) -> Option<i32> {
+    lines.iter().rev().find_map(|line| {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let i = tokens.iter().position(|&token| token == "score")?;
+        let value: i32 = tokens.get(i + 2)?.parse().ok()?;
+        match *tokens.get(i + 1)? {
+            "cp" => Some(value),
+            "mate" if value > 0 => Some(MATE - (2 * value - 1)),
+            "mate" => Some(-MATE + 2 * -value),
+            _ => None,
+        }
+    })
+}
+
+impl Engine for External {
+    fn new_game(&mut self) -> io::Result<()> {
+        self.send("ucinewgame")?;
+        self.ready()
+    }
+
+    fn go(&mut self, game: &Game, limits: &GoLimits) -> io::Result<EngineMove> {
+        let moves: Vec<String> = game.moves.iter().map(|m| m.to_uci()).collect();
+        let mut position = format!("position fen {}", game.start.to_fen());
+        if !moves.is_empty() {
+            position = format!("{} moves {}", position, moves.join(" "));
+        }
+        self.send(&position)?;
+
+        let mut go = String::from("go");
+        let numbers = [
+            ("wtime", limits.white_time),
+            ("btime", limits.black_time),
+            ("winc", limits.white_time.map(|_| limits.increment)),
+            ("binc", limits.black_time.map(|_| limits.increment)),
+            ("movestogo", limits.moves_to_go),
+            ("depth", limits.depth.map(|depth| depth as u64)),
+            ("nodes", limits.nodes),
+            ("movetime", limits.move_time),
+        ];
+        for (name, value) in numbers {
+            if let Some(value) = value {
+                go.push_str(&format!(" {} {}", name, value));
+            }
+        }
+        self.send(&go)?;
+
+        let lines = match self.wait_for("bestmove", limits.timeout(game.board.get_turn())) {
+            Ok(lines) => lines,
+            Err(err) => {
+                // Whatever it was doing, its answer must not be taken for the next move
+                let _ = self.send("stop");
+                let _ = self.wait_for("bestmove", Some(MOVE_TIMEOUT_MARGIN));
+                return Err(err);
+            },
+        };
+
+        let text = lines.last().and_then(|line| line.split_whitespace().nth(1)).unwrap_or("");
+        let mut board = game.board.clone();
+        match MoveGen::from_uci(&mut board, text) {
+            Some(m) => Ok(EngineMove { m, score: parse_score(&lines) }),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("illegal move {}", text))),
+        }
+    }
+}
+
+impl Drop for External {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        for _ in 0..10 {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,445 @@
+use crate::piece::{PieceKind, Player, N_PIECES};
+use crate::board::Board;
+use crate::pawns::{Pawns, PawnTable};
+use crate::king_safety::KingSafety;
+use crate::activity::Activity;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+/*
+ * Scores are in centipawns. The piece-square tables are written the same way the board is
+ * laid out, so a8 is the first entry and h1 the last. They are from white's point of view,
+ * black looks them up with the square mirrored vertically.
+ *
+ * Every term has a middlegame and an endgame value. The two are blended by the game phase,
+ * which goes from MAX_PHASE with all pieces on the board down to 0 when only kings and pawns
+ * are left.
+ *
+ * Every weight that can be tuned has a place in one flat list, see Term. The evaluation reports
+ * which of them it used, and how often, to a Trace. The search traces into (), which does
+ * nothing and costs nothing, the tuner (tune.rs) records them.
+ */
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+pub const MAX_PHASE: i32 = 24;
+// How much each piece kind contributes to the game phase
+const PHASE_WEIGHTS: [i32; N_PIECES] = [0, 1, 1, 2, 4, 0];
+
+pub const PIECE_VALUES: [Score; N_PIECES] = [
+    Score::new(100, 120),
+    Score::new(320, 300),
+    Score::new(330, 320),
+    Score::new(500, 540),
+    Score::new(900, 950),
+    Score::new(0, 0),
+];
+
+const PAWN_MG: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+// Pawns are worth more the closer they get to promoting
+const PAWN_EG: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    80, 80, 80, 80, 80, 80, 80, 80,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    30, 30, 30, 30, 30, 30, 30, 30,
+    15, 15, 15, 15, 15, 15, 15, 15,
+     5,  5,  5,  5,  5,  5,  5,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+const KNIGHT_MG: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+const KNIGHT_EG: [i32; 64] = [
+    -40,-30,-20,-20,-20,-20,-30,-40,
+    -30,-15, -5,  0,  0, -5,-15,-30,
+    -20, -5, 10, 15, 15, 10, -5,-20,
+    -20,  0, 15, 20, 20, 15,  0,-20,
+    -20,  0, 15, 20, 20, 15,  0,-20,
+    -20, -5, 10, 15, 15, 10, -5,-20,
+    -30,-15, -5,  0,  0, -5,-15,-30,
+    -40,-30,-20,-20,-20,-20,-30,-40,
+];
+
+const BISHOP_MG: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+const BISHOP_EG: [i32; 64] = [
+    -15,-10,-10,-10,-10,-10,-10,-15,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -15,-10,-10,-10,-10,-10,-10,-15,
+];
+
+const ROOK_MG: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+const ROOK_EG: [i32; 64] = [
+     5,  5,  5,  5,  5,  5,  5,  5,
+    10, 10, 10, 10, 10, 10, 10, 10,
+     0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+];
+
+const QUEEN_MG: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+const QUEEN_EG: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+    -10,  5, 10, 10, 10, 10,  5,-10,
+     -5,  5, 10, 15, 15, 10,  5, -5,
+     -5,  5, 10, 15, 15, 10,  5, -5,
+    -10,  5, 10, 10, 10, 10,  5,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+const KING_MG: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+// With the queens gone the king should head for the centre
+const KING_EG: [i32; 64] = [
+    -50,-40,-30,-20,-20,-30,-40,-50,
+    -30,-20,-10,  0,  0,-10,-20,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-30,  0,  0,  0,  0,-30,-30,
+    -50,-30,-30,-30,-30,-30,-30,-50,
+];
+
+pub const MG_TABLES: [[i32; 64]; N_PIECES] = [PAWN_MG, KNIGHT_MG, BISHOP_MG, ROOK_MG, QUEEN_MG, KING_MG];
+pub const EG_TABLES: [[i32; 64]; N_PIECES] = [PAWN_EG, KNIGHT_EG, BISHOP_EG, ROOK_EG, QUEEN_EG, KING_EG];
+
+impl Score {
+    pub const fn new(mg: i32, eg: i32) -> Self {
+        Self { mg, eg }
+    }
+
+    // Blends the two values, phase is between 0 (endgame) and MAX_PHASE (middlegame)
+    pub fn taper(&self, phase: i32) -> i32 {
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+
+    fn add(self, other: Score) -> Score {
+        Score::new(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+
+    fn sub(self, other: Score) -> Score {
+        Score::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+
+    fn neg(self) -> Score {
+        Score::new(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Score;
+
+    fn mul(self, n: i32) -> Score {
+        Score::new(self.mg * n, self.eg * n)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, other: Score) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, other: Score) {
+        *self = *self - other;
+    }
+}
+
+// Where each tunable weight starts in the flat list, arrays take one place per entry
+pub struct Term;
+
+impl Term {
+    pub const PIECE_VALUES: usize = 0;
+    pub const PSQT: usize = Self::PIECE_VALUES + N_PIECES; // 64 per piece kind, as white sees it
+    pub const DOUBLED: usize = Self::PSQT + N_PIECES * 64;
+    pub const ISOLATED: usize = Self::DOUBLED + 1;
+    pub const BACKWARD: usize = Self::ISOLATED + 1;
+    pub const CONNECTED: usize = Self::BACKWARD + 1;
+    pub const PASSED: usize = Self::CONNECTED + 8;
+    pub const PASSED_STOP_FREE: usize = Self::PASSED + 8;
+    pub const PASSED_PATH_FREE: usize = Self::PASSED_STOP_FREE + 8;
+    pub const SHIELD_CLOSE: usize = Self::PASSED_PATH_FREE + 8;
+    pub const SHIELD_FAR: usize = Self::SHIELD_CLOSE + 1;
+    pub const SHIELD_MISSING: usize = Self::SHIELD_FAR + 1;
+    pub const STORM: usize = Self::SHIELD_MISSING + 1;
+    pub const SEMI_OPEN_FILE: usize = Self::STORM + 5;
+    pub const OPEN_FILE: usize = Self::SEMI_OPEN_FILE + 1;
+    pub const MOBILITY: usize = Self::OPEN_FILE + 1;
+    pub const ROOK_OPEN_FILE: usize = Self::MOBILITY + N_PIECES;
+    pub const ROOK_SEMI_OPEN_FILE: usize = Self::ROOK_OPEN_FILE + 1;
+    pub const ROOK_ON_SEVENTH: usize = Self::ROOK_SEMI_OPEN_FILE + 1;
+    pub const BISHOP_PAIR: usize = Self::ROOK_ON_SEVENTH + 1;
+    pub const KNIGHT_OUTPOST: usize = Self::BISHOP_PAIR + 1;
+    pub const TRAPPED_BISHOP: usize = Self::KNIGHT_OUTPOST + 1;
+    pub const TRAPPED_ROOK: usize = Self::TRAPPED_BISHOP + 1;
+    pub const COUNT: usize = Self::TRAPPED_ROOK + 1;
+}
+
+pub trait Trace {
+    // The pawn hash table is skipped while tracing, it only remembers scores
+    const ENABLED: bool;
+
+    // `player` used the weight at `term` `count` times
+    fn add(&mut self, term: usize, player: Player, count: i32);
+}
+
+impl Trace for () {
+    const ENABLED: bool = false;
+
+    #[inline(always)]
+    fn add(&mut self, _term: usize, _player: Player, _count: i32) {}
+}
+
+pub struct Eval {
+}
+
+impl Eval {
+    // Material plus placement of a single piece, positive for both players
+    pub fn piece_square(player: Player, kind: PieceKind, index: i32) -> Score {
+        let square = match player {
+            Player::White => index,
+            Player::Black => index ^ 56, // Flip the row
+        } as usize;
+
+        PIECE_VALUES[kind as usize] + Score::new(MG_TABLES[kind as usize][square], EG_TABLES[kind as usize][square])
+    }
+
+    fn sum(boards: &[u64; N_PIECES], player: Player) -> Score {
+        let mut score = Score::default();
+
+        for &kind in PieceKind::iterator() {
+            let mut bits = boards[kind as usize];
+            while bits != 0 {
+                score += Self::piece_square(player, kind, bits.trailing_zeros() as i32);
+                bits &= bits - 1;
+            }
+        }
+
+        score
+    }
+
+    // Material and piece-square score from white's point of view, computed from scratch
+    pub fn psqt(board: &Board) -> Score {
+        Self::sum(&board.white, Player::White) - Self::sum(&board.black, Player::Black)
+    }
+
+    // How much the psqt score changed between two sets of bitboards. Only the squares that
+    // changed are visited, which is what keeps Board::psqt cheap to maintain in apply_move
+    pub fn psqt_delta(white_before: &[u64; N_PIECES], black_before: &[u64; N_PIECES], board: &Board) -> Score {
+        let mut white_added = [0; N_PIECES];
+        let mut white_removed = [0; N_PIECES];
+        let mut black_added = [0; N_PIECES];
+        let mut black_removed = [0; N_PIECES];
+        for i in 0..N_PIECES {
+            white_added[i] = board.white[i] & !white_before[i];
+            white_removed[i] = white_before[i] & !board.white[i];
+            black_added[i] = board.black[i] & !black_before[i];
+            black_removed[i] = black_before[i] & !board.black[i];
+        }
+
+        Self::sum(&white_added, Player::White) - Self::sum(&white_removed, Player::White)
+            - Self::sum(&black_added, Player::Black) + Self::sum(&black_removed, Player::Black)
+    }
+
+    // Based on the non-pawn material left for both players. Promotions can push the sum
+    // above the starting material, so it is capped
+    pub fn phase(board: &Board) -> i32 {
+        let mut phase = 0;
+        for &kind in PieceKind::iterator() {
+            let count = board.white[kind as usize].count_ones() + board.black[kind as usize].count_ones();
+            phase += PHASE_WEIGHTS[kind as usize] * count as i32;
+        }
+
+        phase.min(MAX_PHASE)
+    }
+
+    // Everything besides the material and piece-square score, from white's point of view
+    fn terms<T: Trace>(board: &Board, pawn_table: &mut PawnTable, trace: &mut T) -> Score {
+        Pawns::evaluate(board, pawn_table, trace)
+            + KingSafety::evaluate(board, trace)
+            + Activity::evaluate(board, trace)
+    }
+
+    // The score before tapering, from white's point of view, with the weights that went into it
+    // counted in `trace`
+    pub fn trace<T: Trace>(board: &Board, pawn_table: &mut PawnTable, trace: &mut T) -> Score {
+        for (player, boards) in [(Player::White, &board.white), (Player::Black, &board.black)] {
+            for &kind in PieceKind::iterator() {
+                let mut bits = boards[kind as usize];
+                while bits != 0 {
+                    let square = match player {
+                        Player::White => bits.trailing_zeros(),
+                        Player::Black => bits.trailing_zeros() ^ 56,
+                    } as usize;
+                    bits &= bits - 1;
+
+                    trace.add(Term::PIECE_VALUES + kind as usize, player, 1);
+                    trace.add(Term::PSQT + kind as usize * 64 + square, player, 1);
+                }
+            }
+        }
+
+        board.psqt + Self::terms(board, pawn_table, trace)
+    }
+
+    // Score from the point of view of the player to move
+    pub fn evaluate(board: &Board, pawn_table: &mut PawnTable) -> i32 {
+        let score = board.psqt + Self::terms(board, pawn_table, &mut ());
+        let score = score.taper(Self::phase(board));
+
+        match board.get_turn() {
+            Player::White => score,
+            Player::Black => -score,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movegen::MoveGen;
+
+    const FENS: [&str; 3] = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "2r3k1/pp3ppp/4p3/3nP3/1b1P4/2N2N2/PP3PPP/R4RK1 b - - 0 18",
+        "8/5pk1/6p1/3P4/1r6/5KP1/5P2/3R4 w - - 0 40",
+    ];
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.apply_fen(fen.to_string());
+        board
+    }
+
+    // The same position with the colours swapped and the board turned around
+    fn flip(fen: &str) -> String {
+        let fields: Vec<&str> = fen.split(' ').collect();
+        let swap_case = |text: &str| -> String {
+            text.chars().map(|c| if c.is_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() }).collect()
+        };
+
+        let rows: Vec<String> = fields[0].split('/').rev().map(swap_case).collect();
+        let turn = if fields[1] == "w" { "b" } else { "w" };
+        format!("{} {} {} - {} {}", rows.join("/"), turn, swap_case(fields[2]), fields[4], fields[5])
+    }
+
+    #[test]
+    fn flipped_position_negates_the_score() {
+        for fen in FENS {
+            let (board, flipped) = (board(fen), board(&flip(fen)));
+            assert_eq!(Eval::psqt(&flipped), -Eval::psqt(&board), "{}", fen);
+            assert_eq!(Eval::evaluate(&flipped, &mut PawnTable::new()), Eval::evaluate(&board, &mut PawnTable::new()), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn incremental_psqt_matches_recomputed() {
+        for fen in FENS {
+            let mut start = board(fen);
+            let turn = start.get_turn();
+            for m in MoveGen::all(&mut start, turn) {
+                let mut child = board(fen);
+                child.apply_move(m);
+                assert_eq!(child.psqt, Eval::psqt(&child), "{} after {:?}", fen, m);
+            }
+        }
+    }
+
+    #[test]
+    fn taper_endpoints() {
+        let score = Score::new(120, -40);
+        assert_eq!(score.taper(MAX_PHASE), 120);
+        assert_eq!(score.taper(0), -40);
+        assert_eq!(score.taper(MAX_PHASE / 2), 40);
+
+        assert_eq!(Eval::phase(&board("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")), MAX_PHASE);
+        assert_eq!(Eval::phase(&board("4k3/pppp4/8/8/8/8/4PPPP/4K3 w - - 0 1")), 0);
+        // Extra queens do not push it past the middlegame
+        assert_eq!(Eval::phase(&board("qqqqkqqq/8/8/8/8/8/8/QQQQKQQQ w - - 0 1")), MAX_PHASE);
+    }
+}
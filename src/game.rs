@@ -0,0 +1,98 @@
+use crate::piece::{PieceKind, Player};
+use crate::board::Board;
+use crate::movegen::{ChessMove, MoveGen};
+use crate::pgn::{GameResult, PgnGame};
+
+// A game being played, with what is needed to tell when it is over
+#[derive(Clone)]
+pub struct Game {
+    // Where the game started, the moves are played from here
+    pub start: Board,
+    pub board: Board,
+    // Hashes of every position so far, the current one last, for repetitions and the search
+    pub history: Vec<u64>,
+    pub moves: Vec<ChessMove>,
+}
+
+impl Game {
+    pub fn new(board: Board) -> Self {
+        Self { start: board.clone(), history: vec![board.hash], board, moves: Vec::new() }
+    }
+
+    pub fn play(&mut self, m: ChessMove) {
+        self.board.apply_move(m);
+        self.history.push(self.board.hash);
+        self.moves.push(m);
+    }
+
+    // The result and why, once the rules end the game
+    pub fn outcome(&self) -> Option<(GameResult, &'static str)> {
+        let mut board = self.board.clone();
+        if MoveGen::legal(&mut board).is_empty() {
+            return Some(if !board.is_king_in_check(board.get_turn()) {
+                (GameResult::Draw, "stalemate")
+            } else if board.get_turn() == Player::White {
+                (GameResult::BlackWins, "checkmate")
+            } else {
+                (GameResult::WhiteWins, "checkmate")
+            });
+        }
+
+        if board.halfmove_clock >= 100 {
+            return Some((GameResult::Draw, "fifty move rule"));
+        }
+
+        // Same positions only come back with the same player to move, since the last capture or
+        // pawn move
+        let repetitions = self.history
+            .iter()
+            .rev()
+            .step_by(2)
+            .take(board.halfmove_clock as usize / 2 + 1)
+            .filter(|&&hash| hash == board.hash)
+            .count();
+        if repetitions >= 3 {
+            return Some((GameResult::Draw, "threefold repetition"));
+        }
+
+        if Self::insufficient_material(&board) {
+            return Some((GameResult::Draw, "insufficient material"));
+        }
+
+        None
+    }
+
+    // The moves in SAN, with a FEN tag when the game did not start from the usual position
+    pub fn to_pgn(&self, mut tags: Vec<(String, String)>, result: GameResult) -> PgnGame {
+        let fen = self.start.to_fen();
+        if fen != "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1" {
+            tags.push(("SetUp".to_string(), "1".to_string()));
+            tags.push(("FEN".to_string(), fen));
+        }
+
+        let mut board = self.start.clone();
+        let moves = self.moves
+            .iter()
+            .map(|&m| {
+                let san = MoveGen::to_san(&mut board, m);
+                board.apply_move(m);
+                san
+            })
+            .collect();
+
+        PgnGame { tags, moves, result }
+    }
+
+    // Only kings, or kings and a single knight or bishop
+    fn insufficient_material(board: &Board) -> bool {
+        let heavy = [PieceKind::Pawn, PieceKind::Rook, PieceKind::Queen]
+            .iter()
+            .any(|&kind| board.white[kind as usize] | board.black[kind as usize] != 0);
+        let minors = [PieceKind::Knight, PieceKind::Bishop]
+            .iter()
+            .map(|&kind| (board.white[kind as usize] | board.black[kind as usize]).count_ones())
+            .sum::<u32>();
+
+        !heavy && minors <= 1
+    }
+}
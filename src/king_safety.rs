@@ -0,0 +1,209 @@
+use crate::piece::{PieceKind, Player, N_PIECES};
+use crate::board::Board;
+use crate::movegen::MoveGen;
+use crate::eval::{Score, Term, Trace};
+use crate::pawns::{file_mask, rows_ahead};
+
+/*
+ * King safety is mostly a middlegame concern, in the endgame the king is supposed to be
+ * active, so most of these terms have a small or zero endgame value.
+ *
+ * Scores are from white's point of view.
+ */
+
+// Own pawns one and two rows in front of the king
+pub const SHIELD_CLOSE: Score = Score::new(12, 0);
+pub const SHIELD_FAR: Score = Score::new(6, 0);
+pub const SHIELD_MISSING: Score = Score::new(-12, 0);
+
+// Enemy pawns coming towards the king, indexed by how many rows they are away from it
+pub const STORM: [Score; 5] = [
+    Score::new(0, 0),
+    Score::new(-5, 0), // Blocked right in front of the king
+    Score::new(-25, -5),
+    Score::new(-15, 0),
+    Score::new(-5, 0),
+];
+
+pub const SEMI_OPEN_FILE: Score = Score::new(-15, 0);
+pub const OPEN_FILE: Score = Score::new(-25, 0);
+
+// How dangerous each piece kind is per square it attacks in the king zone
+const ATTACK_WEIGHTS: [i32; N_PIECES] = [0, 2, 2, 3, 5, 0];
+
+// Penalty in centipawns by attack units, grows quadratically before flattening out
+const SAFETY_TABLE: [i32; 64] = {
+    let mut table = [0; 64];
+    let mut i = 0;
+    while i < 64 {
+        let value = (i * i) as i32 * 5 / 4;
+        table[i] = if value > 500 { 500 } else { value };
+        i += 1;
+    }
+    table
+};
+
+pub struct KingSafety {
+}
+
+impl KingSafety {
+    fn boards(board: &Board, player: Player) -> &[u64; N_PIECES] {
+        match player {
+            Player::White => &board.white,
+            Player::Black => &board.black,
+        }
+    }
+
+    // Shield, storm and open files on the king's file and the ones next to it
+    fn pawns_around<T: Trace>(board: &Board, player: Player, king: i32, trace: &mut T) -> Score {
+        let ours = Self::boards(board, player)[PieceKind::Pawn as usize];
+        let theirs = Self::boards(board, Board::opponent(player))[PieceKind::Pawn as usize];
+        let (king_row, king_col) = Board::index_to_row_col(king);
+        let ahead = rows_ahead(player, king_row);
+        let mut score = Score::default();
+
+        // A king on the edge still looks at three files
+        let centre = king_col.clamp(1, 6);
+        for col in centre - 1..=centre + 1 {
+            let file = file_mask(col);
+
+            let shield = ours & file & ahead;
+            if shield == 0 {
+                score += SHIELD_MISSING;
+                trace.add(Term::SHIELD_MISSING, player, 1);
+            } else {
+                let closest = match player {
+                    Player::White => 63 - shield.leading_zeros() as i32,
+                    Player::Black => shield.trailing_zeros() as i32,
+                };
+                match (Board::index_to_row_col(closest).0 - king_row).abs() {
+                    1 => {
+                        score += SHIELD_CLOSE;
+                        trace.add(Term::SHIELD_CLOSE, player, 1);
+                    },
+                    2 => {
+                        score += SHIELD_FAR;
+                        trace.add(Term::SHIELD_FAR, player, 1);
+                    },
+                    _ => (),
+                }
+            }
+
+            let storm = theirs & file & ahead;
+            if storm != 0 {
+                let closest = match player {
+                    Player::White => 63 - storm.leading_zeros() as i32,
+                    Player::Black => storm.trailing_zeros() as i32,
+                };
+                let distance = (Board::index_to_row_col(closest).0 - king_row).unsigned_abs() as usize;
+                if distance < STORM.len() {
+                    score += STORM[distance];
+                    trace.add(Term::STORM + distance, player, 1);
+                }
+            }
+
+            if ours & file == 0 {
+                if theirs & file == 0 {
+                    score += OPEN_FILE;
+                    trace.add(Term::OPEN_FILE, player, 1);
+                } else {
+                    score += SEMI_OPEN_FILE;
+                    trace.add(Term::SEMI_OPEN_FILE, player, 1);
+                }
+            }
+        }
+
+        score
+    }
+
+    // Adds up how hard the enemy pieces hit the squares around the king
+    fn attacks(board: &Board, player: Player, king: i32) -> Score {
+        let enemy = Board::opponent(player);
+        let occupied = !board.get_empty();
+        let zone = MoveGen::king_attack_mask(king) | 1_u64 << king;
+
+        let mut units = 0;
+        let mut attackers = 0;
+        for kind in [PieceKind::Knight, PieceKind::Bishop, PieceKind::Rook, PieceKind::Queen] {
+            let mut bits = Self::boards(board, enemy)[kind as usize];
+            while bits != 0 {
+                let index = bits.trailing_zeros() as i32;
+                bits &= bits - 1;
+
+                let hits = MoveGen::piece_attack_mask(kind, enemy, index, occupied) & zone;
+                if hits != 0 {
+                    attackers += 1;
+                    units += ATTACK_WEIGHTS[kind as usize] * hits.count_ones() as i32;
+                }
+            }
+        }
+
+        // A single piece can rarely do much on its own
+        if attackers < 2 {
+            return Score::default();
+        }
+
+        let penalty = SAFETY_TABLE[units.min(63) as usize];
+        Score::new(-penalty, -penalty / 4)
+    }
+
+    // The attack penalty is not linear in its weights, so it is not traced and the tuner leaves
+    // it as it is
+    fn side<T: Trace>(board: &Board, player: Player, trace: &mut T) -> Score {
+        let kings = Self::boards(board, player)[PieceKind::King as usize];
+        if kings == 0 {
+            return Score::default();
+        }
+        let king = kings.trailing_zeros() as i32;
+
+        Self::attacks(board, player, king) + Self::pawns_around(board, player, king, trace)
+    }
+
+    pub fn evaluate<T: Trace>(board: &Board, trace: &mut T) -> Score {
+        Self::side(board, Player::White, trace) - Self::side(board, Player::Black, trace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.apply_fen(fen.to_string());
+        board
+    }
+
+    #[test]
+    fn shield_and_open_files() {
+        let castled = board("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(KingSafety::side(&castled, Player::White, &mut ()), SHIELD_CLOSE * 3);
+        assert_eq!(KingSafety::evaluate(&castled, &mut ()), Score::default());
+
+        // Both g pawns gone leaves the file open in front of both kings
+        let open = board("6k1/5p1p/8/8/8/8/5P1P/6K1 w - - 0 1");
+        assert_eq!(KingSafety::side(&open, Player::White, &mut ()), SHIELD_CLOSE * 2 + SHIELD_MISSING + OPEN_FILE);
+    }
+
+    #[test]
+    fn attackers_on_the_king_zone() {
+        let g1 = Board::row_col_to_index(7, 6);
+
+        // The queen hits g2 and h2, but on its own it does not count
+        let queen = board("6k1/8/8/8/8/7q/5PPP/6K1 w - - 0 1");
+        assert_eq!(KingSafety::attacks(&queen, Player::White, g1), Score::default());
+
+        // The knight adds f1 and g2
+        let both = board("6k1/8/8/8/8/4n2q/5PPP/6K1 w - - 0 1");
+        let units = 2 * ATTACK_WEIGHTS[PieceKind::Queen as usize] + 2 * ATTACK_WEIGHTS[PieceKind::Knight as usize];
+        let penalty = SAFETY_TABLE[units as usize];
+        assert_eq!(KingSafety::attacks(&both, Player::White, g1), Score::new(-penalty, -penalty / 4));
+    }
+
+    #[test]
+    fn flipped_position_negates_the_score() {
+        let original = board("2kr3r/ppp2ppp/2n5/8/3q4/2N2N2/PP3PPP/R4RK1 w - - 0 1");
+        let flipped = board("r4rk1/pp3ppp/2n2n2/3Q4/8/2N5/PPP2PPP/2KR3R b - - 0 1");
+        assert_eq!(KingSafety::evaluate(&flipped, &mut ()), -KingSafety::evaluate(&original, &mut ()));
+    }
+}
@@ -2,11 +2,43 @@ mod piece;
 mod board;
 mod movegen;
 mod draw;
+mod zobrist;
+mod tt;
+mod eval;
+mod pawns;
+mod king_safety;
+mod activity;
+mod ordering;
+mod see;
+mod search;
+mod time;
+mod bench;
+mod book;
+mod pgn;
+mod uci;
+mod syzygy;
+mod retrograde;
+mod nnue;
+mod tune;
+mod random;
+mod game;
+mod datagen;
+mod engine;
+mod elo;
+mod matches;
+mod tournament;
+mod skill;
+mod bot;
 
 use piece::*;
 use board::*;
 use movegen::*;
 use draw::*;
+use search::{Score, SearchConfig, SearchInfo, SearchLimits};
+use syzygy::{Tablebases, Wdl};
+use retrograde::{Dtm, EndgameTables};
+use skill::Skill;
+use bot::Bot;
 
 use raylib::prelude::*;
 use std::env;
@@ -14,6 +46,13 @@ use std::env;
 const WIDTH: i32 = 800;
 const HEIGHT: i32 = 800;
 
+// How long the bot thinks about its moves in the window, and about the position when analysing
+const BOT_MOVE_TIME: u64 = 1000;
+const ANALYSIS_TIME: u64 = 3000;
+// Lines shown in the analysis panel, and the moves shown of each
+const ANALYSIS_LINES: usize = 3;
+const ANALYSIS_MOVES: usize = 8;
+
 fn main() {
     // Handle command arg to turn on drawing bits
     let mut show_bits = false;
@@ -22,6 +61,265 @@ fn main() {
         show_bits = true;
     }
 
+    // Play through a chess GUI instead of our own window
+    if args.contains(&String::from("--uci")) {
+        uci::run();
+        return;
+    }
+
+    // Polyglot book from PGN games:
+    // --make-book <out.bin> <games.pgn>... [--ply <n>] [--min-games <n>] [--only-wins] [--no-draws]
+    if let Some(i) = args.iter().position(|arg| arg == "--make-book") {
+        let files: Vec<String> = args[i + 1..].iter().take_while(|arg| !arg.starts_with("--")).cloned().collect();
+        let value = |flag: &str| args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1)).and_then(|arg| arg.parse().ok());
+
+        let defaults = book::BookOptions::default();
+        let options = book::BookOptions {
+            max_ply: value("--ply").unwrap_or(defaults.max_ply),
+            min_games: value("--min-games").map_or(defaults.min_games, |games| games as u32),
+            only_wins: args.contains(&String::from("--only-wins")),
+            skip_draws: args.contains(&String::from("--no-draws")),
+        };
+
+        match files.split_first() {
+            Some((output, inputs)) if !inputs.is_empty() => book::make(output, inputs, options),
+            _ => eprintln!("Usage: --make-book <out.bin> <games.pgn>..."),
+        }
+        return;
+    }
+
+    // Tunes the evaluation on positions labelled with game results, see tune.rs:
+    // --tune <positions.txt> [--out <tuned.rs>] [--epochs <n>] [--rate <r>] [--limit <n>]
+    if let Some(i) = args.iter().position(|arg| arg == "--tune") {
+        let Some(input) = args.get(i + 1) else {
+            eprintln!("Usage: --tune <positions.txt> [--out <tuned.rs>]");
+            return;
+        };
+        let value = |flag: &str| args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1));
+
+        let defaults = tune::TuneOptions::default();
+        let options = tune::TuneOptions {
+            epochs: value("--epochs").and_then(|arg| arg.parse().ok()).unwrap_or(defaults.epochs),
+            rate: value("--rate").and_then(|arg| arg.parse().ok()).unwrap_or(defaults.rate),
+            limit: value("--limit").and_then(|arg| arg.parse().ok()),
+        };
+        let output = value("--out").map_or("tuned.rs", |arg| arg.as_str());
+        if let Err(err) = tune::run(input, output, options) {
+            eprintln!("Could not tune on {}: {}", input, err);
+        }
+        return;
+    }
+
+    // Self-play games recorded as training data, see datagen.rs:
+    // --datagen <out.bin> [--games <n>] [--nodes <n>] [--random-plies <n>] [--seed <n>] [--threads <n>]
+    if let Some(i) = args.iter().position(|arg| arg == "--datagen") {
+        let Some(output) = args.get(i + 1) else {
+            eprintln!("Usage: --datagen <out.bin> [--games <n>] [--nodes <n>]");
+            return;
+        };
+        let value = |flag: &str| args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1));
+
+        let defaults = datagen::DatagenOptions::default();
+        let options = datagen::DatagenOptions {
+            games: value("--games").and_then(|arg| arg.parse().ok()).unwrap_or(defaults.games),
+            nodes: value("--nodes").and_then(|arg| arg.parse().ok()).unwrap_or(defaults.nodes),
+            random_plies: value("--random-plies").and_then(|arg| arg.parse().ok()).unwrap_or(defaults.random_plies),
+            seed: value("--seed").and_then(|arg| arg.parse().ok()).unwrap_or(defaults.seed),
+            threads: value("--threads").and_then(|arg| arg.parse().ok()).unwrap_or(defaults.threads),
+            ..defaults
+        };
+        if let Err(err) = datagen::run(output, options) {
+            eprintln!("Could not write {}: {}", output, err);
+        }
+        return;
+    }
+
+    // Training data as text, a FEN, score and result per line:
+    // --export-data <in.bin> <out.txt>
+    if let Some(i) = args.iter().position(|arg| arg == "--export-data") {
+        match (args.get(i + 1), args.get(i + 2)) {
+            (Some(input), Some(output)) => {
+                if let Err(err) = datagen::export(input, output) {
+                    eprintln!("Could not export {}: {}", input, err);
+                }
+            },
+            _ => eprintln!("Usage: --export-data <in.bin> <out.txt>"),
+        }
+        return;
+    }
+
+    // Two engines against each other, see matches.rs and engine.rs:
+    // --match <engine> <engine> [--games <n>] [--tc <[moves/]seconds[+increment]>] [--movetime <ms>]
+    //     [--depth <n>] [--nodes <n>] [--concurrency <n>] [--openings <file.epd|file.pgn>]
+    //     [--pgn <out.pgn>] [--sprt <elo0> <elo1>] [--alpha <a>] [--beta <b>]
+    //     [--draw <move number> <moves> <score>] [--resign <moves> <score>]
+    if let Some(i) = args.iter().position(|arg| arg == "--match") {
+        let specs: Vec<Result<engine::EngineSpec, String>> = args.iter().skip(i + 1).take(2).map(|arg| engine::EngineSpec::parse(arg)).collect();
+        let (first, second) = match specs.as_slice() {
+            [Ok(first), Ok(second)] => (first, second),
+            [Err(err), _] | [_, Err(err)] => {
+                eprintln!("Bad engine: {}", err);
+                return;
+            },
+            _ => {
+                eprintln!("Usage: --match <engine> <engine> [--games <n>] [--tc <seconds+increment>]");
+                return;
+            },
+        };
+        let value = |flag: &str| args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1));
+        let number = |flag: &str| value(flag).and_then(|arg| arg.parse::<f64>().ok());
+        let Some(time_control) = time_control(&args) else {
+            return;
+        };
+
+        let sprt = args.iter().position(|arg| arg == "--sprt").and_then(|i| {
+            Some(elo::Sprt {
+                elo0: args.get(i + 1)?.parse().ok()?,
+                elo1: args.get(i + 2)?.parse().ok()?,
+                alpha: number("--alpha").unwrap_or(0.05),
+                beta: number("--beta").unwrap_or(0.05),
+            })
+        });
+
+        // A test runs until it has an answer
+        let default_games = if sprt.is_some() { usize::MAX / 2 } else { 100 };
+        let options = matches::MatchOptions {
+            games: number("--games").map_or(default_games, |games| games as usize),
+            concurrency: number("--concurrency").map_or(1, |concurrency| concurrency as usize),
+            time_control,
+            adjudication: adjudication(&args),
+            openings: value("--openings").cloned(),
+            pgn: value("--pgn").cloned(),
+            sprt,
+        };
+        if let Err(err) = matches::run(first, second, options) {
+            eprintln!("Match failed: {}", err);
+        }
+        return;
+    }
+
+    // Any number of engines, see tournament.rs. Takes the same time control, concurrency,
+    // openings and adjudication options as --match:
+    // --tournament <engine> <engine>... [--format <round-robin|gauntlet|swiss>] [--rounds <n>]
+    //     [--pgn <out.pgn>] [--results <out.txt>]
+    if let Some(i) = args.iter().position(|arg| arg == "--tournament") {
+        let mut specs = Vec::new();
+        for arg in args[i + 1..].iter().take_while(|arg| !arg.starts_with("--")) {
+            match engine::EngineSpec::parse(arg) {
+                Ok(spec) => specs.push(spec),
+                Err(err) => {
+                    eprintln!("Bad engine: {}", err);
+                    return;
+                },
+            }
+        }
+        let value = |flag: &str| args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1));
+
+        let format = match value("--format") {
+            Some(text) => match tournament::Format::parse(text) {
+                Some(format) => format,
+                None => {
+                    eprintln!("Bad format {}, expected round-robin, gauntlet or swiss", text);
+                    return;
+                },
+            },
+            None => tournament::Format::RoundRobin,
+        };
+        let Some(time_control) = time_control(&args) else {
+            return;
+        };
+
+        let options = tournament::TournamentOptions {
+            format,
+            rounds: value("--rounds").and_then(|arg| arg.parse().ok()),
+            concurrency: value("--concurrency").and_then(|arg| arg.parse().ok()).unwrap_or(1),
+            time_control,
+            adjudication: adjudication(&args),
+            openings: value("--openings").cloned(),
+            pgn: value("--pgn").cloned(),
+            results: value("--results").cloned(),
+        };
+        if let Err(err) = tournament::run(&specs, options) {
+            eprintln!("Tournament failed: {}", err);
+        }
+        return;
+    }
+
+    // Fixed depth search over a few positions, prints node counts:
+    // --bench [depth] [--threads <n>] [--eval-file <network>] [--no-<feature>...]
+    if let Some(i) = args.iter().position(|arg| arg == "--bench") {
+        let depth = args.get(i + 1).and_then(|arg| arg.parse().ok()).unwrap_or(5);
+        let disabled = |flag: &str| args.contains(&String::from(flag));
+        let config = SearchConfig {
+            move_ordering: !disabled("--no-ordering"),
+            null_move: !disabled("--no-null-move"),
+            late_move_reductions: !disabled("--no-lmr"),
+            futility: !disabled("--no-futility"),
+            reverse_futility: !disabled("--no-reverse-futility"),
+            check_extensions: !disabled("--no-check-extensions"),
+            pvs: !disabled("--no-pvs"),
+            aspiration: !disabled("--no-aspiration"),
+            ..SearchConfig::default()
+        };
+        let threads = args.iter()
+            .position(|arg| arg == "--threads")
+            .and_then(|i| args.get(i + 1))
+            .and_then(|arg| arg.parse().ok())
+            .unwrap_or(1);
+        let network = match args.iter().position(|arg| arg == "--eval-file").and_then(|i| args.get(i + 1)) {
+            Some(path) => match nnue::Network::open(path) {
+                Ok(network) => Some(std::sync::Arc::new(network)),
+                Err(err) => {
+                    eprintln!("Could not load {}: {}", path, err);
+                    return;
+                }
+            },
+            None => None,
+        };
+        bench::run(depth, config, threads, network);
+        return;
+    }
+
+    // Our own endgame tables, into a directory that may already hold some of them:
+    // --generate-tables <dir> [KQvK...]
+    if let Some(i) = args.iter().position(|arg| arg == "--generate-tables") {
+        let Some(dir) = args.get(i + 1) else {
+            eprintln!("Usage: --generate-tables <dir> [KQvK...]");
+            return;
+        };
+        let mut names: Vec<&str> = args[i + 2..].iter().take_while(|arg| !arg.starts_with("--")).map(|arg| arg.as_str()).collect();
+        if names.is_empty() {
+            names = retrograde::DEFAULT_TABLES.to_vec();
+        }
+
+        let dir = std::path::Path::new(dir);
+        if let Err(err) = std::fs::create_dir_all(dir) {
+            eprintln!("Could not create {}: {}", dir.display(), err);
+            return;
+        }
+        let mut tables = EndgameTables::open(dir).unwrap_or_else(|_| EndgameTables::new());
+        for name in names {
+            if let Err(err) = tables.generate(name, dir) {
+                eprintln!("{}", err);
+                return;
+            }
+        }
+        return;
+    }
+
+    // Shows who wins endgames that are in the Syzygy tablebases: --syzygy <dir>
+    let tablebases = args.iter()
+        .position(|arg| arg == "--syzygy")
+        .and_then(|i| args.get(i + 1))
+        .map(|path| Tablebases::open(path));
+    // Shows how long the mate is in endgames that are in our own tables: --tables <dir>
+    let endgames = args.iter()
+        .position(|arg| arg == "--tables")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|dir| EndgameTables::open(dir).ok());
+    // Only probed again when the position changes
+    let mut verdict: (u64, Option<String>) = (0, None);
+
     let (mut rl, thread) = raylib::init()
         .size(WIDTH, HEIGHT)
         .title("Chess")
@@ -52,25 +350,214 @@ fn main() {
         rl.load_texture(&thread, "media/white_king.png").unwrap(),
     ];
 
+    // M opens the menu, which sets who plays and how well the bot does
+    let mut menu = Menu::new();
+    let mut bot = Bot::new();
+    // Hashes of every position in the game so far, for the bot to see repetitions
+    let mut history = vec![board.hash];
+    // H marks the pieces that can be won by taking them
+    let mut show_hanging = false;
+    // A opens the analysis panel, with the best few lines for the position on the board
+    let mut show_analysis = false;
+    let mut analyst = Bot::new();
+    analyst.set_multi_pv(ANALYSIS_LINES);
+    let mut analysis: (u64, Vec<String>) = (0, Vec::new());
+
     // Main game loop
     while !rl.window_should_close() {
         let mouse = rl.get_mouse_position();
-        update(&rl, &mut board, mouse);
-        draw(&mut rl, &thread, &mut board, mouse, &black_textures, &white_textures, show_bits);
+        if rl.is_key_pressed(raylib::consts::KeyboardKey::KEY_M) {
+            menu.open = !menu.open;
+        }
+        if rl.is_key_pressed(raylib::consts::KeyboardKey::KEY_H) {
+            show_hanging = !show_hanging;
+        }
+        if rl.is_key_pressed(raylib::consts::KeyboardKey::KEY_A) {
+            show_analysis = !show_analysis;
+            analyst.cancel();
+            analysis = (0, Vec::new());
+        }
+        if menu.open {
+            update_menu(&rl, &mut menu, &mut bot, mouse);
+        } else {
+            update(&rl, &mut board, &mut history, &mut bot, menu.mode, mouse);
+        }
+        if show_analysis && analysis.0 != board.hash && board.promoting.is_none() {
+            if MoveGen::legal(&mut board.clone()).is_empty() {
+                analysis = (board.hash, vec![String::from("No legal moves")]);
+            } else {
+                let limits = SearchLimits { move_time: Some(ANALYSIS_TIME), ..Default::default() };
+                let done = analyst.poll(&board, &history, limits).is_some();
+                // The lines are shown as they come in, the hash is only set once they are done
+                analysis = (if done { board.hash } else { 0 }, describe_lines(&board, analyst.progress()));
+            }
+        }
+        let analysing = [String::from("Analysing...")];
+        let panel = match show_analysis {
+            true if analysis.1.is_empty() => Some(analysing.as_slice()),
+            true => Some(analysis.1.as_slice()),
+            false => None,
+        };
+        if verdict.0 != board.hash {
+            // Our own tables know the distance to mate, so they go first
+            let dtm = endgames.as_ref().and_then(|endgames| endgames.probe(&board)).map(|dtm| describe_dtm(dtm, board.get_turn()));
+            let wdl = || tablebases.as_ref().and_then(|tablebases| {
+                tablebases.probe_wdl(&board).map(|wdl| describe(wdl, tablebases.probe_dtz(&board), board.get_turn()))
+            });
+            verdict = (board.hash, dtm.or_else(wdl));
+        }
+        draw(&mut rl, &thread, &mut board, mouse, &black_textures, &white_textures, show_bits, show_hanging, verdict.1.as_deref(), panel, &menu);
     }
 }
 
-fn update(rl: &RaylibHandle, board: &mut Board, mouse: Vector2) {
+// A tablebase result, which is for the player to move, from the board's point of view. The
+// DTZ is missing when its table is
+fn describe(wdl: Wdl, dtz: Option<i32>, turn: Player) -> String {
+    let (winner, fifty_moves) = match wdl {
+        Wdl::Win => (turn, false),
+        Wdl::CursedWin => (turn, true),
+        Wdl::Loss => (Board::opponent(turn), false),
+        Wdl::BlessedLoss => (Board::opponent(turn), true),
+        Wdl::Draw => return String::from("Tablebase: drawn"),
+    };
+
+    let verdict = if fifty_moves {
+        format!("Tablebase: {} is winning, but the fifty move rule draws", winner)
+    } else {
+        format!("Tablebase: {} is winning", winner)
+    };
+
+    match dtz {
+        Some(dtz) => format!("{} (DTZ {})", verdict, dtz.abs()),
+        None => verdict,
+    }
+}
+
+// An endgame table result, like describe
+fn describe_dtm(dtm: Dtm, turn: Player) -> String {
+    let (winner, plies) = match dtm {
+        Dtm::Win(plies) => (turn, plies),
+        Dtm::Loss(0) => return format!("Endgame table: {} is mated", turn),
+        Dtm::Loss(plies) => (Board::opponent(turn), plies),
+        Dtm::Draw => return String::from("Endgame table: drawn"),
+    };
+
+    format!("Endgame table: {} mates in {}", winner, (plies + 1) / 2)
+}
+
+// --tc <[moves/]seconds[+increment]>, --movetime <ms>, --depth <n> and --nodes <n>, or 10+0.1
+// without any. None after telling the user what was wrong
+fn time_control(args: &[String]) -> Option<matches::TimeControl> {
+    let value = |flag: &str| args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1));
+
+    let mut time_control = match value("--tc") {
+        Some(text) => match matches::TimeControl::parse(text) {
+            Some(time_control) => time_control,
+            None => {
+                eprintln!("Bad time control {}, expected [moves/]seconds[+increment]", text);
+                return None;
+            },
+        },
+        None => matches::TimeControl::default(),
+    };
+    time_control.move_time = value("--movetime").and_then(|arg| arg.parse().ok());
+    time_control.depth = value("--depth").and_then(|arg| arg.parse().ok());
+    time_control.nodes = value("--nodes").and_then(|arg| arg.parse().ok());
+
+    let limited = time_control.base.is_some() || time_control.move_time.is_some() || time_control.depth.is_some() || time_control.nodes.is_some();
+    if !limited {
+        time_control = matches::TimeControl::parse("10+0.1").unwrap();
+    }
+    Some(time_control)
+}
+
+// --draw <move number> <moves> <score> and --resign <moves> <score>, scores in centipawns
+fn adjudication(args: &[String]) -> matches::Adjudication {
+    let values = |flag: &str, count: usize| -> Option<Vec<i32>> {
+        let i = args.iter().position(|arg| arg == flag)?;
+        args.get(i + 1..i + 1 + count)?.iter().map(|arg| arg.parse().ok()).collect()
+    };
+
+    matches::Adjudication {
+        draw: values("--draw", 3).map(|values| matches::DrawAdjudication {
+            move_number: values[0],
+            moves: values[1].max(1) as u32,
+            score: values[2],
+        }),
+        resign: values("--resign", 2).map(|values| matches::ResignAdjudication {
+            moves: values[0].max(1) as u32,
+            score: values[1],
+        }),
+    }
+}
+
+// The analysis panel's lines, best first, with scores from white's point of view
+fn describe_lines(board: &Board, lines: &[SearchInfo]) -> Vec<String> {
+    lines.iter().enumerate().map(|(i, line)| {
+        let mut board = board.clone();
+        let score = describe_score(line.score, board.get_turn());
+        let moves: Vec<String> = line.pv.iter().take(ANALYSIS_MOVES).map(|&m| {
+            let san = MoveGen::to_san(&mut board, m);
+            board.apply_move(m);
+            san
+        }).collect();
+        format!("{}. {} (depth {})  {}", i + 1, score, line.depth, moves.join(" "))
+    }).collect()
+}
+
+// Pawns, or # and the moves until mate, negative when black is winning
+fn describe_score(score: Score, turn: Player) -> String {
+    let sign = if turn == Player::White { 1 } else { -1 };
+    match score {
+        Score::Centipawns(cp) => format!("{:+.2}", (cp * sign) as f64 / 100.0),
+        Score::Mate(moves) => format!("#{}", moves * sign),
+    }
+}
+
+// A new mode or skill level stops the bot from playing the move it was thinking about
+fn update_menu(rl: &RaylibHandle, menu: &mut Menu, bot: &mut Bot, mouse: Vector2) {
+    if !rl.is_mouse_button_pressed(raylib::consts::MouseButton::MOUSE_BUTTON_LEFT) {
+        return;
+    }
+
+    match Menu::clicked(mouse) {
+        Some(MenuItem::Mode(mode)) => {
+            menu.mode = mode;
+            menu.open = false;
+            bot.cancel();
+        },
+        Some(MenuItem::SkillDown) => {
+            menu.skill_level = menu.skill_level.saturating_sub(1);
+            bot.set_skill(Skill::new(menu.skill_level));
+        },
+        Some(MenuItem::SkillUp) => {
+            menu.skill_level = (menu.skill_level + 1).min(skill::MAX_LEVEL);
+            bot.set_skill(Skill::new(menu.skill_level));
+        },
+        None => (),
+    }
+}
+
+fn update(rl: &RaylibHandle, board: &mut Board, history: &mut Vec<u64>, bot: &mut Bot, mode: Mode, mouse: Vector2) {
     if rl.is_mouse_button_pressed(raylib::consts::MouseButton::MOUSE_BUTTON_MIDDLE) {
         board.reset();
+        *history = vec![board.hash];
     }
 
-    //  TODO: make a separate update function for different modes (pvp, pvb, bot-only)
-    // Auto-play
-    // if rl.is_mouse_button_pressed(raylib::consts::MouseButton::MOUSE_BUTTON_LEFT) {
-    //     let moves = MoveGen::all(board);
-    //     board.apply_move(moves[0]);
-    // }
+    // Clicks are ignored while the bot is to move, unless the player is still picking a promotion
+    let bot_to_move = match mode {
+        Mode::PlayerVsPlayer => false,
+        Mode::PlayerVsBot(player) => board.get_turn() != player,
+        Mode::BotVsBot => true,
+    };
+    if bot_to_move && board.promoting.is_none() {
+        let limits = SearchLimits { move_time: Some(BOT_MOVE_TIME), ..Default::default() };
+        if let Some(m) = bot.poll(board, history, limits).and_then(|result| result.best_move) {
+            board.apply_move(m);
+            history.push(board.hash);
+        }
+        return;
+    }
 
     if rl.is_mouse_button_pressed(raylib::consts::MouseButton::MOUSE_BUTTON_LEFT) {
         let (row, col) = ((mouse.y as f32 / 100.0).floor() as i32, (mouse.x as f32 / 100.0).floor() as i32);
@@ -104,13 +591,19 @@ fn update(rl: &RaylibHandle, board: &mut Board, mouse: Vector2) {
             } else if board.is_selected() {
                 for m in MoveGen::piece_at(board, Board::index_to_row_col(board.get_selected().index)) {
                     if m.to == Board::row_col_to_index(row, col) {
-                        board.apply_move(m);
+                        // Promotions come in one move per piece, the promotion UI asks which one we want
+                        board.apply_move(ChessMove { promotion: None, ..m });
+                        break;
                     }
                 }
                 board.deselect();
             }
         }
 
+        if board.promoting.is_none() && history.last() != Some(&board.hash) {
+            history.push(board.hash);
+        }
+
         if let Some(winner) = board.winner() {
             println!("Game over, {} wins!", winner);
         }
@@ -0,0 +1,453 @@
+use crate::piece::Player;
+use crate::board::Board;
+use crate::movegen::MoveGen;
+use crate::game::Game;
+use crate::engine::{Engine, EngineSpec, GoLimits};
+use crate::elo::{MatchScore, Sprt};
+use crate::pgn::{self, GameResult};
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/*
+ * ----- MATCHES -----
+ * Plays two engines against each other to see whether a change made the engine stronger. See
+ * engine.rs for how engines are described, and elo.rs for what is made of the results.
+ *
+ * Every opening is played twice, once with each engine as white, so that an opening that
+ * favours one side does not favour one engine. Openings come from the positions of an EPD file
+ * or the games of a PGN file, played in order and started again from the top when they run
+ * out. Several games can be played at the same time, each with its own copy of both engines.
+ *
+ * Games end by the rules, or when an engine runs out of time, plays an illegal move or stops
+ * answering. An engine that stopped answering is started again for the next game. Games can
+ * also be adjudicated on the engines' own scores, drawn once both have seen a draw for long
+ * enough, or lost by an engine that has seen itself lost for long enough. Every game is
+ * appended to the PGN file as soon as it is over, so an interrupted match keeps what it played.
+ */
+
+// How far past its clock an engine may go before it loses on time, in milliseconds
+const TIME_MARGIN: u64 = 100;
+// Elo estimates are printed after this many games
+const REPORT_EVERY: u32 = 10;
+
+// Limits the engines play with, times in milliseconds
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TimeControl {
+    // The clock gets `base` again after this many moves, sudden death without
+    pub moves: Option<u64>,
+    pub base: Option<u64>,
+    pub increment: u64,
+    pub move_time: Option<u64>,
+    pub depth: Option<i32>,
+    pub nodes: Option<u64>,
+}
+
+// Ends games whose result is no longer in doubt, going by the scores the engines report
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Adjudication {
+    pub draw: Option<DrawAdjudication>,
+    pub resign: Option<ResignAdjudication>,
+}
+
+// From move `move_number` on, drawn once both engines have scored within `score` of 0 for
+// `moves` moves in a row each
+#[derive(Copy, Clone, Debug)]
+pub struct DrawAdjudication {
+    pub move_number: i32,
+    pub moves: u32,
+    pub score: i32,
+}
+
+// Lost by an engine that scored itself at least `score` behind for `moves` moves in a row
+#[derive(Copy, Clone, Debug)]
+pub struct ResignAdjudication {
+    pub moves: u32,
+    pub score: i32,
+}
+
+pub struct MatchOptions {
+    // Rounded up to a whole number of pairs
+    pub games: usize,
+    pub concurrency: usize,
+    pub time_control: TimeControl,
+    pub adjudication: Adjudication,
+    // EPD or PGN, the usual starting position without
+    pub openings: Option<String>,
+    pub pgn: Option<String>,
+    pub sprt: Option<Sprt>,
+}
+
+// A finished game and how it ended
+pub struct Played {
+    pub game: Game,
+    pub result: GameResult,
+    pub reason: String,
+    // For PGN's Termination tag
+    pub termination: &'static str,
+    // The side whose engine stopped answering, it needs starting again
+    pub failed: Option<Player>,
+}
+
+impl TimeControl {
+    // [moves/]seconds[+increment], e.g. 40/60, 10+0.1 or 300+2
+    pub fn parse(text: &str) -> Option<Self> {
+        let (moves, rest) = match text.split_once('/') {
+            Some((moves, rest)) => (Some(moves.parse().ok()?), rest),
+            None => (None, text),
+        };
+        let (base, increment) = match rest.split_once('+') {
+            Some((base, increment)) => (base, increment.parse::<f64>().ok()?),
+            None => (rest, 0.0),
+        };
+        let base = base.parse::<f64>().ok()?;
+
+        Some(Self {
+            moves,
+            base: Some((base * 1000.0) as u64),
+            increment: (increment * 1000.0) as u64,
+            ..Default::default()
+        })
+    }
+
+    // As PGN's TimeControl tag writes it, in seconds
+    pub fn pgn_tag(&self) -> String {
+        let Some(base) = self.base else {
+            return "-".to_string();
+        };
+
+        let mut tag = format!("{}", base as f64 / 1000.0);
+        if let Some(moves) = self.moves {
+            tag = format!("{}/{}", moves, tag);
+        }
+        if self.increment > 0 {
+            tag = format!("{}+{}", tag, self.increment as f64 / 1000.0);
+        }
+        tag
+    }
+}
+
+// Starting positions, the games of a PGN file played out or the positions of an EPD file
+pub fn read_openings(path: &str) -> io::Result<Vec<Game>> {
+    let mut openings = Vec::new();
+
+    if path.to_ascii_lowercase().ends_with(".pgn") {
+        for pgn_game in pgn::read(path)? {
+            let mut board = Board::new();
+            match pgn_game.tag("FEN") {
+                Some(fen) => board.apply_fen(fen.to_string()),
+                None => board.reset(),
+            }
+
+            let mut game = Game::new(board);
+            for san in &pgn_game.moves {
+                let Some(m) = MoveGen::from_san(&mut game.board, san) else {
+                    break;
+                };
+                game.play(m);
+            }
+            openings.push(game);
+        }
+    } else {
+        // EPD leaves out the move counters, anything after the first four fields is opcodes
+        for line in fs::read_to_string(path)?.lines() {
+            let fields: Vec<&str> = line.split_whitespace().take(4).collect();
+            if fields.len() == 4 {
+                let mut board = Board::new();
+                board.apply_fen(format!("{} 0 1", fields.join(" ")));
+                openings.push(Game::new(board));
+            }
+        }
+    }
+
+    if openings.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no openings found"));
+    }
+    Ok(openings)
+}
+
+// Today as PGN writes dates
+pub fn pgn_date() -> String {
+    // Days since 1970 to a date, from Howard Hinnant's civil_from_days
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs() / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!("{}.{:02}.{:02}", year, month, day)
+}
+
+// Plays a game on from the opening, until the rules or adjudication end it or an engine fails
+pub fn play<'a>(
+    white: &mut (dyn Engine + 'a),
+    black: &mut (dyn Engine + 'a),
+    opening: &Game,
+    time_control: &TimeControl,
+    adjudication: &Adjudication,
+) -> Played {
+    let mut game = opening.clone();
+    let ended = |game: Game, result, reason: String, termination, failed| Played { game, result, reason, termination, failed };
+
+    for (engine, player) in [(&mut *white, Player::White), (&mut *black, Player::Black)] {
+        if let Err(err) = engine.new_game() {
+            let reason = format!("{} stopped answering: {}", colour(player), err);
+            return ended(game, loss_for(player), reason, "abandoned", Some(player));
+        }
+    }
+
+    let mut clocks = [time_control.base; 2];
+    let mut moves_made = [0; 2];
+    // Moves in a row that the adjudication rules would count
+    let mut drawn_plies = 0;
+    let mut lost_moves = [0; 2];
+    let mut adjudicated = None;
+
+    loop {
+        if let Some((result, reason)) = game.outcome() {
+            return ended(game, result, reason.to_string(), "normal", None);
+        }
+        if let Some((result, reason)) = adjudicated {
+            return ended(game, result, reason, "adjudication", None);
+        }
+
+        let player = game.board.get_turn();
+        let side = player as usize;
+        let limits = GoLimits {
+            white_time: clocks[Player::White as usize],
+            black_time: clocks[Player::Black as usize],
+            increment: time_control.increment,
+            moves_to_go: time_control.moves.map(|moves| moves - moves_made[side] % moves),
+            depth: time_control.depth,
+            nodes: time_control.nodes,
+            move_time: time_control.move_time,
+        };
+
+        let engine = match player {
+            Player::White => &mut *white,
+            Player::Black => &mut *black,
+        };
+        let start = Instant::now();
+        let answer = engine.go(&game, &limits);
+        let elapsed = start.elapsed().as_millis() as u64;
+
+        let answer = match answer {
+            Ok(answer) => answer,
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                let reason = format!("{} played an {}", colour(player), err);
+                return ended(game, loss_for(player), reason, "rules infraction", None);
+            },
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                let reason = format!("{} loses on time", colour(player));
+                return ended(game, loss_for(player), reason, "time forfeit", Some(player));
+            },
+            Err(err) => {
+                let reason = format!("{} stopped answering: {}", colour(player), err);
+                return ended(game, loss_for(player), reason, "abandoned", Some(player));
+            },
+        };
+
+        let allowed = clocks[side].or(time_control.move_time);
+        if allowed.is_some_and(|allowed| elapsed > allowed + TIME_MARGIN) {
+            let reason = format!("{} loses on time", colour(player));
+            return ended(game, loss_for(player), reason, "time forfeit", None);
+        }
+
+        if let Some(draw) = &adjudication.draw {
+            let drawn = answer.score.is_some_and(|score| score.abs() <= draw.score);
+            drawn_plies = if drawn && game.board.fullmove_number >= draw.move_number { drawn_plies + 1 } else { 0 };
+            if drawn_plies >= 2 * draw.moves {
+                adjudicated = Some((GameResult::Draw, "adjudicated draw".to_string()));
+            }
+        }
+        if let Some(resign) = &adjudication.resign {
+            let lost = answer.score.is_some_and(|score| score <= -resign.score);
+            lost_moves[side] = if lost { lost_moves[side] + 1 } else { 0 };
+            if lost_moves[side] >= resign.moves {
+                adjudicated = Some((loss_for(player), format!("{} resigns", colour(player))));
+            }
+        }
+
+        moves_made[side] += 1;
+        if let Some(time) = &mut clocks[side] {
+            *time = *time - elapsed.min(*time) + time_control.increment;
+            if time_control.moves.is_some_and(|moves| moves_made[side] % moves == 0) {
+                *time += time_control.base.unwrap_or(0);
+            }
+        }
+
+        game.play(answer.m);
+    }
+}
+
+fn colour(player: Player) -> &'static str {
+    match player {
+        Player::White => "white",
+        Player::Black => "black",
+    }
+}
+
+fn loss_for(player: Player) -> GameResult {
+    match player {
+        Player::White => GameResult::BlackWins,
+        Player::Black => GameResult::WhiteWins,
+    }
+}
+
+pub fn run(first: &EngineSpec, second: &EngineSpec, options: MatchOptions) -> io::Result<()> {
+    let openings = match &options.openings {
+        Some(path) => read_openings(path)?,
+        None => {
+            let mut board = Board::new();
+            board.reset();
+            vec![Game::new(board)]
+        },
+    };
+    let mut pgn_file = match &options.pgn {
+        Some(path) => Some(BufWriter::new(File::options().create(true).append(true).open(path)?)),
+        None => None,
+    };
+
+    let games = options.games.div_ceil(2) * 2;
+    let mut names = [first.name.clone(), second.name.clone()];
+    if names[0] == names[1] {
+        names[1].push_str(" 2");
+    }
+    let date = pgn_date();
+    let next_game = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel::<(usize, Played)>();
+
+    println!("{} vs {}, {} openings", names[0], names[1], openings.len());
+
+    let score = thread::scope(|scope| -> io::Result<MatchScore> {
+        for _ in 0..options.concurrency.max(1) {
+            let sender = sender.clone();
+            let (openings, next_game, stop, options) = (&openings, &next_game, &stop, &options);
+            scope.spawn(move || {
+                let started = first.start().and_then(|a| Ok([a, second.start()?]));
+                let mut engines = match started {
+                    Ok(engines) => engines,
+                    Err(err) => {
+                        eprintln!("Could not start the engines: {}", err);
+                        stop.store(true, Ordering::Relaxed);
+                        return;
+                    },
+                };
+
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next_game.fetch_add(1, Ordering::Relaxed);
+                    if index >= games {
+                        break;
+                    }
+
+                    // The first engine is white in the first game of every pair
+                    let opening = &openings[index / 2 % openings.len()];
+                    let first_white = index % 2 == 0;
+                    let [a, b] = &mut engines;
+                    let (white, black) = if first_white { (a, b) } else { (b, a) };
+                    let played = play(white.as_mut(), black.as_mut(), opening, &options.time_control, &options.adjudication);
+
+                    if let Some(player) = played.failed {
+                        let failed = ((player == Player::White) != first_white) as usize;
+                        match [first, second][failed].start() {
+                            Ok(engine) => engines[failed] = engine,
+                            Err(err) => {
+                                eprintln!("Could not start {} again: {}", [first, second][failed].name, err);
+                                stop.store(true, Ordering::Relaxed);
+                            },
+                        }
+                    }
+
+                    if sender.send((index, played)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut score = MatchScore::default();
+        // Points the first engine got from the pairs that are half done
+        let mut half_pairs: HashMap<usize, u32> = HashMap::new();
+
+        for (index, played) in receiver {
+            let first_white = index % 2 == 0;
+            let (white, black) = if first_white { (&names[0], &names[1]) } else { (&names[1], &names[0]) };
+            let points = match (played.result, first_white) {
+                (GameResult::WhiteWins, true) | (GameResult::BlackWins, false) => 2,
+                (GameResult::Draw, _) => 1,
+                _ => 0,
+            };
+            score.add_game(points);
+            if let Some(other) = half_pairs.remove(&(index / 2)) {
+                score.add_pair(points + other);
+            } else {
+                half_pairs.insert(index / 2, points);
+            }
+
+            println!("Game {} ({} vs {}): {} {{{}}}", index + 1, white, black, played.result, played.reason);
+            println!(
+                "Score of {} vs {}: {} - {} - {} [{:.3}] {}",
+                names[0], names[1], score.wins, score.losses, score.draws, score.score(), score.games()
+            );
+
+            if let Some(file) = &mut pgn_file {
+                let tags = vec![
+                    ("Event".to_string(), format!("{} vs {}", names[0], names[1])),
+                    ("Site".to_string(), "?".to_string()),
+                    ("Date".to_string(), date.clone()),
+                    ("Round".to_string(), (index + 1).to_string()),
+                    ("White".to_string(), white.clone()),
+                    ("Black".to_string(), black.clone()),
+                    ("Result".to_string(), played.result.to_string()),
+                    ("TimeControl".to_string(), options.time_control.pgn_tag()),
+                    ("Termination".to_string(), played.termination.to_string()),
+                ];
+                writeln!(file, "{}", played.game.to_pgn(tags, played.result))?;
+                file.flush()?;
+            }
+
+            if score.games() % REPORT_EVERY == 0 {
+                report(&score, options.sprt.as_ref());
+            }
+            if let Some(sprt) = &options.sprt
+                && sprt.verdict(score.llr(sprt)).is_some()
+            {
+                stop.store(true, Ordering::Relaxed);
+            }
+        }
+
+        Ok(score)
+    })?;
+
+    println!();
+    report(&score, options.sprt.as_ref());
+    Ok(())
+}
+
+fn report(score: &MatchScore, sprt: Option<&Sprt>) {
+    let (elo, margin) = score.elo();
+    println!("Elo difference: {:.1} +/- {:.1}, LOS: {:.1}%", elo, margin, score.likelihood_of_superiority() * 100.0);
+    println!("Pairs by points (0, 0.5, 1, 1.5, 2): {:?}", score.pairs);
+
+    if let Some(sprt) = sprt {
+        let llr = score.llr(sprt);
+        let (lower, upper) = sprt.bounds();
+        let verdict = match sprt.verdict(llr) {
+            Some(true) => ", H1 accepted",
+            Some(false) => ", H0 accepted",
+            None => "",
+        };
+        println!("SPRT [{}, {}]: LLR {:.2} ({:.2}, {:.2}){}", sprt.elo0, sprt.elo1, llr, lower, upper, verdict);
+    }
+}
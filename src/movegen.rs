@@ -17,18 +17,52 @@ const KING_MOVES: [(i32, i32); 8] = [
     (1, -1), (1, 0), (1, 1)
 ];
 
-#[derive(Debug, Copy, Clone)]
+const BISHOP_DIRS: [(i32, i32); 4] = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+const ROOK_DIRS: [(i32, i32); 4] = [(0, -1), (0, 1), (-1, 0), (1, 0)];
+
+// Column masks, used to stop shifted bitboards from wrapping around the edges
+pub const FILE_A: u64 = 0x0101010101010101;
+pub const FILE_H: u64 = 0x8080808080808080;
+
+// Attack bitboards for the pieces that do not slide, indexed by square
+const KNIGHT_ATTACKS: [u64; 64] = jump_table(&KNIGHT_MOVES);
+const KING_ATTACKS: [u64; 64] = jump_table(&KING_MOVES);
+
+const fn jump_table(jumps: &[(i32, i32); 8]) -> [u64; 64] {
+    let mut table = [0; 64];
+    let mut index = 0;
+    while index < 64 {
+        let (row, col) = (index as i32 / 8, index as i32 % 8);
+        let mut i = 0;
+        while i < jumps.len() {
+            let (to_row, to_col) = (row + jumps[i].0, col + jumps[i].1);
+            if to_row >= 0 && to_row <= 7 && to_col >= 0 && to_col <= 7 {
+                table[index] |= 1_u64 << (to_row * 8 + to_col);
+            }
+            i += 1;
+        }
+        index += 1;
+    }
+    table
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct ChessMove {
     pub from: i32, // The position in the bitboard
     pub to: i32,
     pub kind: PieceKind,
-    pub player: Player
+    pub player: Player,
+    pub promotion: Option<PieceKind> // None on a promotion lets the GUI ask for the piece
 }
 
 // Is it necessary to include the piece kind here?
 impl ChessMove {
     pub fn new(from: i32, to: i32, kind: PieceKind, player: Player) -> Self {
-        return Self { from, to, kind, player }
+        return Self { from, to, kind, player, promotion: None }
+    }
+
+    pub fn with_promotion(&self, kind: PieceKind) -> Self {
+        Self { promotion: Some(kind), ..*self }
     }
 
     pub fn is_castling(&self) -> bool {
@@ -38,6 +72,29 @@ impl ChessMove {
             (to.1 - from.1).abs() == 2
         }
     }
+
+    // Compact form used by the transposition table
+    pub fn pack(&self) -> u16 {
+        let promotion = match self.promotion {
+            Some(kind) => kind as i32,
+            None => 0
+        };
+
+        (self.from | self.to << 6 | promotion << 12) as u16
+    }
+
+    // Long algebraic notation as used by UCI, e.g. e2e4 or e7e8q
+    pub fn to_uci(self) -> String {
+        let promotion = match self.promotion {
+            Some(PieceKind::Knight) => "n",
+            Some(PieceKind::Bishop) => "b",
+            Some(PieceKind::Rook) => "r",
+            Some(_) => "q",
+            None => ""
+        };
+
+        format!("{}{}{}", Board::square_name(self.from), Board::square_name(self.to), promotion)
+    }
 }
 
 pub struct MoveGen {
@@ -46,9 +103,9 @@ pub struct MoveGen {
 // NOTE: Does the board have to be mutably borrowed in all these?
 impl MoveGen {
     pub fn all(board: &mut Board, player: Player) -> Vec<ChessMove> {
-        let (queen, king): (u64, u64) = match player {
-            Player::White => (board.white[PieceKind::Queen as usize], board.white[PieceKind::King as usize]),
-            Player::Black => (board.black[PieceKind::Queen as usize], board.black[PieceKind::King as usize]),
+        let king: u64 = match player {
+            Player::White => board.white[PieceKind::King as usize],
+            Player::Black => board.black[PieceKind::King as usize],
         };
 
         let mut moves: Vec<ChessMove> = Vec::new();
@@ -56,29 +113,194 @@ impl MoveGen {
         moves.extend(Self::knights(board, player));
         moves.extend(Self::bishops(board, player));
         moves.extend(Self::rooks(board, player));
-        moves.extend(Self::queen(board, player, Board::u64_to_row_col(queen)));
-        moves.extend(Self::king(board, player, Board::u64_to_row_col(king)));
+        moves.extend(Self::queens(board, player));
+        if king != 0 {
+            moves.extend(Self::king(board, player, Board::u64_to_row_col(king)));
+        }
 
         moves
     }
 
-    pub fn all_no_castling(board: &mut Board, player: Player) -> Vec<ChessMove> {
-        let (queen, king): (u64, u64) = match player {
-            Player::White => (board.white[PieceKind::Queen as usize], board.white[PieceKind::King as usize]),
-            Player::Black => (board.black[PieceKind::Queen as usize], board.black[PieceKind::King as usize]),
+    // Moves that do not leave the player's own king in check
+    pub fn legal(board: &mut Board) -> Vec<ChessMove> {
+        let player = board.get_turn();
+
+        Self::all(board, player)
+            .into_iter()
+            .filter(|&m| {
+                let mut next = board.clone();
+                next.apply_move(m);
+                !next.is_king_in_check(player)
+            })
+            .collect()
+    }
+
+    // The moves without a capture or promotion that the player who just moved could have
+    // played to get here. Whether the position before was legal is left to the caller
+    pub fn unmoves(board: &Board) -> Vec<ChessMove> {
+        let player = Board::opponent(board.get_turn());
+        let pieces = match player {
+            Player::White => &board.white,
+            Player::Black => &board.black,
         };
+        let empty = board.get_empty();
+
+        let mut moves = Vec::new();
+        for &kind in PieceKind::iterator() {
+            let mut bits = pieces[kind as usize];
+            while bits != 0 {
+                let to = bits.trailing_zeros() as i32;
+                bits &= bits - 1;
+
+                let mut from = if kind == PieceKind::Pawn {
+                    // One square back, or two from the fourth rank, never from the first
+                    let row = to / 8;
+                    let (back, can_step, double_row) = match player {
+                        Player::White => (8, row < 6, 4),
+                        Player::Black => (-8, row > 1, 3),
+                    };
+                    let mut from = 0;
+                    if can_step && Board::index_to_u64(to + back) & empty != 0 {
+                        from |= Board::index_to_u64(to + back);
+                        if row == double_row && Board::index_to_u64(to + 2 * back) & empty != 0 {
+                            from |= Board::index_to_u64(to + 2 * back);
+                        }
+                    }
+                    from
+                } else {
+                    Self::piece_attack_mask(kind, player, to, !empty) & empty
+                };
 
-        let mut moves: Vec<ChessMove> = Vec::new();
-        moves.extend(Self::pawns(board, player));
-        moves.extend(Self::knights(board, player));
-        moves.extend(Self::bishops(board, player));
-        moves.extend(Self::rooks(board, player));
-        moves.extend(Self::queen(board, player, Board::u64_to_row_col(queen)));
-        moves.extend(Self::king_no_castling(board, player, Board::u64_to_row_col(king)));
+                while from != 0 {
+                    moves.push(ChessMove::new(from.trailing_zeros() as i32, to, kind, player));
+                    from &= from - 1;
+                }
+            }
+        }
 
         moves
     }
 
+    // The legal move written as `text` in long algebraic notation, if there is one
+    pub fn from_uci(board: &mut Board, text: &str) -> Option<ChessMove> {
+        Self::legal(board).into_iter().find(|m| m.to_uci() == text)
+    }
+
+    // The legal move written as `text` in standard algebraic notation, as used in PGN files,
+    // e.g. Nf3, exd5, O-O or e8=Q+
+    pub fn from_san(board: &mut Board, text: &str) -> Option<ChessMove> {
+        let san = text.trim_end_matches(['+', '#', '!', '?']);
+        let legal = Self::legal(board);
+
+        match san {
+            "O-O" | "0-0" => return legal.into_iter().find(|m| m.is_castling() && m.to > m.from),
+            "O-O-O" | "0-0-0" => return legal.into_iter().find(|m| m.is_castling() && m.to < m.from),
+            _ => ()
+        }
+
+        let (san, promotion) = match san.split_once('=') {
+            Some((rest, piece)) => (rest, Some(Self::san_piece(piece.chars().next()?)?)),
+            None => (san, None)
+        };
+
+        let mut chars: Vec<char> = san.chars().filter(|&c| c != 'x' && c != '-').collect();
+        let kind = match chars.first().and_then(|&c| Self::san_piece(c)) {
+            Some(kind) => {
+                chars.remove(0);
+                kind
+            },
+            None => PieceKind::Pawn
+        };
+
+        if chars.len() < 2 {
+            return None;
+        }
+        let (hints, square) = chars.split_at(chars.len() - 2);
+        let to = Board::parse_square(&square.iter().collect::<String>())?;
+
+        // Whatever is left tells apart pieces of the same kind that can reach the square
+        legal.into_iter().find(|m| {
+            let from: Vec<char> = Board::square_name(m.from).chars().collect();
+            m.kind == kind && m.to == to && m.promotion == promotion && hints.iter().all(|hint| from.contains(hint))
+        })
+    }
+
+    // `m` written in standard algebraic notation, with just enough of the from square to tell
+    // it apart from the other moves to the same square. The move must be legal
+    pub fn to_san(board: &mut Board, m: ChessMove) -> String {
+        let mut san = if m.is_castling() {
+            if m.to > m.from { "O-O" } else { "O-O-O" }.to_string()
+        } else {
+            let from = Board::square_name(m.from);
+            let capture = board.is_occupied(Board::index_to_row_col(m.to)) || (m.kind == PieceKind::Pawn && m.from % 8 != m.to % 8);
+            let mut san = String::new();
+
+            if m.kind == PieceKind::Pawn {
+                if capture {
+                    san.push_str(&from[..1]);
+                }
+            } else {
+                san.push(Self::san_letter(m.kind));
+
+                let others: Vec<ChessMove> = Self::legal(board)
+                    .into_iter()
+                    .filter(|other| other.kind == m.kind && other.to == m.to && other.from != m.from)
+                    .collect();
+                if !others.is_empty() {
+                    let same_file = others.iter().any(|other| other.from % 8 == m.from % 8);
+                    let same_rank = others.iter().any(|other| other.from / 8 == m.from / 8);
+                    if !same_file {
+                        san.push_str(&from[..1]);
+                    } else if !same_rank {
+                        san.push_str(&from[1..]);
+                    } else {
+                        san.push_str(&from);
+                    }
+                }
+            }
+
+            if capture {
+                san.push('x');
+            }
+            san.push_str(&Board::square_name(m.to));
+            if let Some(kind) = m.promotion {
+                san.push('=');
+                san.push(Self::san_letter(kind));
+            }
+            san
+        };
+
+        let mut next = board.clone();
+        next.apply_move(m);
+        if next.is_king_in_check(next.get_turn()) {
+            san.push(if Self::legal(&mut next).is_empty() { '#' } else { '+' });
+        }
+
+        san
+    }
+
+    fn san_letter(kind: PieceKind) -> char {
+        match kind {
+            PieceKind::Pawn => 'P',
+            PieceKind::Knight => 'N',
+            PieceKind::Bishop => 'B',
+            PieceKind::Rook => 'R',
+            PieceKind::Queen => 'Q',
+            PieceKind::King => 'K',
+        }
+    }
+
+    fn san_piece(letter: char) -> Option<PieceKind> {
+        match letter {
+            'N' => Some(PieceKind::Knight),
+            'B' => Some(PieceKind::Bishop),
+            'R' => Some(PieceKind::Rook),
+            'Q' => Some(PieceKind::Queen),
+            'K' => Some(PieceKind::King),
+            _ => None
+        }
+    }
+
     pub fn pawn(board: &mut Board, player: Player, (x, y): (i32, i32)) -> Vec<ChessMove> {
         let friends = board.get_occupied(player);
 
@@ -189,7 +411,23 @@ impl MoveGen {
             }
         }
 
-        moves
+        // A pawn reaching the last row turns into one of these
+        let last_row = match player {
+            Player::White => 0,
+            Player::Black => 7
+        };
+        let mut with_promotions: Vec<ChessMove> = Vec::new();
+        for m in moves {
+            if Board::index_to_row_col(m.to).0 == last_row {
+                for kind in [PieceKind::Queen, PieceKind::Knight, PieceKind::Rook, PieceKind::Bishop] {
+                    with_promotions.push(m.with_promotion(kind));
+                }
+            } else {
+                with_promotions.push(m);
+            }
+        }
+
+        with_promotions
     }
 
     pub fn pawns(board: &mut Board, player: Player) -> Vec<ChessMove> {
@@ -208,38 +446,91 @@ impl MoveGen {
         moves
     }
 
-    // Needed because pawns have special kill moves
-    pub fn pawn_attacks(board: &mut Board, player: Player) -> Vec<ChessMove> {
-        let mut moves: Vec<ChessMove> = Vec::new();
+    // All squares attacked by a set of pawns, as a bitboard
+    pub fn pawn_attack_mask(pawns: u64, player: Player) -> u64 {
+        match player {
+            Player::White => shift(pawns & !FILE_A, -9) | shift(pawns & !FILE_H, -7),
+            Player::Black => shift(pawns & !FILE_A, 7) | shift(pawns & !FILE_H, 9),
+        }
+    }
 
-        let pawns = match player {
-            Player::White => board.white[PieceKind::Pawn as usize],
-            Player::Black => board.black[PieceKind::Pawn as usize]
-        };
-        
-        for i in 0..u64::BITS {
-            if (pawns >> i) & 0b1 != 0 {
-                let (row, col) = Board::u64_to_row_col(1u64 << i);
+    /*
+     * ----- ATTACK BITBOARDS -----
+     * These only say which squares a piece hits, they do not care about whose pieces are
+     * there. They are a lot cheaper than building ChessMoves, so the evaluation uses them.
+     */
+    pub fn knight_attack_mask(index: i32) -> u64 {
+        KNIGHT_ATTACKS[index as usize]
+    }
 
-                let attacks = match player {
-                    Player::White => vec![(row - 1, col - 1), (row - 1, col + 1)],
-                    Player::Black => vec![(row + 1, col - 1), (row + 1, col + 1)],
-                };
+    pub fn king_attack_mask(index: i32) -> u64 {
+        KING_ATTACKS[index as usize]
+    }
 
-                for (to_r, to_c) in attacks {
-                    if board.is_valid((to_r, to_c), board.get_occupied(player)) {
-                        moves.push(ChessMove::new(
-                                Board::row_col_to_index(row, col),
-                                Board::row_col_to_index(to_r, to_c),
-                                PieceKind::Pawn,
-                                player,
-                        ));
-                    }
+    // Sliding pieces stop at the first occupied square, which is included
+    fn slide(index: i32, occupied: u64, dirs: &[(i32, i32)]) -> u64 {
+        let (row, col) = Board::index_to_row_col(index);
+        let mut attacks = 0;
+
+        for &(dx, dy) in dirs {
+            let (mut to_x, mut to_y) = (row, col);
+            loop {
+                to_x += dx;
+                to_y += dy;
+
+                if !Board::in_bounds((to_x, to_y)) {
+                    break;
+                }
+
+                let bit = Board::row_col_to_u64(to_x, to_y);
+                attacks |= bit;
+                if occupied & bit != 0 {
+                    break;
                 }
             }
         }
 
-        moves
+        attacks
+    }
+
+    pub fn bishop_attack_mask(index: i32, occupied: u64) -> u64 {
+        Self::slide(index, occupied, &BISHOP_DIRS)
+    }
+
+    pub fn rook_attack_mask(index: i32, occupied: u64) -> u64 {
+        Self::slide(index, occupied, &ROOK_DIRS)
+    }
+
+    pub fn queen_attack_mask(index: i32, occupied: u64) -> u64 {
+        Self::bishop_attack_mask(index, occupied) | Self::rook_attack_mask(index, occupied)
+    }
+
+    pub fn piece_attack_mask(kind: PieceKind, player: Player, index: i32, occupied: u64) -> u64 {
+        match kind {
+            PieceKind::Pawn => Self::pawn_attack_mask(1_u64 << index, player),
+            PieceKind::Knight => Self::knight_attack_mask(index),
+            PieceKind::Bishop => Self::bishop_attack_mask(index, occupied),
+            PieceKind::Rook => Self::rook_attack_mask(index, occupied),
+            PieceKind::Queen => Self::queen_attack_mask(index, occupied),
+            PieceKind::King => Self::king_attack_mask(index),
+        }
+    }
+
+    // Pieces of both players that attack the square, given the occupancy
+    pub fn attackers_to(board: &Board, index: i32, occupied: u64) -> u64 {
+        let bit = 1_u64 << index;
+        let diagonal = board.white[PieceKind::Bishop as usize] | board.black[PieceKind::Bishop as usize]
+            | board.white[PieceKind::Queen as usize] | board.black[PieceKind::Queen as usize];
+        let straight = board.white[PieceKind::Rook as usize] | board.black[PieceKind::Rook as usize]
+            | board.white[PieceKind::Queen as usize] | board.black[PieceKind::Queen as usize];
+
+        // A white pawn attacks the square if a black pawn on the square would attack it
+        (Self::pawn_attack_mask(bit, Player::Black) & board.white[PieceKind::Pawn as usize])
+            | (Self::pawn_attack_mask(bit, Player::White) & board.black[PieceKind::Pawn as usize])
+            | (Self::knight_attack_mask(index) & (board.white[PieceKind::Knight as usize] | board.black[PieceKind::Knight as usize]))
+            | (Self::king_attack_mask(index) & (board.white[PieceKind::King as usize] | board.black[PieceKind::King as usize]))
+            | (Self::bishop_attack_mask(index, occupied) & diagonal)
+            | (Self::rook_attack_mask(index, occupied) & straight)
     }
 
     pub fn knight(board: &mut Board, player: Player, (x, y): (i32, i32)) -> Vec<ChessMove> {
@@ -442,21 +733,16 @@ impl MoveGen {
         moves
     }
 
-    pub fn king_no_castling(board: &mut Board, player: Player, (x, y): (i32, i32)) -> Vec<ChessMove> {
-        let friends = board.get_occupied(player);
-
+    pub fn queens(board: &mut Board, player: Player) -> Vec<ChessMove> {
         let mut moves: Vec<ChessMove> = Vec::new();
+        let queens = match player {
+            Player::White => board.white[PieceKind::Queen as usize],
+            Player::Black => board.black[PieceKind::Queen as usize]
+        };
 
-        for &(dx, dy) in &KING_MOVES {
-            let (to_x, to_y) = (x + dx, y + dy);
-            if board.is_valid((to_x, to_y), friends) {
-                moves.push(
-                    ChessMove::new(
-                        Board::row_col_to_index(x, y),
-                        Board::row_col_to_index(to_x, to_y),
-                        PieceKind::King,
-                        player,
-                    ));
+        for i in 0..u64::BITS {
+            if (queens >> i) & 0b1 != 0 {
+                moves.extend(Self::queen(board, player, Board::index_to_row_col(i as i32)));
             }
         }
 
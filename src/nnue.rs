@@ -0,0 +1,369 @@
+use crate::piece::{Player, N_PIECES};
+use crate::board::Board;
+use crate::syzygy::TB_WIN;
+use crate::tt::MAX_PLY;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/*
+ * ----- NNUE -----
+ * An efficiently updatable neural network that can stand in for the hand-crafted evaluation.
+ * Its inputs are the 768 (colour, kind, square) combinations, seen from each player's side:
+ * the player's own pieces come first, and black sees the board upside down. The first layer
+ * turns those into two accumulators of `hidden` values, one per player. The side to move's
+ * accumulator and the other one are clipped to 0..QA, squared (SCReLU) and weighted into the
+ * score.
+ *
+ * Only a few inputs change with a move, so an accumulator is kept up to date by adding and
+ * subtracting the first layer's rows for the pieces that moved, found by comparing the
+ * white/black bitboards before and after apply_move, the same way Board keeps its psqt.
+ *
+ * The file is the quantised network as bullet writes it, little endian i16s:
+ *   - Feature weights, 768 rows of `hidden`, squares counted from a1
+ *   - Feature biases, `hidden`
+ *   - Output weights, `hidden` for the side to move followed by `hidden` for the other side
+ *   - Output bias
+ * and padding up to a multiple of 64 bytes. `hidden` follows from the file's size.
+ */
+
+const INPUTS: usize = 768;
+
+// Quantisation of the first and second layer's weights, and the score's scale in centipawns
+const QA: i32 = 255;
+const QB: i32 = 64;
+const SCALE: i32 = 400;
+
+// An accumulator this many pieces away from the position is built again from scratch
+const REFRESH_CHANGES: u32 = 16;
+
+// Stays clear of the tablebase and mate scores
+const MAX_EVAL: i32 = TB_WIN - MAX_PLY - 1;
+
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+    // Checked once, the CPU does not change
+    avx2: bool,
+}
+
+#[derive(Clone)]
+struct Accumulator {
+    // The pieces the values are for
+    white: [u64; N_PIECES],
+    black: [u64; N_PIECES],
+    // From white's and from black's side
+    values: [Vec<i16>; 2],
+    valid: bool,
+}
+
+// A network with an accumulator for every ply of a search, each one updated from the one
+// before it
+pub struct Nnue {
+    network: Arc<Network>,
+    stack: Vec<Accumulator>,
+}
+
+impl Network {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let values: Vec<i16> = bytes.chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect();
+
+        // The padding is shorter than a row, so it does not change the division
+        let hidden = values.len().saturating_sub(1) / (INPUTS + 3);
+        let used = hidden * (INPUTS + 3) + 1;
+        if hidden == 0 || bytes.len() - used * 2 >= 64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a 768 input network"));
+        }
+
+        let (feature_weights, rest) = values.split_at(INPUTS * hidden);
+        let (feature_biases, rest) = rest.split_at(hidden);
+        let (output_weights, rest) = rest.split_at(2 * hidden);
+
+        Ok(Self {
+            hidden,
+            feature_weights: feature_weights.to_vec(),
+            feature_biases: feature_biases.to_vec(),
+            output_weights: output_weights.to_vec(),
+            output_bias: rest[0] as i32,
+            avx2: Self::has_avx2(),
+        })
+    }
+
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+
+    fn row(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden]
+    }
+
+    // Centipawns for the player whose accumulator is `us`
+    fn output(&self, us: &[i16], them: &[i16]) -> i32 {
+        let (ours, theirs) = self.output_weights.split_at(self.hidden);
+        let sum = self.activate(us, ours).wrapping_add(self.activate(them, theirs));
+
+        // The squared activation is QA * QA too large, one QA of it goes first
+        (sum / QA + self.output_bias) * SCALE / (QA * QB)
+    }
+
+    fn activate(&self, values: &[i16], weights: &[i16]) -> i32 {
+        #[cfg(target_arch = "x86_64")]
+        if self.avx2 {
+            // SAFETY: the CPU was checked for AVX2 when the network was loaded
+            return unsafe { activate_avx2(values, weights) };
+        }
+
+        activate_scalar(values, weights)
+    }
+
+    fn has_avx2() -> bool {
+        #[cfg(target_arch = "x86_64")]
+        {
+            is_x86_feature_detected!("avx2")
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            false
+        }
+    }
+}
+
+impl Accumulator {
+    fn new(hidden: usize) -> Self {
+        Self {
+            white: [0; N_PIECES],
+            black: [0; N_PIECES],
+            values: [vec![0; hidden], vec![0; hidden]],
+            valid: false,
+        }
+    }
+
+    // How many pieces are placed differently on the board
+    fn distance(&self, board: &Board) -> u32 {
+        (0..N_PIECES)
+            .map(|i| (self.white[i] ^ board.white[i]).count_ones() + (self.black[i] ^ board.black[i]).count_ones())
+            .sum()
+    }
+
+    fn refresh(&mut self, network: &Network, board: &Board) {
+        for values in &mut self.values {
+            values.copy_from_slice(&network.feature_biases);
+        }
+        self.white = [0; N_PIECES];
+        self.black = [0; N_PIECES];
+        self.update(network, board);
+    }
+
+    // Moves the values from the pieces they are for to the board's
+    fn update(&mut self, network: &Network, board: &Board) {
+        for kind in 0..N_PIECES {
+            for (player, before, after) in [
+                (Player::White, self.white[kind], board.white[kind]),
+                (Player::Black, self.black[kind], board.black[kind]),
+            ] {
+                for square in Board::squares(before & !after) {
+                    self.apply(network, player, kind, square, sub);
+                }
+                for square in Board::squares(after & !before) {
+                    self.apply(network, player, kind, square, add);
+                }
+            }
+        }
+
+        self.white = board.white;
+        self.black = board.black;
+        self.valid = true;
+    }
+
+    fn apply(&mut self, network: &Network, player: Player, kind: usize, square: i32, op: fn(&mut [i16], &[i16])) {
+        op(&mut self.values[0], network.row(feature(Player::White, player, kind, square)));
+        op(&mut self.values[1], network.row(feature(Player::Black, player, kind, square)));
+    }
+}
+
+impl Nnue {
+    pub fn new(network: Arc<Network>) -> Self {
+        let stack = vec![Accumulator::new(network.hidden); MAX_PLY as usize + 1];
+        Self { network, stack }
+    }
+
+    pub fn network(&self) -> &Arc<Network> {
+        &self.network
+    }
+
+    // Score from the point of view of the player to move, like Eval::evaluate. The
+    // accumulator of the ply before is normally the parent position, a move away
+    pub fn evaluate(&mut self, board: &Board, ply: usize) -> i32 {
+        let ply = ply.min(self.stack.len() - 1);
+        let (before, rest) = self.stack.split_at_mut(ply);
+        let accumulator = &mut rest[0];
+
+        let own = if accumulator.valid { accumulator.distance(board) } else { u32::MAX };
+        let parent = before.last().filter(|parent| parent.valid).map_or(u32::MAX, |parent| parent.distance(board));
+
+        if own > 0 {
+            if parent < own && parent <= REFRESH_CHANGES {
+                let parent = &before[ply - 1];
+                for (values, from) in accumulator.values.iter_mut().zip(&parent.values) {
+                    values.copy_from_slice(from);
+                }
+                accumulator.white = parent.white;
+                accumulator.black = parent.black;
+                accumulator.update(&self.network, board);
+            } else if own <= REFRESH_CHANGES {
+                accumulator.update(&self.network, board);
+            } else {
+                accumulator.refresh(&self.network, board);
+            }
+        }
+
+        let (us, them) = match board.get_turn() {
+            Player::White => (&accumulator.values[0], &accumulator.values[1]),
+            Player::Black => (&accumulator.values[1], &accumulator.values[0]),
+        };
+
+        self.network.output(us, them).clamp(-MAX_EVAL, MAX_EVAL)
+    }
+}
+
+// Input for a piece seen from `perspective`'s side. The network counts squares from a1 and
+// the board from a8, so white is the one that flips them
+fn feature(perspective: Player, player: Player, kind: usize, square: i32) -> usize {
+    let (theirs, square) = match perspective {
+        Player::White => (player != Player::White, square ^ 56),
+        Player::Black => (player != Player::Black, square),
+    };
+
+    theirs as usize * 384 + kind * 64 + square as usize
+}
+
+// Simple enough loops for the compiler to vectorise
+fn add(values: &mut [i16], row: &[i16]) {
+    for (value, &weight) in values.iter_mut().zip(row) {
+        *value = value.wrapping_add(weight);
+    }
+}
+
+fn sub(values: &mut [i16], row: &[i16]) {
+    for (value, &weight) in values.iter_mut().zip(row) {
+        *value = value.wrapping_sub(weight);
+    }
+}
+
+fn activate_scalar(values: &[i16], weights: &[i16]) -> i32 {
+    values.iter().zip(weights).fold(0i32, |sum, (&value, &weight)| {
+        let value = (value as i32).clamp(0, QA);
+        sum.wrapping_add((value * value).wrapping_mul(weight as i32))
+    })
+}
+
+// Eight values at a time, widened to i32 so that the products can not overflow
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn activate_avx2(values: &[i16], weights: &[i16]) -> i32 {
+    use std::arch::x86_64::*;
+
+    let chunks = values.len().min(weights.len()) / 8;
+    let zero = _mm256_setzero_si256();
+    let qa = _mm256_set1_epi32(QA);
+    let mut sum = _mm256_setzero_si256();
+
+    for i in 0..chunks {
+        // SAFETY: both slices hold at least 8 * chunks values
+        let (value, weight) = unsafe {
+            (
+                _mm_loadu_si128(values.as_ptr().add(i * 8) as *const __m128i),
+                _mm_loadu_si128(weights.as_ptr().add(i * 8) as *const __m128i),
+            )
+        };
+        let value = _mm256_min_epi32(_mm256_max_epi32(_mm256_cvtepi16_epi32(value), zero), qa);
+        let weight = _mm256_cvtepi16_epi32(weight);
+        sum = _mm256_add_epi32(sum, _mm256_mullo_epi32(_mm256_mullo_epi32(value, value), weight));
+    }
+
+    let mut lanes = [0i32; 8];
+    // SAFETY: lanes is 32 bytes
+    unsafe { _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, sum) };
+
+    let rest = activate_scalar(&values[chunks * 8..], &weights[chunks * 8..]);
+    lanes.iter().fold(rest, |total, &lane| total.wrapping_add(lane))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movegen::MoveGen;
+
+    // xorshift, small values so that the accumulators stay well inside an i16
+    fn random_values(count: usize, seed: &mut u64, range: i16) -> Vec<i16> {
+        (0..count)
+            .map(|_| {
+                *seed ^= *seed << 13;
+                *seed ^= *seed >> 7;
+                *seed ^= *seed << 17;
+                (*seed % (2 * range as u64 + 1)) as i16 - range
+            })
+            .collect()
+    }
+
+    // Not a multiple of 8, so the AVX2 loop leaves a few values for the scalar one
+    fn random_network(hidden: usize) -> Network {
+        let mut seed = 0x9e3779b97f4a7c15;
+        Network {
+            hidden,
+            feature_weights: random_values(INPUTS * hidden, &mut seed, 40),
+            feature_biases: random_values(hidden, &mut seed, 100),
+            output_weights: random_values(2 * hidden, &mut seed, 64),
+            output_bias: 17,
+            avx2: Network::has_avx2(),
+        }
+    }
+
+    #[test]
+    fn incremental_accumulator_matches_refresh() {
+        let network = Arc::new(random_network(36));
+        let mut nnue = Nnue::new(network.clone());
+        let mut board = Board::new();
+        board.apply_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1".to_string());
+
+        // Castling and captures for both sides
+        for (ply, text) in ["e1g1", "b4c3", "d2c3", "e8c8", "e5f7", "a6e2"].iter().enumerate() {
+            nnue.evaluate(&board, ply);
+            let m = MoveGen::from_uci(&mut board, text).unwrap();
+            board.apply_move(m);
+
+            let score = nnue.evaluate(&board, ply + 1);
+            let mut fresh = Accumulator::new(network.hidden());
+            fresh.refresh(&network, &board);
+            assert_eq!(nnue.stack[ply + 1].values, fresh.values, "after {}", text);
+
+            let (us, them) = match board.get_turn() {
+                Player::White => (&fresh.values[0], &fresh.values[1]),
+                Player::Black => (&fresh.values[1], &fresh.values[0]),
+            };
+            assert_eq!(score, network.output(us, them).clamp(-MAX_EVAL, MAX_EVAL));
+        }
+    }
+
+    #[test]
+    fn avx2_matches_scalar() {
+        #[cfg(target_arch = "x86_64")]
+        if Network::has_avx2() {
+            let mut seed = 12345;
+            for length in [0, 7, 8, 36, 256] {
+                // Values outside 0..QA are clipped either way
+                let values = random_values(length, &mut seed, 400);
+                let weights = random_values(length, &mut seed, 128);
+                // SAFETY: the CPU was checked for AVX2 above
+                let avx2 = unsafe { activate_avx2(&values, &weights) };
+                assert_eq!(avx2, activate_scalar(&values, &weights), "length {}", length);
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::piece::{PieceKind, Player};
+use crate::board::Board;
+use crate::movegen::ChessMove;
+use crate::tt::MAX_PLY;
+use crate::see::see;
+
+use std::cmp::Reverse;
+
+/*
+ * Alpha-beta cuts off more the earlier the best move is tried, so moves are sorted by how
+ * likely they are to be good:
+ *   1. The best move the transposition table remembers for this position
+ *   2. Captures that do not lose material, most valuable victim first and least valuable
+ *      attacker second (MVV-LVA)
+ *   3. The two killer moves, quiet moves that caused a cutoff at the same ply elsewhere
+ *   4. The rest of the quiet moves, by how often they have caused cutoffs (history heuristic)
+ *   5. Captures that lose material according to the static exchange evaluation
+ */
+
+const HASH_MOVE: i32 = 1_000_000;
+const CAPTURE: i32 = 500_000;
+const BAD_CAPTURE: i32 = -500_000;
+const PROMOTION: i32 = 400_000;
+const KILLERS: [i32; 2] = [300_000, 290_000];
+// History scores are kept below this so they never pass the killers
+const MAX_HISTORY: i32 = 16_384;
+
+// Ordering only, not the evaluation's piece values
+const MVV_LVA_VALUES: [i32; 6] = [1, 3, 3, 5, 9, 20];
+
+pub struct MoveOrdering {
+    killers: Vec<[Option<ChessMove>; 2]>,
+    history: Box<[[[i32; 64]; 64]; 2]>, // [player][from][to]
+}
+
+impl MoveOrdering {
+    pub fn new() -> Self {
+        Self {
+            killers: vec![[None; 2]; MAX_PLY as usize + 1],
+            history: Box::new([[[0; 64]; 64]; 2]),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.killers.fill([None; 2]);
+        for table in self.history.iter_mut() {
+            table.fill([0; 64]);
+        }
+    }
+
+    // Killers are only useful within a search, the history is kept but made less important
+    pub fn new_search(&mut self) {
+        self.killers.fill([None; 2]);
+        for table in self.history.iter_mut() {
+            for row in table.iter_mut() {
+                for value in row.iter_mut() {
+                    *value /= 2;
+                }
+            }
+        }
+    }
+
+    pub fn victim(board: &Board, m: ChessMove) -> Option<PieceKind> {
+        match board.at(Board::index_to_row_col(m.to)) {
+            Some(piece) => Some(piece.kind),
+            // A pawn moving diagonally to an empty square takes en passant
+            None if m.kind == PieceKind::Pawn && m.from % 8 != m.to % 8 => Some(PieceKind::Pawn),
+            None => None
+        }
+    }
+
+    pub fn is_capture(board: &Board, m: ChessMove) -> bool {
+        Self::victim(board, m).is_some()
+    }
+
+    pub fn is_quiet(board: &Board, m: ChessMove) -> bool {
+        m.promotion.is_none() && !Self::is_capture(board, m)
+    }
+
+    pub fn mvv_lva(board: &Board, m: ChessMove) -> i32 {
+        match Self::victim(board, m) {
+            Some(victim) => MVV_LVA_VALUES[victim as usize] * 100 - MVV_LVA_VALUES[m.kind as usize],
+            None => 0
+        }
+    }
+
+    fn side(player: Player) -> usize {
+        match player {
+            Player::White => 0,
+            Player::Black => 1,
+        }
+    }
+
+    pub fn score(&self, board: &Board, m: ChessMove, tt_move: u16, ply: i32) -> i32 {
+        if tt_move != 0 && m.pack() == tt_move {
+            return HASH_MOVE;
+        }
+
+        if Self::is_capture(board, m) {
+            let base = if see(board, m) >= 0 { CAPTURE } else { BAD_CAPTURE };
+            return base + Self::mvv_lva(board, m);
+        }
+
+        if let Some(kind) = m.promotion {
+            return PROMOTION + kind as i32;
+        }
+
+        let killers = self.killers[ply as usize];
+        for (i, killer) in killers.iter().enumerate() {
+            if *killer == Some(m) {
+                return KILLERS[i];
+            }
+        }
+
+        self.history[Self::side(m.player)][m.from as usize][m.to as usize]
+    }
+
+    pub fn is_killer(&self, m: ChessMove, ply: i32) -> bool {
+        self.killers[ply as usize].contains(&Some(m))
+    }
+
+    // Best moves first
+    pub fn order(&self, board: &Board, moves: &mut Vec<ChessMove>, tt_move: u16, ply: i32) {
+        let mut scored: Vec<(i32, ChessMove)> = moves.iter().map(|&m| (self.score(board, m, tt_move, ply), m)).collect();
+        scored.sort_by_key(|&(score, _)| Reverse(score));
+
+        moves.clear();
+        moves.extend(scored.into_iter().map(|(_, m)| m));
+    }
+
+    // Called when a quiet move causes a beta cutoff. The quiet moves tried before it did not,
+    // so they get pushed down
+    pub fn update(&mut self, best: ChessMove, tried: &[ChessMove], depth: i32, ply: i32) {
+        let killers = &mut self.killers[ply as usize];
+        if killers[0] != Some(best) {
+            killers[1] = killers[0];
+            killers[0] = Some(best);
+        }
+
+        let bonus = (depth * depth).min(400);
+        self.add_history(best, bonus);
+        for &m in tried {
+            if m != best {
+                self.add_history(m, -bonus);
+            }
+        }
+    }
+
+    // Moves the entry towards +-MAX_HISTORY, slower the closer it already is
+    fn add_history(&mut self, m: ChessMove, bonus: i32) {
+        let entry = &mut self.history[Self::side(m.player)][m.from as usize][m.to as usize];
+        *entry += bonus - *entry * bonus.abs() / MAX_HISTORY;
+    }
+}
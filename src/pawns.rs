@@ -0,0 +1,300 @@
+use crate::piece::{PieceKind, Player};
+use crate::board::Board;
+use crate::movegen::{MoveGen, FILE_A};
+use crate::eval::{Score, Term, Trace};
+
+/*
+ * Pawn structure terms. Everything that only depends on where the pawns are is cached in the
+ * pawn hash table, since the pawn structure changes a lot less often than the rest of the board.
+ * Passed pawns also depend on the pieces in front of them, so the table only remembers which
+ * pawns are passed and their bonus is finished in Pawns::evaluate.
+ *
+ * Scores are from white's point of view. Ranks are relative, so 1 is the pawn's starting rank
+ * minus one and 6 is one step away from promoting.
+ */
+
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+pub const DOUBLED: Score = Score::new(-10, -25);
+pub const ISOLATED: Score = Score::new(-10, -15);
+pub const BACKWARD: Score = Score::new(-8, -12);
+
+// Indexed by relative rank
+pub const CONNECTED: [Score; 8] = [
+    Score::new(0, 0),
+    Score::new(5, 0),
+    Score::new(8, 3),
+    Score::new(12, 8),
+    Score::new(20, 15),
+    Score::new(35, 30),
+    Score::new(55, 50),
+    Score::new(0, 0),
+];
+
+pub const PASSED: [Score; 8] = [
+    Score::new(0, 0),
+    Score::new(5, 10),
+    Score::new(10, 15),
+    Score::new(15, 25),
+    Score::new(30, 50),
+    Score::new(50, 85),
+    Score::new(80, 130),
+    Score::new(0, 0),
+];
+
+// Extra bonus for a passed pawn whose next square is empty, and for one with nothing at all in front of it
+pub const PASSED_STOP_FREE: [Score; 8] = [
+    Score::new(0, 0),
+    Score::new(0, 2),
+    Score::new(0, 4),
+    Score::new(2, 8),
+    Score::new(5, 15),
+    Score::new(10, 25),
+    Score::new(15, 40),
+    Score::new(0, 0),
+];
+
+pub const PASSED_PATH_FREE: [Score; 8] = [
+    Score::new(0, 0),
+    Score::new(0, 2),
+    Score::new(0, 5),
+    Score::new(3, 10),
+    Score::new(8, 20),
+    Score::new(15, 40),
+    Score::new(25, 60),
+    Score::new(0, 0),
+];
+
+#[derive(Copy, Clone, Debug)]
+struct PawnEntry {
+    key: u64,
+    score: Score,
+    passed: [u64; 2], // White and black passed pawns
+}
+
+pub struct PawnTable {
+    entries: Vec<Option<PawnEntry>>,
+}
+
+pub struct Pawns {
+}
+
+impl PawnTable {
+    pub fn new() -> Self {
+        Self { entries: vec![None; PAWN_TABLE_SIZE] }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+
+    fn probe(&mut self, board: &Board) -> PawnEntry {
+        let index = board.pawn_hash as usize % PAWN_TABLE_SIZE;
+        if let Some(entry) = self.entries[index] && entry.key == board.pawn_hash {
+            return entry;
+        }
+
+        let entry = Pawns::structure(board, &mut ());
+        self.entries[index] = Some(entry);
+        entry
+    }
+}
+
+pub fn file_mask(col: i32) -> u64 {
+    FILE_A << col
+}
+
+pub fn row_mask(row: i32) -> u64 {
+    0xff_u64 << (row * 8)
+}
+
+pub fn adjacent_files(col: i32) -> u64 {
+    let mut mask = 0;
+    if col > 0 {
+        mask |= file_mask(col - 1);
+    }
+    if col < 7 {
+        mask |= file_mask(col + 1);
+    }
+
+    mask
+}
+
+// All rows strictly in front of the row, seen from the player
+pub fn rows_ahead(player: Player, row: i32) -> u64 {
+    match player {
+        Player::White => (1_u64 << (row * 8)) - 1,
+        Player::Black => if row == 7 { 0 } else { !((1_u64 << ((row + 1) * 8)) - 1) },
+    }
+}
+
+pub fn relative_rank(player: Player, row: i32) -> usize {
+    match player {
+        Player::White => (7 - row) as usize,
+        Player::Black => row as usize,
+    }
+}
+
+// Squares a pawn has to pass, or have next to it, to promote
+pub fn passed_mask(player: Player, index: i32) -> u64 {
+    let (row, col) = Board::index_to_row_col(index);
+    rows_ahead(player, row) & (file_mask(col) | adjacent_files(col))
+}
+
+impl Pawns {
+    fn pawns(board: &Board, player: Player) -> u64 {
+        match player {
+            Player::White => board.white[PieceKind::Pawn as usize],
+            Player::Black => board.black[PieceKind::Pawn as usize],
+        }
+    }
+
+    // The cached part, computed from the pawn bitboards alone
+    fn structure<T: Trace>(board: &Board, trace: &mut T) -> PawnEntry {
+        let mut entry = PawnEntry { key: board.pawn_hash, score: Score::default(), passed: [0; 2] };
+
+        for (side, player) in [Player::White, Player::Black].into_iter().enumerate() {
+            let ours = Self::pawns(board, player);
+            let theirs = Self::pawns(board, Board::opponent(player));
+            let our_attacks = MoveGen::pawn_attack_mask(ours, player);
+            let their_attacks = MoveGen::pawn_attack_mask(theirs, Board::opponent(player));
+            let mut score = Score::default();
+
+            for col in 0..8 {
+                let count = (ours & file_mask(col)).count_ones() as i32;
+                if count > 1 {
+                    score += DOUBLED * (count - 1);
+                    trace.add(Term::DOUBLED, player, count - 1);
+                }
+            }
+
+            let mut bits = ours;
+            while bits != 0 {
+                let index = bits.trailing_zeros() as i32;
+                let bit = 1_u64 << index;
+                let (row, col) = Board::index_to_row_col(index);
+                let rank = relative_rank(player, row);
+                bits &= bits - 1;
+
+                let neighbours = ours & adjacent_files(col);
+                if neighbours == 0 {
+                    score += ISOLATED;
+                    trace.add(Term::ISOLATED, player, 1);
+                } else {
+                    // No pawn next to or behind it that could ever defend it, and it can not
+                    // advance without being taken
+                    let stop = match player {
+                        Player::White => bit >> 8,
+                        Player::Black => bit << 8,
+                    };
+                    let behind = !rows_ahead(player, row) | row_mask(row);
+                    if neighbours & behind == 0 && stop & their_attacks != 0 {
+                        score += BACKWARD;
+                        trace.add(Term::BACKWARD, player, 1);
+                    }
+                }
+
+                let phalanx = neighbours & row_mask(row) != 0;
+                let supported = bit & our_attacks != 0;
+                if phalanx || supported {
+                    score += CONNECTED[rank];
+                    trace.add(Term::CONNECTED + rank, player, 1);
+                }
+
+                if theirs & passed_mask(player, index) == 0 && ours & rows_ahead(player, row) & file_mask(col) == 0 {
+                    entry.passed[side] |= bit;
+                }
+            }
+
+            match player {
+                Player::White => entry.score += score,
+                Player::Black => entry.score -= score,
+            }
+        }
+
+        entry
+    }
+
+    // Passed pawns are worth more the further they have come and the freer their path is
+    fn passed<T: Trace>(board: &Board, passed: u64, player: Player, trace: &mut T) -> Score {
+        let occupied = !board.get_empty();
+        let mut score = Score::default();
+
+        let mut bits = passed;
+        while bits != 0 {
+            let index = bits.trailing_zeros() as i32;
+            let (row, col) = Board::index_to_row_col(index);
+            let rank = relative_rank(player, row);
+            bits &= bits - 1;
+
+            // Only happens while the GUI waits for a promotion piece
+            if rank == 7 {
+                continue;
+            }
+
+            score += PASSED[rank];
+            trace.add(Term::PASSED + rank, player, 1);
+
+            let path = rows_ahead(player, row) & file_mask(col);
+            let stop = match player {
+                Player::White => 1_u64 << (index - 8),
+                Player::Black => 1_u64 << (index + 8),
+            };
+            if path & occupied == 0 {
+                score += PASSED_PATH_FREE[rank];
+                trace.add(Term::PASSED_PATH_FREE + rank, player, 1);
+            } else if stop & occupied == 0 {
+                score += PASSED_STOP_FREE[rank];
+                trace.add(Term::PASSED_STOP_FREE + rank, player, 1);
+            }
+        }
+
+        score
+    }
+
+    pub fn evaluate<T: Trace>(board: &Board, table: &mut PawnTable, trace: &mut T) -> Score {
+        let entry = if T::ENABLED { Self::structure(board, trace) } else { table.probe(board) };
+
+        entry.score + Self::passed(board, entry.passed[0], Player::White, trace) - Self::passed(board, entry.passed[1], Player::Black, trace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.apply_fen(fen.to_string());
+        board
+    }
+
+    fn bit(row: i32, col: i32) -> u64 {
+        1_u64 << Board::row_col_to_index(row, col)
+    }
+
+    #[test]
+    fn isolated_and_passed() {
+        // a5 is passed, e5 is held up by d7, and all three are isolated
+        let entry = Pawns::structure(&board("4k3/3p4/8/P3P3/8/8/8/4K3 w - - 0 1"), &mut ());
+        assert_eq!(entry.passed, [bit(3, 0), 0]);
+        assert_eq!(entry.score, ISOLATED * 2 - ISOLATED);
+    }
+
+    #[test]
+    fn doubled() {
+        // Only the front pawn of the two is passed
+        let entry = Pawns::structure(&board("4k3/8/8/8/8/2P5/2P5/4K3 w - - 0 1"), &mut ());
+        assert_eq!(entry.passed, [bit(5, 2), 0]);
+        assert_eq!(entry.score, DOUBLED + ISOLATED * 2);
+    }
+
+    #[test]
+    fn table_gives_the_computed_score() {
+        let board = board("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let mut table = PawnTable::new();
+        let computed = Pawns::evaluate(&board, &mut table, &mut ());
+        assert_eq!(Pawns::evaluate(&board, &mut table, &mut ()), computed);
+        assert_eq!(table.probe(&board).score, Pawns::structure(&board, &mut ()).score);
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/*
+ * ----- PGN -----
+ * Reads games in Portable Game Notation. Only the tags and the main line are kept, comments,
+ * variations and annotations are skipped. The moves are left in SAN, MoveGen::from_san turns
+ * them into ChessMoves once the game is replayed.
+ *
+ * Games are written back the same way through Display, tags first and then the moves.
+ */
+
+// Movetext lines are kept below this, as the standard asks
+const LINE_LENGTH: usize = 80;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    Unknown,
+}
+
+#[derive(Clone, Debug)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<String>,
+    pub result: GameResult,
+}
+
+impl GameResult {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "1-0" => Some(Self::WhiteWins),
+            "0-1" => Some(Self::BlackWins),
+            "1/2-1/2" => Some(Self::Draw),
+            "*" => Some(Self::Unknown),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::WhiteWins => "1-0",
+            Self::BlackWins => "0-1",
+            Self::Draw => "1/2-1/2",
+            Self::Unknown => "*",
+        })
+    }
+}
+
+impl PgnGame {
+    fn new() -> Self {
+        Self { tags: Vec::new(), moves: Vec::new(), result: GameResult::Unknown }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for PgnGame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, value) in &self.tags {
+            writeln!(f, "[{} \"{}\"]", name, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+        }
+        writeln!(f)?;
+
+        // Games set up with black to move start at `1...`
+        let fen: Vec<&str> = self.tag("FEN").unwrap_or("").split_whitespace().collect();
+        let black_first = fen.get(1) == Some(&"b");
+        let first_move = fen.get(5).and_then(|number| number.parse().ok()).unwrap_or(1_usize);
+
+        let mut tokens = Vec::new();
+        for (i, san) in self.moves.iter().enumerate() {
+            let ply = i + black_first as usize;
+            let number = first_move + ply / 2;
+            if ply.is_multiple_of(2) {
+                tokens.push(format!("{}.", number));
+            } else if i == 0 {
+                tokens.push(format!("{}...", number));
+            }
+            tokens.push(san.clone());
+        }
+        tokens.push(self.result.to_string());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() >= LINE_LENGTH {
+                writeln!(f, "{}", line)?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        writeln!(f, "{}", line)
+    }
+}
+
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<PgnGame>> {
+    // Lots of PGN files out there are Latin-1, the odd name with an accent should not stop us
+    let bytes = fs::read(path)?;
+    Ok(parse(&String::from_utf8_lossy(&bytes)))
+}
+
+pub fn parse(text: &str) -> Vec<PgnGame> {
+    let mut games = Vec::new();
+    let mut game = PgnGame::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                // Tags after moves belong to the next game, even if the result was left out
+                if !game.moves.is_empty() {
+                    games.push(std::mem::replace(&mut game, PgnGame::new()));
+                }
+
+                let tag: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                if let Some((name, value)) = tag.trim().split_once(char::is_whitespace) {
+                    game.tags.push((name.to_string(), value.trim().trim_matches('"').to_string()));
+                }
+            },
+            '{' => {
+                chars.by_ref().find(|&c| c == '}');
+            },
+            ';' => {
+                chars.by_ref().find(|&c| c == '\n');
+            },
+            '(' => {
+                let mut depth = 1;
+                for c in chars.by_ref() {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => ()
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            },
+            c if c.is_whitespace() => (),
+            c => {
+                let mut token = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "[]{}();".contains(next) {
+                        break;
+                    }
+                    token.push(next);
+                    chars.next();
+                }
+
+                if let Some(result) = GameResult::parse(&token) {
+                    game.result = result;
+                    games.push(std::mem::replace(&mut game, PgnGame::new()));
+                    continue;
+                }
+
+                // Annotations ($1) and move numbers (12. or 12...), which may be stuck to the move
+                if token.starts_with('$') {
+                    continue;
+                }
+                let san = if token.contains('.') {
+                    token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.')
+                } else {
+                    &token
+                };
+                if !san.is_empty() {
+                    game.moves.push(san.to_string());
+                }
+            }
+        }
+    }
+
+    if !game.moves.is_empty() || !game.tags.is_empty() {
+        games.push(game);
+    }
+
+    games
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+    use crate::movegen::MoveGen;
+
+    #[test]
+    fn tags_and_moves() {
+        let text = "[Event \"Test\"]\n[FEN \"r3k2r/1P6/8/8/8/8/6p1/R3K2R w KQkq - 0 1\"]\n\n\
+                    1. O-O-O gxh1=Q {a comment} 2. Rxh1 (2. Rd8+ Kxd8) Rxh1+ 3. Kc2 Kd7 4. bxa8=Q 1-0\n";
+        let games = parse(text);
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].tag("Event"), Some("Test"));
+        assert_eq!(games[0].result, GameResult::WhiteWins);
+        assert_eq!(games[0].moves, ["O-O-O", "gxh1=Q", "Rxh1", "Rxh1+", "Kc2", "Kd7", "bxa8=Q"]);
+
+        // Castling, captures and promotions all replay to the right moves
+        let mut board = Board::new();
+        board.apply_fen(games[0].tag("FEN").unwrap().to_string());
+        let mut played = Vec::new();
+        for san in &games[0].moves {
+            let m = MoveGen::from_san(&mut board, san).unwrap_or_else(|| panic!("{}", san));
+            board.apply_move(m);
+            played.push(m.to_uci());
+        }
+        assert_eq!(played, ["e1c1", "g2h1q", "d1h1", "h8h1", "c1c2", "e8d7", "b7a8q"]);
+    }
+
+    #[test]
+    fn written_games_read_back() {
+        let fen = "r3k2r/1P6/8/8/8/8/6p1/R3K2R w KQkq - 0 1";
+        let moves = ["e1c1", "g2h1q", "d1h1", "h8h1", "c1c2", "e8d7", "b7a8q"];
+
+        let mut board = Board::new();
+        board.apply_fen(fen.to_string());
+        let mut game = PgnGame::new();
+        game.tags.push(("FEN".to_string(), fen.to_string()));
+        game.result = GameResult::WhiteWins;
+        for text in moves {
+            let m = MoveGen::from_uci(&mut board, text).unwrap();
+            game.moves.push(MoveGen::to_san(&mut board, m));
+            board.apply_move(m);
+        }
+        assert_eq!(game.moves, ["O-O-O", "gxh1=Q", "Rxh1", "Rxh1+", "Kc2", "Kd7", "bxa8=Q"]);
+
+        let read = parse(&game.to_string());
+        assert_eq!(read.len(), 1);
+        assert_eq!((read[0].tag("FEN"), read[0].result), (Some(fen), GameResult::WhiteWins));
+
+        let mut board = Board::new();
+        board.apply_fen(fen.to_string());
+        for (san, text) in read[0].moves.iter().zip(moves) {
+            let m = MoveGen::from_san(&mut board, san).unwrap_or_else(|| panic!("{}", san));
+            assert_eq!(m.to_uci(), text);
+            board.apply_move(m);
+        }
+        assert_eq!(read[0].moves.len(), moves.len());
+    }
+}
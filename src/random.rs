@@ -0,0 +1,30 @@
+use crate::zobrist::next_random;
+
+// Pseudo random numbers from a seed, the same seed always gives the same numbers. Not for
+// anything where it matters that they can not be guessed
+#[derive(Clone, Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next(&mut self) -> u64 {
+        let (state, value) = next_random(self.state);
+        self.state = state;
+        value
+    }
+
+    // Below `n`, which must not be 0. The slight bias towards low numbers does not matter here
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    // Evenly spread in [0, 1)
+    pub fn fraction(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
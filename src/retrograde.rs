@@ -0,0 +1,696 @@
+use crate::piece::{PieceKind, Player, N_PIECES};
+use crate::board::Board;
+use crate::movegen::{ChessMove, MoveGen};
+use crate::ordering::MoveOrdering;
+use crate::syzygy::Tablebases;
+use crate::tt::MATE;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+/*
+ * ----- RETROGRADE ENDGAME TABLES -----
+ * Our own tables for endings with a few pieces, holding the distance to mate (DTM) of every
+ * position, so they can be played perfectly. A table is generated backwards from the mates:
+ *   - Every position's legal moves are generated once. Captures and promotions leave the
+ *     table and are looked up in the smaller tables, which are generated first. The other
+ *     moves are counted
+ *   - Going up one ply at a time, positions lost in n plies make every position that can move
+ *     into them won in n + 1. Positions won in n take one off the count of every position that
+ *     can move into them, and a position whose moves have all been counted off is lost
+ *   - What can move into a position is found by taking moves back, see MoveGen::unmoves
+ * Whatever is never reached is a draw.
+ *
+ * Tables are named like KBNvK, with the stronger side as white. A position is indexed by the
+ * side to move, the white king and the squares of the other pieces. The board's symmetries
+ * keep the white king in the a1-d1-d4 triangle, or on files a-d with pawns. En passant is not
+ * part of a position, and the fifty move rule is ignored.
+ *
+ * On disk a table is a magic number followed by runs of equal values, each a byte followed by
+ * the run's length as a varint.
+ */
+
+const MAGIC: [u8; 4] = *b"DTM1";
+const EXTENSION: &str = "dtm";
+
+// Values are stored as plies to mate plus one, so 0 can mean a draw
+const MAX_PLIES: usize = 254;
+
+// What --generate-tables makes when not told otherwise
+pub const DEFAULT_TABLES: [&str; 8] = ["KQvK", "KRvK", "KPvK", "KBNvK", "KQvKR", "KRvKB", "KRvKN", "KBBvK"];
+
+const PIECE_LETTERS: [(PieceKind, char, i32); 5] = [
+    (PieceKind::Queen, 'Q', 9),
+    (PieceKind::Rook, 'R', 5),
+    (PieceKind::Bishop, 'B', 3),
+    (PieceKind::Knight, 'N', 3),
+    (PieceKind::Pawn, 'P', 1),
+];
+
+// Plies to mate, for the player to move
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dtm {
+    Draw,
+    Win(i32),
+    Loss(i32), // Loss(0) is checkmate
+}
+
+struct Material {
+    name: String,
+    // The pieces besides the white king, grouped by kind, in the order they are indexed
+    groups: Vec<(Player, PieceKind, usize)>,
+    has_pawns: bool,
+    // The squares the white king is kept on, and the other way around
+    king_squares: Vec<i32>,
+    king_slots: [Option<usize>; 64],
+}
+
+pub struct EndgameTable {
+    material: Material,
+    values: Vec<u8>,
+}
+
+pub struct EndgameTables {
+    tables: HashMap<String, EndgameTable>,
+    max_pieces: usize,
+}
+
+impl Dtm {
+    fn from_value(value: u8) -> Self {
+        match value {
+            0 => Self::Draw,
+            value if value % 2 == 0 => Self::Win(value as i32 - 1),
+            value => Self::Loss(value as i32 - 1),
+        }
+    }
+
+    // The same result one ply earlier, for the player who made the move
+    fn before_move(self) -> Self {
+        match self {
+            Self::Draw => Self::Draw,
+            Self::Win(plies) => Self::Loss(plies + 1),
+            Self::Loss(plies) => Self::Win(plies + 1),
+        }
+    }
+
+    // Higher is better. Quick wins and slow losses
+    fn rank(self) -> i32 {
+        match self {
+            Self::Draw => 0,
+            Self::Win(plies) => 1000 - plies,
+            Self::Loss(plies) => -1000 + plies,
+        }
+    }
+
+    // Search score at `ply`, a mate score like the search's own
+    pub fn score(self, ply: i32) -> i32 {
+        match self {
+            Self::Draw => 0,
+            Self::Win(plies) => MATE - ply - plies,
+            Self::Loss(plies) => -MATE + ply + plies,
+        }
+    }
+}
+
+impl Material {
+    fn parse(name: &str) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+
+        let mut groups = Vec::new();
+        for (player, side) in [(Player::White, white), (Player::Black, black)] {
+            let mut letters = side.chars();
+            if letters.next() != Some('K') || !letters.all(|c| PIECE_LETTERS.iter().any(|&(_, letter, _)| letter == c)) {
+                return None;
+            }
+
+            if player == Player::Black {
+                groups.push((Player::Black, PieceKind::King, 1));
+            }
+            for &(kind, letter, _) in &PIECE_LETTERS {
+                let count = side.chars().filter(|&c| c == letter).count();
+                if count > 0 {
+                    groups.push((player, kind, count));
+                }
+            }
+        }
+
+        let has_pawns = name.contains('P');
+        let mut king_squares = Vec::new();
+        let mut king_slots = [None; 64];
+        for square in 0..64 {
+            let (file, rank) = (square % 8, 7 - square / 8);
+            if file <= 3 && (has_pawns || rank <= file) {
+                king_slots[square as usize] = Some(king_squares.len());
+                king_squares.push(square);
+            }
+        }
+
+        Some(Self { name: name.to_string(), groups, has_pawns, king_squares, king_slots })
+    }
+
+    fn piece_count(&self) -> usize {
+        1 + self.groups.iter().map(|&(_, _, count)| count).sum::<usize>()
+    }
+
+    fn size(&self) -> usize {
+        2 * self.king_squares.len() * 64_usize.pow(self.piece_count() as u32 - 1)
+    }
+
+    // The materials a capture or a promotion leads to, with the stronger side first
+    fn successors(&self) -> Vec<String> {
+        let (white, black) = self.name.split_once('v').unwrap_or_default();
+        let mut names = Vec::new();
+
+        for (side, other, white_side) in [(white, black, true), (black, white, false)] {
+            for (i, letter) in side.char_indices().skip(1) {
+                let mut without = side.to_string();
+                without.remove(i);
+
+                // Captured
+                names.push(if white_side { normalize(&without, other) } else { normalize(other, &without) });
+
+                // Promoted
+                if letter == 'P' {
+                    for promotion in ['Q', 'R', 'B', 'N'] {
+                        let promoted = format!("{}{}", without, promotion);
+                        names.push(if white_side { normalize(&promoted, other) } else { normalize(other, &promoted) });
+                    }
+                }
+            }
+        }
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // The board's white pieces must match the table's white pieces
+    fn index(&self, board: &Board) -> Option<usize> {
+        let pieces = |player: Player| match player {
+            Player::White => &board.white,
+            Player::Black => &board.black,
+        };
+
+        let king = pieces(Player::White)[PieceKind::King as usize];
+        if king.count_ones() != 1 {
+            return None;
+        }
+        let king = king.trailing_zeros() as i32;
+
+        let mut squares = Vec::with_capacity(self.piece_count());
+        for &(player, kind, count) in &self.groups {
+            let bits = pieces(player)[kind as usize];
+            if bits.count_ones() as usize != count {
+                return None;
+            }
+            squares.extend(Board::squares(bits));
+        }
+
+        // Several symmetries can put the king where it belongs, the lowest index wins
+        let stm = (board.get_turn() == Player::Black) as usize;
+        let mut best: Option<usize> = None;
+        for t in 0..self.transforms() {
+            let Some(slot) = self.king_slots[transform(king, t) as usize] else {
+                continue;
+            };
+
+            let mut index = stm * self.king_squares.len() + slot;
+            let mut at = 0;
+            for &(_, _, count) in &self.groups {
+                // Pieces of the same kind could be in either order, keep them sorted
+                let mut group: Vec<i32> = squares[at..at + count].iter().map(|&square| transform(square, t)).collect();
+                group.sort_unstable();
+                for square in group {
+                    index = index * 64 + square as usize;
+                }
+                at += count;
+            }
+
+            best = Some(best.map_or(index, |best| best.min(index)));
+        }
+
+        best
+    }
+
+    // None for indices that are not a legal position
+    fn decode(&self, mut index: usize) -> Option<Board> {
+        let mut squares = vec![0; self.piece_count() - 1];
+        for square in squares.iter_mut().rev() {
+            *square = (index % 64) as i32;
+            index /= 64;
+        }
+        let king = self.king_squares[index % self.king_squares.len()];
+        let turn = if index / self.king_squares.len() == 0 { Player::White } else { Player::Black };
+
+        let mut board = Board::new();
+        board.set(PieceKind::King, true, Board::index_to_u64(king));
+
+        let mut occupied = Board::index_to_u64(king);
+        let mut at = 0;
+        for &(player, kind, count) in &self.groups {
+            for &square in &squares[at..at + count] {
+                let bit = Board::index_to_u64(square);
+                let back_rank = square / 8 == 0 || square / 8 == 7;
+                if occupied & bit != 0 || (kind == PieceKind::Pawn && back_rank) {
+                    return None;
+                }
+
+                occupied |= bit;
+                board.set(kind, player == Player::White, bit);
+            }
+            at += count;
+        }
+
+        board.set_turn(turn);
+
+        // The player who just moved can not be in check
+        if board.is_king_in_check(Board::opponent(turn)) {
+            return None;
+        }
+
+        Some(board)
+    }
+
+    fn transforms(&self) -> usize {
+        if self.has_pawns { 2 } else { 8 }
+    }
+}
+
+impl EndgameTable {
+    fn probe(&self, board: &Board) -> Option<Dtm> {
+        let index = self.material.index(board)?;
+        Some(Dtm::from_value(self.values[index]))
+    }
+
+    fn write(&self, dir: &Path) -> io::Result<()> {
+        let mut bytes = MAGIC.to_vec();
+
+        let mut i = 0;
+        while i < self.values.len() {
+            let value = self.values[i];
+            let run = self.values[i..].iter().take_while(|&&v| v == value).count();
+            bytes.push(value);
+
+            let mut rest = run;
+            while rest >= 0x80 {
+                bytes.push((rest & 0x7F) as u8 | 0x80);
+                rest >>= 7;
+            }
+            bytes.push(rest as u8);
+
+            i += run;
+        }
+
+        fs::write(dir.join(format!("{}.{}", self.material.name, EXTENSION)), bytes)
+    }
+
+    fn read(path: &Path, material: Material) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+        let bytes = fs::read(path)?;
+        if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Err(invalid("not an endgame table"));
+        }
+
+        let size = material.size();
+        let mut values = Vec::with_capacity(size);
+        let mut at = MAGIC.len();
+
+        while at < bytes.len() {
+            let value = bytes[at];
+            at += 1;
+
+            let mut run = 0;
+            let mut shift = 0;
+            loop {
+                let byte = *bytes.get(at).ok_or_else(|| invalid("table is truncated"))?;
+                at += 1;
+                run |= ((byte & 0x7F) as usize) << shift;
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+
+            if values.len() + run > size {
+                return Err(invalid("table is too long"));
+            }
+            values.resize(values.len() + run, value);
+        }
+
+        if values.len() != size {
+            return Err(invalid("table is too short"));
+        }
+
+        Ok(Self { material, values })
+    }
+}
+
+impl EndgameTables {
+    pub fn new() -> Self {
+        Self { tables: HashMap::new(), max_pieces: 0 }
+    }
+
+    // Every table in the directory
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let mut tables = Self::new();
+
+        for file in fs::read_dir(dir)?.flatten() {
+            let path = file.path();
+            if path.extension().is_none_or(|extension| extension != EXTENSION) {
+                continue;
+            }
+
+            let Some(material) = path.file_stem().and_then(|stem| stem.to_str()).and_then(Material::parse) else {
+                continue;
+            };
+
+            match EndgameTable::read(&path, material) {
+                Ok(table) => tables.insert(table),
+                Err(err) => eprintln!("Could not read {}: {}", path.display(), err),
+            }
+        }
+
+        Ok(tables)
+    }
+
+    fn insert(&mut self, table: EndgameTable) {
+        self.max_pieces = self.max_pieces.max(table.material.piece_count());
+        self.tables.insert(table.material.name.clone(), table);
+    }
+
+    pub fn table_count(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    // Distance to mate for the player to move, None if the position is not in the tables
+    pub fn probe(&self, board: &Board) -> Option<Dtm> {
+        let castling = board.white_castling_k || board.white_castling_q || board.black_castling_k || board.black_castling_q;
+        if castling || Tablebases::piece_count(board) > self.max_pieces.max(2) {
+            return None;
+        }
+
+        // En passant is not in the tables, if it can be taken the moves have to be looked at
+        if let Some(target) = board.en_passant_target {
+            let mut board = board.clone();
+            let moves = MoveGen::legal(&mut board);
+            if moves.iter().any(|m| m.kind == PieceKind::Pawn && m.to == target) {
+                return self.best_of(&board, &moves).map(|(_, dtm)| dtm);
+            }
+        }
+
+        let white = board.material_name(Player::White);
+        let black = board.material_name(Player::Black);
+        if white == "K" && black == "K" {
+            return Some(Dtm::Draw);
+        }
+
+        if let Some(table) = self.tables.get(&format!("{}v{}", white, black)) {
+            return table.probe(board);
+        }
+
+        // The table has the other player as white, so the board is turned around
+        let table = self.tables.get(&format!("{}v{}", black, white))?;
+        let mut flipped = Board::new();
+        for i in 0..N_PIECES {
+            flipped.white[i] = board.black[i].swap_bytes();
+            flipped.black[i] = board.white[i].swap_bytes();
+        }
+        flipped.set_turn(Board::opponent(board.get_turn()));
+        table.probe(&flipped)
+    }
+
+    // The moves that keep the best result, with that result
+    pub fn best_moves(&self, board: &Board) -> Option<(Vec<ChessMove>, Dtm)> {
+        self.probe(board)?;
+
+        let mut board = board.clone();
+        let moves = MoveGen::legal(&mut board);
+        let (best, dtm) = self.best_of(&board, &moves)?;
+
+        let moves = moves
+            .into_iter()
+            .filter(|&m| {
+                let mut child = board.clone();
+                child.apply_move(m);
+                self.probe(&child).is_some_and(|child| child.before_move().rank() == best)
+            })
+            .collect();
+
+        Some((moves, dtm))
+    }
+
+    // The best rank and result of the moves, None if a move leads out of the tables
+    fn best_of(&self, board: &Board, moves: &[ChessMove]) -> Option<(i32, Dtm)> {
+        if moves.is_empty() {
+            let mated = board.is_king_in_check(board.get_turn());
+            let dtm = if mated { Dtm::Loss(0) } else { Dtm::Draw };
+            return Some((dtm.rank(), dtm));
+        }
+
+        let mut best: Option<(i32, Dtm)> = None;
+        for &m in moves {
+            let mut child = board.clone();
+            child.apply_move(m);
+            let dtm = self.probe(&child)?.before_move();
+
+            if best.is_none_or(|(rank, _)| dtm.rank() > rank) {
+                best = Some((dtm.rank(), dtm));
+            }
+        }
+
+        best
+    }
+
+    // Generates the tables, and the smaller ones they lead to, that are not there yet. New
+    // tables are written to `dir`
+    pub fn generate(&mut self, name: &str, dir: &Path) -> Result<(), String> {
+        let Some(material) = Material::parse(name) else {
+            return Err(format!("{} is not a valid table name, it should look like KRvKN", name));
+        };
+        let material = Material::parse(&normalize_name(&material.name)).unwrap_or(material);
+
+        if self.tables.contains_key(&material.name) || material.piece_count() <= 2 {
+            return Ok(());
+        }
+
+        for successor in material.successors() {
+            self.generate(&successor, dir)?;
+        }
+
+        let start = Instant::now();
+        let values = self.build(&material)?;
+        let table = EndgameTable { material, values };
+
+        let decisive = table.values.iter().filter(|&&value| value != 0).count();
+        let longest = table.values.iter().max().copied().unwrap_or(0);
+        println!(
+            "{}: {} positions, {} won or lost, longest mate {} plies, {:.1}s",
+            table.material.name,
+            table.values.len(),
+            decisive,
+            longest.saturating_sub(1),
+            start.elapsed().as_secs_f64()
+        );
+
+        table.write(dir).map_err(|err| format!("could not write {}: {}", table.material.name, err))?;
+        self.insert(table);
+        Ok(())
+    }
+
+    fn build(&self, material: &Material) -> Result<Vec<u8>, String> {
+        let size = material.size();
+        let mut values = vec![0u8; size];
+        let mut done = vec![false; size];
+        // Moves that stay in the table and have not been found to lose yet
+        let mut counts = vec![0u8; size];
+        // Through captures and promotions: whether one wins or draws, and the longest loss
+        let mut winning_exit = vec![false; size];
+        let mut drawing_exit = vec![false; size];
+        let mut losing_exit = vec![0u8; size];
+        // Positions to settle at each ply, some of them settled earlier already
+        let mut plies: Vec<Vec<u32>> = vec![Vec::new(); MAX_PLIES + 1];
+
+        let schedule = |plies: &mut Vec<Vec<u32>>, ply: usize, index: usize| -> Result<(), String> {
+            let bucket = plies.get_mut(ply).ok_or_else(|| format!("{} has mates longer than {} plies", material.name, MAX_PLIES))?;
+            bucket.push(index as u32);
+            Ok(())
+        };
+
+        for index in 0..size {
+            let Some(mut board) = material.decode(index).filter(|board| material.index(board) == Some(index)) else {
+                done[index] = true;
+                continue;
+            };
+
+            let player = board.get_turn();
+            let moves = MoveGen::legal(&mut board);
+            if moves.is_empty() {
+                if board.is_king_in_check(player) {
+                    schedule(&mut plies, 0, index)?;
+                } else {
+                    done[index] = true;
+                }
+                continue;
+            }
+
+            let mut children = Vec::with_capacity(moves.len());
+            let mut fastest_win = None;
+            for m in moves {
+                let exits = m.promotion.is_some() || MoveOrdering::is_capture(&board, m);
+                let mut child = board.clone();
+                child.apply_move(m);
+
+                if !exits {
+                    children.push(material.index(&child).ok_or("move left the table")?);
+                    continue;
+                }
+
+                match self.probe(&child).map(Dtm::before_move) {
+                    Some(Dtm::Win(win)) => fastest_win = Some(fastest_win.map_or(win, |fastest: i32| fastest.min(win))),
+                    Some(Dtm::Loss(loss)) => losing_exit[index] = losing_exit[index].max(loss as u8),
+                    Some(Dtm::Draw) => drawing_exit[index] = true,
+                    None => return Err(format!("{} needs a table that is missing", material.name)),
+                }
+            }
+
+            // Moves to the same position, or to mirrored ones, are only counted once, like
+            // they are only taken back once
+            children.sort_unstable();
+            children.dedup();
+            counts[index] = children.len() as u8;
+
+            if let Some(win) = fastest_win {
+                winning_exit[index] = true;
+                schedule(&mut plies, win as usize, index)?;
+            } else if children.is_empty() && !drawing_exit[index] {
+                schedule(&mut plies, losing_exit[index] as usize, index)?;
+            }
+        }
+
+        for ply in 0..plies.len() {
+            let bucket = std::mem::take(&mut plies[ply]);
+
+            for index in bucket {
+                let index = index as usize;
+                if done[index] {
+                    continue;
+                }
+                done[index] = true;
+                values[index] = ply as u8 + 1;
+
+                let Some(board) = material.decode(index) else {
+                    continue;
+                };
+
+                // The player to move here did not move last, so can not be in check before
+                let mut parents: Vec<usize> = MoveGen::unmoves(&board)
+                    .into_iter()
+                    .filter_map(|m| {
+                        let mut parent = board.clone();
+                        parent.retract_move(m);
+                        if parent.is_king_in_check(board.get_turn()) { None } else { material.index(&parent) }
+                    })
+                    .collect();
+                parents.sort_unstable();
+                parents.dedup();
+
+                for parent in parents {
+                    if done[parent] {
+                        continue;
+                    }
+
+                    if ply % 2 == 0 {
+                        // Lost here, so won for whoever can move here
+                        schedule(&mut plies, ply + 1, parent)?;
+                    } else if counts[parent] > 0 {
+                        counts[parent] -= 1;
+                        if counts[parent] == 0 && !winning_exit[parent] && !drawing_exit[parent] {
+                            schedule(&mut plies, (ply + 1).max(losing_exit[parent] as usize), parent)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(values)
+    }
+}
+
+// The side with more material goes first, as white
+fn normalize(white: &str, black: &str) -> String {
+    let sort = |side: &str| {
+        let mut letters: Vec<char> = side.chars().skip(1).collect();
+        letters.sort_by_key(|&c| PIECE_LETTERS.iter().position(|&(_, letter, _)| letter == c));
+        format!("K{}", letters.into_iter().collect::<String>())
+    };
+    let strength = |side: &str| -> (i32, String) {
+        let value = side.chars().filter_map(|c| PIECE_LETTERS.iter().find(|&&(_, letter, _)| letter == c)).map(|&(_, _, value)| value).sum();
+        (value, side.to_string())
+    };
+
+    let (white, black) = (sort(white), sort(black));
+    if strength(&white) >= strength(&black) {
+        format!("{}v{}", white, black)
+    } else {
+        format!("{}v{}", black, white)
+    }
+}
+
+fn normalize_name(name: &str) -> String {
+    let (white, black) = name.split_once('v').unwrap_or((name, "K"));
+    normalize(white, black)
+}
+
+// One of the board's 8 symmetries: transposed, then mirrored left-right and top-bottom
+fn transform(square: i32, t: usize) -> i32 {
+    let (mut row, mut col) = (square / 8, square % 8);
+    if t & 4 != 0 {
+        std::mem::swap(&mut row, &mut col);
+    }
+    if t & 1 != 0 {
+        col = 7 - col;
+    }
+    if t & 2 != 0 {
+        row = 7 - row;
+    }
+    row * 8 + col
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.apply_fen(fen.to_string());
+        board
+    }
+
+    #[test]
+    fn generate_and_read_back_kqvk() {
+        let dir = std::env::temp_dir().join(format!("chess-dtm-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        EndgameTables::new().generate("KQvK", &dir).unwrap();
+        let tables = EndgameTables::open(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(tables.table_count(), 1);
+        assert_eq!(tables.probe(&board("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1")), Some(Dtm::Win(1)));
+        assert_eq!(tables.probe(&board("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1")), Some(Dtm::Loss(0)));
+        assert_eq!(tables.probe(&board("k7/8/1Q6/8/8/8/8/K7 b - - 0 1")), Some(Dtm::Draw));
+        // The same mate in one with the colours swapped
+        assert_eq!(tables.probe(&board("K7/8/1k6/8/8/8/8/6q1 b - - 0 1")), Some(Dtm::Win(1)));
+
+        let (moves, dtm) = tables.best_moves(&board("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1")).unwrap();
+        assert_eq!(dtm, Dtm::Win(1));
+        assert!(moves.iter().any(|m| m.to_uci() == "g1g8"));
+    }
+}
@@ -0,0 +1,1016 @@
+use crate::piece::{PieceKind, Player};
+use crate::board::Board;
+use crate::movegen::{ChessMove, MoveGen};
+use crate::eval::Eval;
+use crate::pawns::PawnTable;
+use crate::ordering::MoveOrdering;
+use crate::see::see;
+use crate::tt::{Bound, TranspositionTable, MATE, MATE_BOUND, MAX_PLY};
+use crate::time::TimeManager;
+use crate::syzygy::{Tablebases, TB_WIN};
+use crate::retrograde::EndgameTables;
+use crate::nnue::{Network, Nnue};
+use crate::skill::Skill;
+use crate::random::Random;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const INFINITY: i32 = MATE + 1;
+
+// Reverse futility pruning: a node this far above beta is assumed to stay there
+const REVERSE_FUTILITY_DEPTH: i32 = 6;
+const REVERSE_FUTILITY_MARGIN: i32 = 80; // Per ply of depth
+
+// Futility pruning: quiet moves are skipped when even this much would not reach alpha
+const FUTILITY_MARGINS: [i32; 4] = [0, 150, 300, 500];
+
+// Null move pruning
+const NULL_MOVE_DEPTH: i32 = 3;
+// Deep enough for a verification search to be worth it, in case of zugzwang
+const NULL_MOVE_VERIFY_DEPTH: i32 = 10;
+
+// Aspiration windows, in centipawns around the previous iteration's score
+const ASPIRATION_DEPTH: i32 = 4;
+const ASPIRATION_WINDOW: i32 = 25;
+
+// Late move reductions
+const LMR_DEPTH: i32 = 3;
+const LMR_MOVES: i32 = 3; // Moves searched at full depth before reducing
+
+#[derive(Copy, Clone, Debug)]
+pub struct SearchConfig {
+    // Only affects the main search, quiescence needs its captures ordered to stay small
+    pub move_ordering: bool,
+    pub null_move: bool,
+    pub late_move_reductions: bool,
+    pub futility: bool,
+    pub reverse_futility: bool,
+    pub check_extensions: bool,
+    pub pvs: bool,
+    pub aspiration: bool,
+    // Tablebase wins that the fifty move rule would draw count as draws
+    pub syzygy_50_move_rule: bool,
+}
+
+// Times are in milliseconds. No limits at all searches until stopped
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SearchLimits {
+    pub depth: Option<i32>,
+    pub nodes: Option<u64>,
+    pub move_time: Option<u64>,
+    // Left on our clock
+    pub time: Option<u64>,
+    pub increment: u64,
+    pub moves_to_go: Option<u64>,
+    // Goes on until stopped, even past a forced mate
+    pub infinite: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: Option<ChessMove>,
+    pub score: i32,
+    pub depth: i32,
+    pub nodes: u64,
+    pub pv: Vec<ChessMove>,
+    // The best few moves with MultiPV, best first. The first is the same as the result itself
+    pub lines: Vec<PvLine>,
+    pub stats: SearchStats,
+}
+
+#[derive(Clone, Debug)]
+pub struct PvLine {
+    pub score: i32,
+    pub depth: i32,
+    pub pv: Vec<ChessMove>,
+}
+
+// How often the search had to go over the same moves again
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SearchStats {
+    // Moves that beat alpha in their zero window search and had to be searched with the full one
+    pub pvs_researches: u64,
+    pub aspiration_fail_lows: u64,
+    pub aspiration_fail_highs: u64,
+    // Positions found in the tablebases
+    pub tb_hits: u64,
+}
+
+// A score the way it is shown to people
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Score {
+    Centipawns(i32),
+    // Moves until mate, negative when the player to move is the one getting mated
+    Mate(i32),
+}
+
+// How a running search is getting on, for the observer
+#[derive(Clone, Debug)]
+pub struct SearchInfo {
+    pub depth: i32,
+    // The most plies from the root any line reached, quiescence included
+    pub seldepth: i32,
+    // Which of the MultiPV lines this is, from 1
+    pub multi_pv: usize,
+    pub score: Score,
+    // Of the main thread, the helpers' are only added to the result at the end
+    pub nodes: u64,
+    pub nps: u64,
+    // Permille of the transposition table used by this search
+    pub hashfull: usize,
+    pub tb_hits: u64,
+    pub time: Duration,
+    pub pv: Vec<ChessMove>,
+}
+
+// Told about the search's progress, on the thread running it, so it should be quick about it
+pub trait SearchObserver: Send {
+    // Every line once an iteration is done, and whatever there is when the search is stopped
+    fn iteration(&mut self, info: &SearchInfo);
+
+    // A new best move in the middle of an iteration, with the depth it is still searching
+    fn best_move(&mut self, _info: &SearchInfo) {}
+}
+
+// Stops a running search from any thread, which then returns its best move so far. It is not
+// cleared by the search, that is up to whoever stops it
+#[derive(Clone, Debug, Default)]
+pub struct StopHandle {
+    stopped: Arc<AtomicBool>,
+}
+
+/*
+ * With more than one thread the search is Lazy SMP: helper threads search the same position
+ * at the same time, sharing only the transposition table. They mostly fill it with results
+ * the main thread can use. Half of them start a ply deeper, so that the threads do not all
+ * search the same depths in step. The main thread's result is the one returned.
+ */
+pub struct Search {
+    tt: Arc<TranspositionTable>,
+    pub config: SearchConfig,
+    helpers: Vec<Search>,
+    pawn_table: PawnTable,
+    // Evaluates instead of Eval when a network is loaded
+    nnue: Option<Nnue>,
+    ordering: MoveOrdering,
+    limits: SearchLimits,
+    time: TimeManager,
+    stop: StopHandle,
+    // Set while thinking on the opponent's time, see ponder_flag()
+    ponder: Arc<AtomicBool>,
+    nodes: u64,
+    stats: SearchStats,
+    stopped: bool,
+    // Hashes of the positions played before the root and on the current path, for repetitions
+    history: Vec<u64>,
+    pv: Vec<Vec<ChessMove>>,
+    tablebases: Option<Arc<Tablebases>>,
+    endgames: Option<Arc<EndgameTables>>,
+    // Only these are searched at the root, all moves when empty
+    root_moves: Vec<ChessMove>,
+    // Number of lines to search, and the root moves that already have one in this iteration
+    multi_pv: usize,
+    excluded: Vec<ChessMove>,
+    // Positions with up to this many pieces are looked up in the tablebases, 0 for none
+    probe_limit: usize,
+    // Below full strength every root move gets an exact score, and the move is picked among them
+    skill: Skill,
+    random: Random,
+    root_scores: Vec<(ChessMove, i32)>,
+    // Only the main thread has one
+    observer: Option<Box<dyn SearchObserver>>,
+    seldepth: i32,
+    // The last iteration's best move, to tell when another one takes over
+    root_best: Option<ChessMove>,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            move_ordering: true,
+            null_move: true,
+            late_move_reductions: true,
+            futility: true,
+            reverse_futility: true,
+            check_extensions: true,
+            pvs: true,
+            aspiration: true,
+            syzygy_50_move_rule: true,
+        }
+    }
+}
+
+impl Score {
+    // From a score as the search has it, where mates count down from MATE
+    pub fn from_search(score: i32) -> Self {
+        if score >= MATE_BOUND {
+            Score::Mate((MATE - score + 1) / 2)
+        } else if score <= -MATE_BOUND {
+            Score::Mate(-(MATE + score) / 2)
+        } else {
+            Score::Centipawns(score)
+        }
+    }
+}
+
+impl StopHandle {
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    // Lets the next search run
+    pub fn clear(&self) {
+        self.stopped.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+}
+
+impl Search {
+    pub fn new(hash_mb: usize) -> Self {
+        Self::with_table(Arc::new(TranspositionTable::new(hash_mb)))
+    }
+
+    fn with_table(tt: Arc<TranspositionTable>) -> Self {
+        Self {
+            tt,
+            config: SearchConfig::default(),
+            helpers: Vec::new(),
+            pawn_table: PawnTable::new(),
+            nnue: None,
+            ordering: MoveOrdering::new(),
+            limits: SearchLimits::default(),
+            time: TimeManager::new(&SearchLimits::default()),
+            stop: StopHandle::default(),
+            ponder: Arc::new(AtomicBool::new(false)),
+            nodes: 0,
+            stats: SearchStats::default(),
+            stopped: false,
+            history: Vec::new(),
+            pv: vec![Vec::new(); MAX_PLY as usize + 1],
+            tablebases: None,
+            endgames: None,
+            root_moves: Vec::new(),
+            multi_pv: 1,
+            excluded: Vec::new(),
+            probe_limit: 0,
+            skill: Skill::default(),
+            random: Random::new(0),
+            root_scores: Vec::new(),
+            observer: None,
+            seldepth: 0,
+            root_best: None,
+        }
+    }
+
+    // Forget everything learned in the previous game
+    pub fn new_game(&mut self) {
+        self.tt.clear();
+        self.pawn_table.clear();
+        self.ordering.clear();
+        for helper in &mut self.helpers {
+            helper.pawn_table.clear();
+            helper.ordering.clear();
+        }
+    }
+
+    // Throws away the contents of the transposition table
+    pub fn resize_tt(&mut self, megabytes: usize) {
+        self.tt = Arc::new(TranspositionTable::new(megabytes));
+        for helper in &mut self.helpers {
+            helper.tt = self.tt.clone();
+        }
+    }
+
+    // Total number of threads, including the one calling think()
+    pub fn set_threads(&mut self, threads: usize) {
+        self.helpers = (1..threads.max(1)).map(|_| Self::with_table(self.tt.clone())).collect();
+    }
+
+    pub fn set_tablebases(&mut self, tablebases: Option<Arc<Tablebases>>) {
+        self.tablebases = tablebases;
+    }
+
+    // NNUE evaluation, the hand-crafted one without a network
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        let same = match (&self.nnue, &network) {
+            (Some(nnue), Some(network)) => Arc::ptr_eq(nnue.network(), network),
+            (None, None) => true,
+            _ => false,
+        };
+        if !same {
+            self.nnue = network.map(Nnue::new);
+        }
+    }
+
+    // Our own endgame tables, see retrograde.rs
+    pub fn set_endgames(&mut self, endgames: Option<Arc<EndgameTables>>) {
+        self.endgames = endgames;
+    }
+
+    // The best `lines` moves are each searched to the same depth, for analysis. Only the main
+    // thread does this, the helpers just fill the transposition table as usual
+    pub fn set_multi_pv(&mut self, lines: usize) {
+        self.multi_pv = lines.max(1);
+    }
+
+    // Weaker play, see skill.rs. The moves picked are different every time
+    pub fn set_skill(&mut self, skill: Skill) {
+        self.skill = skill;
+        self.random = Random::new(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64));
+    }
+
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    // Told about every iteration from now on, see SearchObserver
+    pub fn set_observer(&mut self, observer: Option<Box<dyn SearchObserver>>) {
+        self.observer = observer;
+    }
+
+    // While the flag is set the clock is ignored and the search goes on until stopped. Clearing
+    // it, when the opponent plays the move that was expected, brings the clock back in, counted
+    // from the start of the search, so the time spent pondering is not spent again
+    pub fn ponder_flag(&self) -> Arc<AtomicBool> {
+        self.ponder.clone()
+    }
+
+    // `history` holds the hashes of the positions played so far in the game
+    pub fn think(&mut self, board: &Board, history: &[u64], limits: SearchLimits) -> SearchResult {
+        self.tt.new_search();
+        let limits = self.skill.limit(limits);
+
+        // In a tablebase ending only the moves that keep the best result are searched
+        self.root_moves.clear();
+        self.probe_limit = 0;
+        let mut tb_score = None;
+        if let Some(tablebases) = &self.tablebases {
+            self.probe_limit = tablebases.max_pieces();
+            if let Some(root) = tablebases.rank_root_moves(board, history, self.config.syzygy_50_move_rule) {
+                // Moves ranked by DTZ already make progress, probing further would not help
+                if root.dtz || root.score <= 0 {
+                    self.probe_limit = 0;
+                }
+                tb_score = Some(root.score);
+                self.root_moves = root.moves;
+            }
+        }
+
+        // Our own tables know the distance to mate, so they pick the moves that mate fastest
+        let mut dtm_score = None;
+        if let Some((moves, dtm)) = self.endgames.as_ref().and_then(|endgames| endgames.best_moves(board))
+            && !moves.is_empty()
+        {
+            dtm_score = Some(dtm.score(0));
+            self.root_moves = moves;
+        }
+
+        let mut result = if self.helpers.is_empty() {
+            self.iterate(board, history, limits, 1)
+        } else {
+            self.think_smp(board, history, limits)
+        };
+
+        // A mate the search found says more than the tablebase score
+        if let Some(score) = tb_score && result.score.abs() < MATE_BOUND {
+            result.score = score;
+        }
+        if let Some(score) = dtm_score {
+            result.score = score;
+        }
+        if let Some(line) = result.lines.first_mut() {
+            line.score = result.score;
+        }
+
+        if !self.skill.is_full_strength() && !self.root_scores.is_empty() {
+            let (m, score) = self.skill.pick(&self.root_scores, &mut self.random);
+            if result.best_move != Some(m) {
+                result.best_move = Some(m);
+                result.score = score;
+                result.pv = vec![m];
+            }
+        }
+
+        result
+    }
+
+    fn think_smp(&mut self, board: &Board, history: &[u64], limits: SearchLimits) -> SearchResult {
+        // The helpers only know to stop when the main thread tells them
+        let helpers_stop = StopHandle::default();
+        let helper_limits = SearchLimits { depth: limits.depth, ..Default::default() };
+
+        let mut helpers = std::mem::take(&mut self.helpers);
+        for helper in &mut helpers {
+            helper.config = self.config;
+            helper.stop = helpers_stop.clone();
+            helper.tablebases = self.tablebases.clone();
+            helper.endgames = self.endgames.clone();
+            helper.set_network(self.nnue.as_ref().map(|nnue| nnue.network().clone()));
+            helper.root_moves = self.root_moves.clone();
+            helper.probe_limit = self.probe_limit;
+        }
+
+        let mut result = thread::scope(|scope| {
+            for (i, helper) in helpers.iter_mut().enumerate() {
+                let start_depth = 1 + (i + 1) as i32 % 2;
+                scope.spawn(move || helper.iterate(board, history, helper_limits, start_depth));
+            }
+
+            let result = self.iterate(board, history, limits, 1);
+            helpers_stop.stop();
+            result
+        });
+
+        result.nodes += helpers.iter().map(|helper| helper.nodes).sum::<u64>();
+        result.stats.tb_hits += helpers.iter().map(|helper| helper.stats.tb_hits).sum::<u64>();
+        self.helpers = helpers;
+        result
+    }
+
+    // Iterative deepening
+    fn iterate(&mut self, board: &Board, history: &[u64], limits: SearchLimits, start_depth: i32) -> SearchResult {
+        self.limits = limits;
+        self.time = TimeManager::new(&limits);
+        self.nodes = 0;
+        self.stats = SearchStats::default();
+        self.stopped = false;
+        self.history = history.to_vec();
+        self.ordering.new_search();
+        self.root_best = None;
+
+        let mut board = board.clone();
+        let max_depth = limits.depth.unwrap_or(MAX_PLY - 1).min(MAX_PLY - 1);
+        let mut result = SearchResult { best_move: None, score: 0, depth: 0, nodes: 0, pv: Vec::new(), lines: Vec::new(), stats: SearchStats::default() };
+        let mut root_scores = Vec::new();
+        let root_count = if self.root_moves.is_empty() { MoveGen::legal(&mut board).len() } else { self.root_moves.len() };
+        let line_count = self.multi_pv.min(root_count).max(1);
+
+        for depth in start_depth.min(max_depth)..=max_depth {
+            // Each line is the best move left once the ones before it are taken out of the root
+            self.excluded.clear();
+            self.seldepth = 0;
+            let mut lines = Vec::new();
+            for index in 0..line_count {
+                // Every root move is searched with the full window when they all need a score
+                let aspiration = self.config.aspiration && self.skill.is_full_strength();
+                let score = match result.lines.get(index) {
+                    Some(line) if aspiration && depth >= ASPIRATION_DEPTH && line.score.abs() < MATE_BOUND => {
+                        self.aspiration(&mut board, depth, line.score)
+                    },
+                    _ => self.negamax(&mut board, depth, -INFINITY, INFINITY, 0, true),
+                };
+
+                // An unfinished search can not be trusted, unless we have nothing else
+                if self.stopped && (index > 0 || result.best_move.is_some()) {
+                    break;
+                }
+
+                if index == 0 {
+                    root_scores = std::mem::take(&mut self.root_scores);
+                }
+                let pv = self.pv[0].clone();
+                self.excluded.extend(pv.first());
+                lines.push(PvLine { score, depth, pv });
+
+                if self.stopped {
+                    break;
+                }
+            }
+
+            // Stopped before the first line, the previous iteration's lines are all there is
+            let Some(best) = lines.first() else {
+                self.report(&result.lines);
+                break;
+            };
+            result.score = best.score;
+            result.depth = depth;
+            result.pv = best.pv.clone();
+            result.best_move = result.pv.first().copied();
+
+            // The lines this iteration did not get to are kept from the one before
+            for line in &result.lines {
+                if lines.len() < line_count && line.pv.first().is_some_and(|m| !self.excluded.contains(m)) {
+                    lines.push(line.clone());
+                }
+            }
+            result.lines = lines;
+            let score = result.score;
+            self.root_best = result.best_move;
+
+            self.report(&result.lines);
+
+            if self.stopped {
+                break;
+            }
+
+            // No point in searching deeper once a forced mate has been found
+            if !limits.infinite && score.abs() >= MATE_BOUND && MATE - score.abs() <= depth {
+                break;
+            }
+
+            // The next iteration would take several times as long as this one
+            self.time.update(result.best_move.map_or(0, |m| m.pack()), score);
+            if !self.ponder.load(Ordering::Relaxed) && self.time.soft_limit_reached() {
+                break;
+            }
+        }
+
+        // Still return a move if the search was stopped before finishing the first iteration
+        if result.best_move.is_none() {
+            result.best_move = self.root_moves.first().copied().or_else(|| MoveGen::legal(&mut board).first().copied());
+        }
+
+        self.root_scores = root_scores;
+        result.nodes = self.nodes;
+        result.stats = self.stats;
+        result
+    }
+
+    // The score rarely moves much between iterations, and a narrow window cuts off more. If the
+    // score falls outside of it the window is widened on that side and the depth searched again
+    fn aspiration(&mut self, board: &mut Board, depth: i32, previous: i32) -> i32 {
+        let mut delta = ASPIRATION_WINDOW;
+        let mut alpha = previous - delta;
+        let mut beta = previous + delta;
+
+        loop {
+            let score = self.negamax(board, depth, alpha, beta, 0, true);
+            if self.stopped {
+                return score;
+            }
+
+            if score <= alpha {
+                self.stats.aspiration_fail_lows += 1;
+                alpha = (score - delta).max(-INFINITY);
+            } else if score >= beta {
+                self.stats.aspiration_fail_highs += 1;
+                beta = (score + delta).min(INFINITY);
+            } else {
+                return score;
+            }
+
+            delta *= 2;
+        }
+    }
+
+    fn info(&self, depth: i32, multi_pv: usize, score: i32, pv: Vec<ChessMove>) -> SearchInfo {
+        let time = self.time.elapsed();
+        SearchInfo {
+            depth,
+            seldepth: self.seldepth,
+            multi_pv,
+            score: Score::from_search(score),
+            nodes: self.nodes,
+            nps: (self.nodes as f64 / time.as_secs_f64().max(0.001)) as u64,
+            hashfull: self.tt.hashfull(),
+            tb_hits: self.stats.tb_hits,
+            time,
+            pv,
+        }
+    }
+
+    // Tells the observer about each of the lines, best first
+    fn report(&mut self, lines: &[PvLine]) {
+        if let Some(mut observer) = self.observer.take() {
+            for (i, line) in lines.iter().enumerate() {
+                observer.iteration(&self.info(line.depth, i + 1, line.score, line.pv.clone()));
+            }
+            self.observer = Some(observer);
+        }
+    }
+
+    fn check_limits(&mut self) {
+        if let Some(nodes) = self.limits.nodes && self.nodes >= nodes {
+            self.stopped = true;
+        }
+
+        let pondering = self.ponder.load(Ordering::Relaxed);
+        if self.stop.is_stopped() || (!pondering && self.time.hard_limit_reached()) {
+            self.stopped = true;
+        }
+    }
+
+    fn is_draw(&self, board: &Board) -> bool {
+        if board.halfmove_clock >= 100 {
+            return true;
+        }
+
+        // Only positions since the last capture or pawn move can repeat, and only with the same
+        // player to move, so every other entry is skipped. The last entry is the current position
+        self.history
+            .iter()
+            .rev()
+            .skip(2)
+            .step_by(2)
+            .take(board.halfmove_clock as usize / 2)
+            .any(|&hash| hash == board.hash)
+    }
+
+    // Null moves are not allowed twice in a row, `allow_null` is false right after one
+    fn negamax(&mut self, board: &mut Board, mut depth: i32, mut alpha: i32, beta: i32, ply: i32, allow_null: bool) -> i32 {
+        self.pv[ply as usize].clear();
+        self.seldepth = self.seldepth.max(ply);
+        let score_root_moves = ply == 0 && !self.skill.is_full_strength() && self.excluded.is_empty();
+        if score_root_moves {
+            self.root_scores.clear();
+        }
+
+        let player = board.get_turn();
+        let in_check = board.is_king_in_check(player);
+
+        // Do not drop into quiescence while in check, it would miss the evasions
+        if in_check && self.config.check_extensions {
+            depth += 1;
+        }
+
+        if depth <= 0 {
+            return self.quiescence(board, alpha, beta, ply);
+        }
+
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(1024) {
+            self.check_limits();
+        }
+        if self.stopped {
+            return 0;
+        }
+
+        if ply > 0 && self.is_draw(board) {
+            return 0;
+        }
+
+        if ply >= MAX_PLY - 1 {
+            return self.evaluate(board, ply);
+        }
+
+        let mut tt_move = 0;
+        if let Some(entry) = self.tt.probe(board.hash, ply) {
+            if ply > 0 && entry.cuts(depth, alpha, beta) {
+                return entry.score;
+            }
+            tt_move = entry.best_move;
+        }
+
+        // Our own tables know the exact distance to mate, though nothing of the fifty move rule
+        if ply > 0
+            && let Some(endgames) = &self.endgames
+            && Tablebases::piece_count(board) <= endgames.max_pieces()
+            && let Some(dtm) = endgames.probe(board)
+        {
+            self.stats.tb_hits += 1;
+            let score = dtm.score(ply);
+            self.tt.store(board.hash, (depth + 6).min(MAX_PLY - 1), Bound::Exact, score, None, ply);
+            return score;
+        }
+
+        // The tablebases know the result for certain, but not how far along the fifty move
+        // rule is, so they are only asked right after a capture or pawn move
+        if ply > 0
+            && board.halfmove_clock == 0
+            && Tablebases::piece_count(board) <= self.probe_limit
+            && let Some(wdl) = self.tablebases.as_ref().and_then(|tablebases| tablebases.probe_wdl(board))
+        {
+            self.stats.tb_hits += 1;
+
+            let draw_score = self.config.syzygy_50_move_rule as i32;
+            let wdl = wdl.value();
+            let (score, bound) = if wdl < -draw_score {
+                (-TB_WIN + ply, Bound::Upper)
+            } else if wdl > draw_score {
+                (TB_WIN - ply, Bound::Lower)
+            } else {
+                (2 * wdl * draw_score, Bound::Exact)
+            };
+
+            if bound == Bound::Exact
+                || (bound == Bound::Lower && score >= beta)
+                || (bound == Bound::Upper && score <= alpha)
+            {
+                self.tt.store(board.hash, (depth + 6).min(MAX_PLY - 1), bound, score, None, ply);
+                return score;
+            }
+        }
+
+        // Searches with a zero window only need to know whether a move beats beta, the
+        // principal variation is never going to come from them, so they can be pruned harder
+        let pv_node = beta - alpha > 1;
+        let static_eval = if in_check { -INFINITY } else { self.evaluate(board, ply) };
+
+        if self.config.reverse_futility
+            && !pv_node
+            && !in_check
+            && depth <= REVERSE_FUTILITY_DEPTH
+            && beta.abs() < MATE_BOUND
+            && static_eval - REVERSE_FUTILITY_MARGIN * depth >= beta
+        {
+            return static_eval;
+        }
+
+        // Giving the opponent a free move and still failing high means the real moves would too.
+        // Not true in zugzwang, which mostly happens with only pawns left
+        if self.config.null_move
+            && allow_null
+            && !pv_node
+            && !in_check
+            && depth >= NULL_MOVE_DEPTH
+            && static_eval >= beta
+            && Self::has_pieces(board, player)
+        {
+            let reduction = 3 + depth / 6;
+
+            let mut child = board.clone();
+            child.apply_null_move();
+            self.history.push(child.hash);
+            let score = -self.negamax(&mut child, depth - 1 - reduction, -beta, -beta + 1, ply + 1, false);
+            self.history.pop();
+
+            if self.stopped {
+                return 0;
+            }
+
+            if score >= beta {
+                // Mates found after passing are not real
+                let score = if score >= MATE_BOUND { beta } else { score };
+
+                if depth < NULL_MOVE_VERIFY_DEPTH {
+                    return score;
+                }
+
+                // Deep in the tree a wrong cutoff is expensive, so check with a normal
+                // reduced search that can not pass again
+                let verified = self.negamax(board, depth - reduction, beta - 1, beta, ply, false);
+                if self.stopped {
+                    return 0;
+                }
+                if verified >= beta {
+                    return score;
+                }
+            }
+        }
+
+        // Quiet moves at the frontier that can not bring the score back up to alpha
+        let futile = self.config.futility
+            && !pv_node
+            && !in_check
+            && depth < FUTILITY_MARGINS.len() as i32
+            && alpha.abs() < MATE_BOUND
+            && static_eval + FUTILITY_MARGINS[depth as usize] <= alpha;
+
+        let mut moves = MoveGen::all(board, player);
+        if self.config.move_ordering {
+            self.ordering.order(board, &mut moves, tt_move, ply);
+        }
+
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        let mut legal = 0;
+        let mut quiets_tried: Vec<ChessMove> = Vec::new();
+
+        for m in moves {
+            if ply == 0 && ((!self.root_moves.is_empty() && !self.root_moves.contains(&m)) || self.excluded.contains(&m)) {
+                continue;
+            }
+
+            let mut child = board.clone();
+            child.apply_move(m);
+            if child.is_king_in_check(player) {
+                continue;
+            }
+            legal += 1;
+
+            let quiet = MoveOrdering::is_quiet(board, m);
+            let gives_check = child.is_king_in_check(Board::opponent(player));
+
+            // The first legal move is always searched, so that there is a score to return. A
+            // skipped move was never tried, so it is not blamed in the history either
+            if futile && quiet && !gives_check && legal > 1 {
+                continue;
+            }
+
+            self.history.push(child.hash);
+
+            // Late quiet moves are unlikely to be best, so they are searched with less depth
+            // first, and only searched properly if they turn out to beat alpha
+            let mut reduction = 0;
+            if self.config.late_move_reductions
+                && depth >= LMR_DEPTH
+                && legal > LMR_MOVES
+                && quiet
+                && !in_check
+                && !gives_check
+                && !self.ordering.is_killer(m, ply)
+            {
+                reduction = Self::reduction(depth, legal);
+                if pv_node {
+                    reduction -= 1;
+                }
+                reduction = reduction.clamp(0, depth - 2);
+            }
+
+            let score = if score_root_moves {
+                -self.negamax(&mut child, depth - 1, -INFINITY, INFINITY, ply + 1, true)
+            } else if legal == 1 || !self.config.pvs {
+                // Full window, reduced moves that beat alpha are searched again at full depth
+                let mut score = -INFINITY;
+                if reduction > 0 {
+                    score = -self.negamax(&mut child, depth - 1 - reduction, -alpha - 1, -alpha, ply + 1, true);
+                }
+                if reduction == 0 || (score > alpha && !self.stopped) {
+                    score = -self.negamax(&mut child, depth - 1, -beta, -alpha, ply + 1, true);
+                }
+                score
+            } else {
+                // Principal variation search: the first move is assumed to be the best, the
+                // others only have to be proven worse, which a zero window does cheaply
+                let mut score = -self.negamax(&mut child, depth - 1 - reduction, -alpha - 1, -alpha, ply + 1, true);
+                if score > alpha && reduction > 0 && !self.stopped {
+                    score = -self.negamax(&mut child, depth - 1, -alpha - 1, -alpha, ply + 1, true);
+                }
+                if score > alpha && score < beta && !self.stopped {
+                    self.stats.pvs_researches += 1;
+                    score = -self.negamax(&mut child, depth - 1, -beta, -alpha, ply + 1, true);
+                }
+                score
+            };
+
+            self.history.pop();
+
+            if self.stopped {
+                return 0;
+            }
+
+            if score_root_moves {
+                self.root_scores.push((m, score));
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(m);
+
+                if score > alpha {
+                    alpha = score;
+
+                    let (head, tail) = self.pv.split_at_mut(ply as usize + 1);
+                    head[ply as usize].clear();
+                    head[ply as usize].push(m);
+                    head[ply as usize].extend_from_slice(&tail[0]);
+
+                    // Only an exact score is worth telling about
+                    if ply == 0 && score < beta && self.excluded.is_empty() && self.root_best.is_some_and(|best| best != m) {
+                        self.root_best = Some(m);
+                        if let Some(mut observer) = self.observer.take() {
+                            observer.best_move(&self.info(depth, 1, score, self.pv[0].clone()));
+                            self.observer = Some(observer);
+                        }
+                    }
+                }
+
+                if score >= beta {
+                    if quiet {
+                        self.ordering.update(m, &quiets_tried, depth, ply);
+                    }
+                    break;
+                }
+            }
+
+            if quiet {
+                quiets_tried.push(m);
+            }
+        }
+
+        if legal == 0 {
+            // Checkmate or stalemate. Mates closer to the root are better
+            return if in_check { -MATE + ply } else { 0 };
+        }
+
+        // Without the excluded moves the root's score is not the position's
+        if ply == 0 && !self.excluded.is_empty() {
+            return best_score;
+        }
+
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(board.hash, depth, bound, best_score, best_move, ply);
+
+        best_score
+    }
+
+    // Score from the point of view of the player to move
+    fn evaluate(&mut self, board: &Board, ply: i32) -> i32 {
+        match &mut self.nnue {
+            Some(nnue) => nnue.evaluate(board, ply as usize),
+            None => Eval::evaluate(board, &mut self.pawn_table),
+        }
+    }
+
+    // Grows with both the depth and how late the move comes in the ordering
+    fn reduction(depth: i32, move_number: i32) -> i32 {
+        (0.75 + (depth as f64).ln() * (move_number as f64).ln() / 2.25) as i32
+    }
+
+    // Anything besides pawns and the king, for the zugzwang check of null move pruning
+    fn has_pieces(board: &Board, player: Player) -> bool {
+        let pieces = match player {
+            Player::White => &board.white,
+            Player::Black => &board.black,
+        };
+
+        [PieceKind::Knight, PieceKind::Bishop, PieceKind::Rook, PieceKind::Queen]
+            .iter()
+            .any(|&kind| pieces[kind as usize] != 0)
+    }
+
+    // Only looks at captures and promotions, so that the evaluation is not taken in the middle
+    // of an exchange
+    fn quiescence(&mut self, board: &mut Board, mut alpha: i32, beta: i32, ply: i32) -> i32 {
+        self.seldepth = self.seldepth.max(ply);
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(1024) {
+            self.check_limits();
+        }
+        if self.stopped {
+            return 0;
+        }
+
+        let stand_pat = self.evaluate(board, ply);
+        if ply >= MAX_PLY - 1 || stand_pat >= beta {
+            return stand_pat;
+        }
+        if stand_pat > alpha {
+            alpha = stand_pat;
+        }
+
+        let player = board.get_turn();
+        let mut moves: Vec<ChessMove> = MoveGen::all(board, player)
+            .into_iter()
+            .filter(|&m| !MoveOrdering::is_quiet(board, m))
+            .collect();
+        self.ordering.order(board, &mut moves, 0, ply);
+
+        for m in moves {
+            // Losing captures are not going to raise alpha
+            if m.promotion.is_none() && see(board, m) < 0 {
+                continue;
+            }
+
+            let mut child = board.clone();
+            child.apply_move(m);
+            if child.is_king_in_check(player) {
+                continue;
+            }
+
+            let score = -self.quiescence(&mut child, -beta, -alpha, ply + 1);
+            if self.stopped {
+                return 0;
+            }
+
+            if score >= beta {
+                return score;
+            }
+            if score > alpha {
+                alpha = score;
+            }
+        }
+
+        alpha
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mate_scores_in_moves() {
+        assert_eq!(Score::from_search(MATE - 1), Score::Mate(1));
+        assert_eq!(Score::from_search(MATE - 3), Score::Mate(2));
+        assert_eq!(Score::from_search(-MATE), Score::Mate(0));
+        assert_eq!(Score::from_search(-MATE + 2), Score::Mate(-1));
+        assert_eq!(Score::from_search(-MATE + 4), Score::Mate(-2));
+        assert_eq!(Score::from_search(MATE_BOUND - 1), Score::Centipawns(MATE_BOUND - 1));
+        assert_eq!(Score::from_search(-35), Score::Centipawns(-35));
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        let mut board = Board::new();
+        board.apply_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1".to_string());
+
+        let mut search = Search::new(1);
+        let result = search.think(&board, &[board.hash], SearchLimits { depth: Some(4), ..Default::default() });
+        assert_eq!(result.best_move.map(|m| m.to_uci()).as_deref(), Some("a1a8"));
+        assert_eq!(Score::from_search(result.score), Score::Mate(1));
+    }
+}
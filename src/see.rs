@@ -0,0 +1,183 @@
+use crate::piece::{PieceKind, Player, N_PIECES};
+use crate::board::Board;
+use crate::movegen::{ChessMove, MoveGen};
+use crate::ordering::MoveOrdering;
+
+/*
+ * ----- STATIC EXCHANGE EVALUATION -----
+ * Plays out all captures on the target square of a move, each side always recapturing with
+ * its least valuable piece, and either side allowed to stop when continuing would lose
+ * material. Pieces are taken off the occupancy as they capture, so sliders lined up behind
+ * them (x-rays) join in. Pins and checks are ignored.
+ */
+
+pub const SEE_VALUES: [i32; N_PIECES] = [100, 320, 330, 500, 900, 20000];
+
+fn boards(board: &Board, player: Player) -> &[u64; N_PIECES] {
+    match player {
+        Player::White => &board.white,
+        Player::Black => &board.black,
+    }
+}
+
+// Cheapest of the player's pieces among the attackers
+fn least_valuable(board: &Board, attackers: u64, player: Player) -> Option<(PieceKind, u64)> {
+    for &kind in PieceKind::iterator() {
+        let pieces = boards(board, player)[kind as usize] & attackers;
+        if pieces != 0 {
+            return Some((kind, pieces & pieces.wrapping_neg()));
+        }
+    }
+
+    None
+}
+
+// Material the player making the move wins (or loses if negative) in the exchange
+pub fn see(board: &Board, m: ChessMove) -> i32 {
+    let mut gain = [0; 32];
+    let mut occupied = !board.get_empty();
+
+    gain[0] = match MoveOrdering::victim(board, m) {
+        Some(kind) => SEE_VALUES[kind as usize],
+        None => 0
+    };
+
+    // The pawn taken en passant is not on the target square
+    if m.kind == PieceKind::Pawn && board.at(Board::index_to_row_col(m.to)).is_none() && m.from % 8 != m.to % 8 {
+        occupied &= !(1_u64 << (m.to + if m.player == Player::White { 8 } else { -8 }));
+    }
+
+    let mut on_square = m.kind;
+    if let Some(kind) = m.promotion {
+        gain[0] += SEE_VALUES[kind as usize] - SEE_VALUES[PieceKind::Pawn as usize];
+        on_square = kind;
+    }
+
+    occupied &= !(1_u64 << m.from);
+    let mut side = Board::opponent(m.player);
+    let mut depth = 0;
+
+    while depth < gain.len() - 1 {
+        let attackers = MoveGen::attackers_to(board, m.to, occupied) & occupied;
+        let Some((kind, bit)) = least_valuable(board, attackers, side) else {
+            break;
+        };
+
+        // The king can only take if nothing can take it back
+        if kind == PieceKind::King && attackers & !bit & board.get_occupied(Board::opponent(side)) != 0 {
+            break;
+        }
+
+        // Neither side can gain from going on
+        let next = SEE_VALUES[on_square as usize] - gain[depth];
+        if (-gain[depth]).max(next) < 0 {
+            break;
+        }
+
+        depth += 1;
+        gain[depth] = next;
+
+        occupied &= !bit;
+        on_square = kind;
+        side = Board::opponent(side);
+    }
+
+    // Each side gets to decide whether to take, going backwards from the end of the sequence
+    while depth > 0 {
+        gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+        depth -= 1;
+    }
+
+    gain[0]
+}
+
+// How much material the owner of the piece on the square loses if the opponent starts an
+// exchange there with their cheapest attacker, 0 if it is safe
+pub fn hanging(board: &Board, index: i32) -> i32 {
+    let Some(piece) = board.at(Board::index_to_row_col(index)) else {
+        return 0;
+    };
+
+    let enemy = Board::opponent(piece.player);
+    let attackers = MoveGen::attackers_to(board, index, !board.get_empty());
+    let Some((kind, bit)) = least_valuable(board, attackers, enemy) else {
+        return 0;
+    };
+
+    let capture = ChessMove::new(Board::u64_to_index(bit), index, kind, enemy);
+    see(board, capture).max(0)
+}
+
+// Every piece of the player that loses material when attacked, with how much it loses
+pub fn hanging_pieces(board: &Board, player: Player) -> Vec<(i32, i32)> {
+    let mut pieces = Vec::new();
+
+    let mut bits = board.get_occupied(player);
+    while bits != 0 {
+        let index = bits.trailing_zeros() as i32;
+        bits &= bits - 1;
+
+        let loss = hanging(board, index);
+        if loss > 0 {
+            pieces.push((index, loss));
+        }
+    }
+
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.apply_fen(fen.to_string());
+        board
+    }
+
+    // e.g. "e4"
+    fn square(name: &str) -> i32 {
+        let bytes = name.as_bytes();
+        Board::row_col_to_index(b'8' as i32 - bytes[1] as i32, (bytes[0] - b'a') as i32)
+    }
+
+    fn see_of(fen: &str, from: &str, to: &str) -> i32 {
+        let board = board(fen);
+        let piece = board.at(Board::index_to_row_col(square(from))).unwrap();
+        see(&board, ChessMove::new(square(from), square(to), piece.kind, piece.player))
+    }
+
+    #[test]
+    fn exchanges() {
+        // An undefended pawn
+        assert_eq!(see_of("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1", "e5"), 100);
+        // A defended pawn, for a knight
+        assert_eq!(see_of("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "d3", "e5"), 100 - 320);
+        // The rook behind the first one joins in once that one has taken, so the rooks trade
+        assert_eq!(see_of("4k3/4r3/8/4p3/8/8/4R3/4R1K1 w - - 0 1", "e2", "e5"), 100);
+        // Walking a knight onto a square a pawn covers
+        assert_eq!(see_of("4k3/8/8/3p4/8/8/8/1N2K3 w - - 0 1", "b1", "c3"), 0);
+        assert_eq!(see_of("4k3/8/8/8/3p4/8/8/1N2K3 w - - 0 1", "b1", "c3"), -320);
+        // En passant, the pawn taken is not on the target square
+        assert_eq!(see_of("4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1", "e5", "d6"), 100);
+    }
+
+    #[test]
+    fn promotion() {
+        let board = board("4k3/P7/8/8/8/8/8/4K3 w - - 0 1");
+        let m = ChessMove::new(square("a7"), square("a8"), PieceKind::Pawn, Player::White).with_promotion(PieceKind::Queen);
+        assert_eq!(see(&board, m), 800);
+    }
+
+    #[test]
+    fn hanging_pieces_of_each_side() {
+        // The knight on c3 is attacked by a pawn and the rook on h1 by a rook, neither of them
+        // defended. The rook on h4 is defended by the queen, so taking it only trades rooks
+        let board = board("3qk3/8/8/8/3p3r/2N5/8/4K2R w - - 0 1");
+
+        assert_eq!(hanging(&board, square("h4")), 0);
+        assert_eq!(hanging_pieces(&board, Player::White), vec![(square("c3"), 320), (square("h1"), 500)]);
+        assert!(hanging_pieces(&board, Player::Black).is_empty());
+    }
+}
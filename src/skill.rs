@@ -0,0 +1,97 @@
+use crate::movegen::ChessMove;
+use crate::random::Random;
+use crate::search::SearchLimits;
+
+/*
+ * ----- SKILL -----
+ * Weaker play, for people who want a game they can win. Below the top level the search is cut
+ * short, both in depth and in nodes, and every root move is given an exact score so that the
+ * move played can be drawn at random among them. A move is picked with a weight that falls off
+ * with how much worse it scores than the best one:
+ *   weight = exp(-loss / temperature)
+ * where the temperature shrinks as the level goes up. The lowest levels happily give away a
+ * pawn, the higher ones only pick between moves that are about as good as each other, and
+ * a blunder stays rare at every level.
+ */
+
+pub const MAX_LEVEL: u8 = 20;
+
+// Rough guesses at the strength of the lowest level and of the top one, not measured against
+// rated players. In between the levels are spread evenly
+pub const MIN_ELO: u32 = 600;
+pub const MAX_ELO: u32 = 2400;
+
+// Level 0 searches this deep and this many nodes, every level above it a little more
+const BASE_DEPTH: f64 = 1.0;
+const DEPTH_PER_LEVEL: f64 = 0.5;
+const BASE_NODES: f64 = 200.0;
+// The nodes double every two levels
+const NODES_GROWTH: f64 = std::f64::consts::SQRT_2;
+
+// Centipawns at level 0, shrinking by the factor with every level
+const BASE_TEMPERATURE: f64 = 250.0;
+const TEMPERATURE_DECAY: f64 = 0.82;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Skill {
+    // Fractional, so that the Elo can fall between two levels
+    level: f64,
+}
+
+impl Default for Skill {
+    fn default() -> Self {
+        Self::new(MAX_LEVEL)
+    }
+}
+
+impl Skill {
+    pub fn new(level: u8) -> Self {
+        Self { level: level.min(MAX_LEVEL) as f64 }
+    }
+
+    pub fn from_elo(elo: u32) -> Self {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO);
+        Self { level: (elo - MIN_ELO) as f64 / (MAX_ELO - MIN_ELO) as f64 * MAX_LEVEL as f64 }
+    }
+
+    pub fn is_full_strength(&self) -> bool {
+        self.level >= MAX_LEVEL as f64
+    }
+
+    // The limits asked for, or the level's own when those are tighter
+    pub fn limit(&self, limits: SearchLimits) -> SearchLimits {
+        if self.is_full_strength() {
+            return limits;
+        }
+
+        let depth = (BASE_DEPTH + DEPTH_PER_LEVEL * self.level) as i32;
+        let nodes = (BASE_NODES * NODES_GROWTH.powf(self.level)) as u64;
+        SearchLimits {
+            depth: Some(limits.depth.map_or(depth, |limit| limit.min(depth))),
+            nodes: Some(limits.nodes.map_or(nodes, |limit| limit.min(nodes))),
+            ..limits
+        }
+    }
+
+    // One of the scored moves, most likely the best one. `moves` must not be empty
+    pub fn pick(&self, moves: &[(ChessMove, i32)], random: &mut Random) -> (ChessMove, i32) {
+        let best = moves.iter().map(|&(_, score)| score).max().unwrap();
+        if self.is_full_strength() {
+            return *moves.iter().find(|&&(_, score)| score == best).unwrap();
+        }
+
+        let temperature = BASE_TEMPERATURE * TEMPERATURE_DECAY.powf(self.level);
+        let weights: Vec<f64> = moves.iter().map(|&(_, score)| (-((best - score) as f64) / temperature).exp()).collect();
+
+        let mut target = random.fraction() * weights.iter().sum::<f64>();
+        for (&entry, weight) in moves.iter().zip(weights) {
+            if target < weight {
+                return entry;
+            }
+            target -= weight;
+        }
+
+        // Only reached through rounding
+        *moves.iter().find(|&&(_, score)| score == best).unwrap()
+    }
+}
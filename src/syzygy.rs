@@ -0,0 +1,1220 @@
+use crate::piece::{PieceKind, Player};
+use crate::board::Board;
+use crate::movegen::{ChessMove, MoveGen};
+use crate::ordering::MoveOrdering;
+use crate::tt::MATE_BOUND;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::ops::Neg;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/*
+ * ----- SYZYGY TABLEBASES -----
+ * Probes the Syzygy endgame tables, which hold the result of every position with few pieces.
+ * There are two files per material balance, named after it like KQvKR:
+ *   - .rtbw holds win/draw/loss, with cursed wins and blessed losses for the results the fifty
+ *     move rule turns into draws. These are probed during the search
+ *   - .rtbz holds the distance to the next capture or pawn move (DTZ) on the way to the
+ *     result. Only probed at the root, to pick moves that win without running into the
+ *     fifty move rule
+ *
+ * Positions are stored as an index worked out from the piece squares, using the board's
+ * symmetries to need as few of them as possible. The values are compressed with canonical
+ * Huffman codes over symbols that stand for pairs of other symbols (recursive pairing).
+ *
+ * The files leave out what a capture can tell, such as positions where a capture wins, so a
+ * probe also tries the captures itself. Positions with castling rights are not in the tables.
+ *
+ * This follows the layout of Stockfish's tbprobe.cpp, with squares numbered from a1 like the
+ * files do. Files are read into memory the first time they are needed.
+ */
+
+// The largest tables there are
+const TB_PIECES: usize = 7;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+// Flags of a table's compressed data
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+// Won tablebase positions score just below the mates found by the search
+pub const TB_WIN: i32 = MATE_BOUND - 1;
+
+// Root move ranks and scores for each result, from Wdl::Loss up
+const WDL_TO_RANK: [i32; 5] = [-1000, -899, 0, 899, 1000];
+const WDL_TO_SCORE: [i32; 5] = [-TB_WIN, -2, 0, 2, TB_WIN];
+
+// Which of a DTZ table's value maps belongs to each result, from Wdl::Loss up
+const WDL_TO_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+
+// Same order as the file names
+const PIECE_LETTERS: [(PieceKind, char); 5] = [
+    (PieceKind::Queen, 'Q'),
+    (PieceKind::Rook, 'R'),
+    (PieceKind::Bishop, 'B'),
+    (PieceKind::Knight, 'N'),
+    (PieceKind::Pawn, 'P'),
+];
+
+// From the point of view of the player to move
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss, // Lost, but drawn by the fifty move rule
+    Draw,
+    CursedWin, // Won, but drawn by the fifty move rule
+    Win,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ProbeState {
+    Ok,
+    Fail,
+    // DTZ tables only hold one side to move, the other has to be found with a 1 ply search
+    ChangeStm,
+    // The best move is a capture or pawn move, whose DTZ value is not stored
+    ZeroingBestMove,
+}
+
+// Tablebase results for the moves at the root
+pub struct RootMoves {
+    // The moves with the best result, only these are worth searching
+    pub moves: Vec<ChessMove>,
+    pub score: i32,
+    // Ranked by distance to zeroing, so the moves already make progress towards the result
+    pub dtz: bool,
+}
+
+// The pieces of a table, from its name
+struct Material {
+    name: String,
+    piece_count: usize,
+    has_pawns: bool,
+    // A piece besides the kings that is the only one of its kind and colour
+    has_unique_pieces: bool,
+    // [leading colour, other colour]. The leading colour is the one with fewer pawns
+    pawn_count: [usize; 2],
+    // Both sides have the same pieces, only white to move is stored
+    symmetric: bool,
+}
+
+// Offsets point into the table's file
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    max_sym_len: usize,
+    min_sym_len: usize,
+    num_blocks: usize,
+    block_size: usize,
+    // Every `span` values there is an entry in the sparse index
+    span: usize,
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    // base64[l - min_sym_len] is the lowest symbol of length l, padded to 64 bits
+    base64: Vec<u64>,
+    // How many values (minus one) each symbol stands for
+    symlen: Vec<u8>,
+    // The order the pieces are encoded in
+    pieces: [u8; TB_PIECES],
+    group_idx: [u64; TB_PIECES + 1],
+    // Zero terminated
+    group_len: [usize; TB_PIECES + 1],
+    // Start of the DTZ value maps for Win, Loss, CursedWin and BlessedLoss
+    map_idx: [usize; 4],
+}
+
+struct Table {
+    bytes: Vec<u8>,
+    // [side to move][leading pawn file], one side for DTZ tables and symmetric materials
+    items: Vec<Vec<PairsData>>,
+    // Start of the DTZ value maps
+    map: usize,
+}
+
+struct Entry {
+    material: Material,
+    wdl: OnceLock<Option<Table>>,
+    dtz: OnceLock<Option<Table>>,
+}
+
+// Lookup tables for the position indices
+struct Indices {
+    // Pawns on a2-h7 numbered so that the leading pawn, nearest the edge and then lowest, is
+    // the highest. Also the number of squares left for the other pawns
+    map_pawns: [usize; 64],
+    // Squares below the a1-h8 diagonal
+    map_b1h1h7: [usize; 64],
+    // The a1-d1-d4 triangle, diagonal squares last
+    map_a1d1d4: [usize; 64],
+    // The 462 ways to place the kings with the first one in the triangle
+    map_kk: [[usize; 64]; 10],
+    // binomial[k][n] ways to choose k of n squares
+    binomial: [[u64; 64]; 6],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+pub struct Tablebases {
+    dirs: Vec<PathBuf>,
+    entries: HashMap<String, Entry>,
+    indices: Indices,
+    max_pieces: usize,
+}
+
+impl Neg for Wdl {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::from_value(-self.value())
+    }
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Self {
+        match value {
+            ..=-2 => Self::Loss,
+            -1 => Self::BlessedLoss,
+            0 => Self::Draw,
+            1 => Self::CursedWin,
+            _ => Self::Win,
+        }
+    }
+
+    pub fn value(self) -> i32 {
+        self as i32 - 2
+    }
+
+    // DTZ values are not stored for captures and pawn moves, but follow from the result
+    fn dtz_before_zeroing(self) -> i32 {
+        match self {
+            Self::Win => 1,
+            Self::CursedWin => 101,
+            Self::BlessedLoss => -101,
+            Self::Loss => -1,
+            Self::Draw => 0,
+        }
+    }
+}
+
+impl Material {
+    // Like KRPvKR, white's pieces first
+    fn parse(name: &str) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+        let count = |side: &str, letter: char| side.chars().filter(|&c| c == letter).count();
+
+        let valid = |side: &str| side.starts_with('K') && count(side, 'K') == 1 && side.chars().all(|c| "KQRBNP".contains(c));
+        if !valid(white) || !valid(black) {
+            return None;
+        }
+
+        let has_unique_pieces = [white, black]
+            .iter()
+            .any(|side| PIECE_LETTERS.iter().any(|&(_, letter)| count(side, letter) == 1));
+
+        // Fewer pawns compress better, so that side's pawns lead
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+
+        Some(Self {
+            name: name.to_string(),
+            piece_count: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count: if white_leads { [white_pawns, black_pawns] } else { [black_pawns, white_pawns] },
+            symmetric: white == black,
+        })
+    }
+}
+
+impl Table {
+    fn get(&self, stm: usize, file: usize) -> &PairsData {
+        &self.items[stm % self.items.len()][file % self.items[0].len()]
+    }
+
+    fn read(path: &Path, magic: [u8; 4], material: &Material, indices: &Indices) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < 5 || bytes[0..4] != magic[..] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a Syzygy table"));
+        }
+
+        let dtz = magic == DTZ_MAGIC;
+        let has_pawns = bytes[4] & 2 != 0;
+        let split = bytes[4] & 1 != 0;
+        if has_pawns != material.has_pawns || split == material.symmetric {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "table does not match its name"));
+        }
+
+        let sides = if !dtz && !material.symmetric { 2 } else { 1 };
+        let files = if material.has_pawns { 4 } else { 1 };
+        let pawns_on_both_sides = material.has_pawns && material.pawn_count[1] > 0;
+
+        let mut table = Self {
+            items: vec![vec![PairsData::default(); files]; sides],
+            bytes,
+            map: 0,
+        };
+        let bytes = &table.bytes;
+        let mut at = 5;
+
+        for file in 0..files {
+            // The order the groups of pieces are encoded in, per side
+            let second = if pawns_on_both_sides { bytes[at + 1] } else { 0xFF };
+            let order = [[bytes[at] & 0xF, second & 0xF], [bytes[at] >> 4, second >> 4]];
+            at += 1 + pawns_on_both_sides as usize;
+
+            for k in 0..material.piece_count {
+                for (side, items) in table.items.iter_mut().enumerate() {
+                    items[file].pieces[k] = if side == 1 { bytes[at] >> 4 } else { bytes[at] & 0xF };
+                }
+                at += 1;
+            }
+
+            for (side, items) in table.items.iter_mut().enumerate() {
+                indices.set_groups(material, &mut items[file], order[side], file);
+            }
+        }
+
+        at += at & 1;
+
+        for file in 0..files {
+            for items in &mut table.items {
+                at = items[file].set_sizes(bytes, at);
+            }
+        }
+
+        if dtz {
+            table.map = at;
+            for file in 0..files {
+                let d = &mut table.items[0][file];
+                if d.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+
+                // Four maps, one per result, of bytes or of 16 bit words
+                for i in 0..4 {
+                    if d.flags & FLAG_WIDE != 0 {
+                        at += at & 1;
+                        d.map_idx[i] = (at - table.map) / 2 + 1;
+                        at += 2 * read_u16(bytes, at) as usize + 2;
+                    } else {
+                        d.map_idx[i] = at - table.map + 1;
+                        at += bytes.get(at).copied().unwrap_or(0) as usize + 1;
+                    }
+                }
+            }
+            at += at & 1;
+        }
+
+        for file in 0..files {
+            for items in &mut table.items {
+                items[file].sparse_index = at;
+                at += items[file].sparse_index_size * 6;
+            }
+        }
+
+        for file in 0..files {
+            for items in &mut table.items {
+                items[file].block_length = at;
+                at += items[file].block_length_size * 2;
+            }
+        }
+
+        for file in 0..files {
+            for items in &mut table.items {
+                at = (at + 0x3F) & !0x3F;
+                items[file].data = at;
+                at += items[file].num_blocks * items[file].block_size;
+            }
+        }
+
+        if at > bytes.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "table is truncated"));
+        }
+
+        Ok(table)
+    }
+
+    // DTZ values are stored by how common they are, the maps turn them back into plies
+    fn map_score(&self, file: usize, value: i32, wdl: Wdl) -> i32 {
+        let d = self.get(0, file);
+        let mut value = value;
+
+        if d.flags & FLAG_MAPPED != 0 {
+            let i = d.map_idx[WDL_TO_MAP[wdl as usize]] + value as usize;
+            value = if d.flags & FLAG_WIDE != 0 {
+                read_u16(&self.bytes, self.map + 2 * i) as i32
+            } else {
+                self.bytes.get(self.map + i).copied().unwrap_or(0) as i32
+            };
+        }
+
+        // Some tables count in moves rather than plies
+        if (wdl == Wdl::Win && d.flags & FLAG_WIN_PLIES == 0)
+            || (wdl == Wdl::Loss && d.flags & FLAG_LOSS_PLIES == 0)
+            || wdl == Wdl::CursedWin
+            || wdl == Wdl::BlessedLoss
+        {
+            value *= 2;
+        }
+
+        value + 1
+    }
+}
+
+impl PairsData {
+    // Reads the sizes of the compressed data and the Huffman code, returns where they end
+    fn set_sizes(&mut self, bytes: &[u8], mut at: usize) -> usize {
+        let byte = |at: usize| bytes.get(at).copied().unwrap_or(0);
+
+        self.flags = byte(at);
+        at += 1;
+
+        // The whole table is one value
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            self.min_sym_len = byte(at) as usize;
+            return at + 1;
+        }
+
+        let groups = self.group_len.iter().position(|&len| len == 0).unwrap_or(TB_PIECES);
+        let size = self.group_idx[groups];
+
+        self.block_size = 1 << byte(at);
+        self.span = 1 << byte(at + 1);
+        self.sparse_index_size = size.div_ceil(self.span as u64) as usize;
+        let padding = byte(at + 2) as usize;
+        self.num_blocks = read_u32(bytes, at + 3) as usize;
+        // Padded so that the sparse index can not point past the end
+        self.block_length_size = self.num_blocks + padding;
+        self.max_sym_len = byte(at + 7) as usize;
+        self.min_sym_len = byte(at + 8) as usize;
+        at += 9;
+        self.lowest_sym = at;
+
+        // Longer codes have lower values, so base64 goes down as the length goes up
+        let lengths = (self.max_sym_len + 1).saturating_sub(self.min_sym_len).max(1);
+        let lowest = |i: usize| read_u16(bytes, self.lowest_sym + 2 * i) as u64;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            self.base64[i] = self.base64[i + 1].wrapping_add(lowest(i)).wrapping_sub(lowest(i + 1)) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base = base.checked_shl(64usize.saturating_sub(i + self.min_sym_len) as u32).unwrap_or(0);
+        }
+
+        at += lengths * 2;
+        let symbols = read_u16(bytes, at) as usize;
+        at += 2;
+        self.btree = at;
+
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.set_symlen(bytes, sym, &mut visited);
+            }
+        }
+
+        at + symbols * 3 + (symbols & 1)
+    }
+
+    // How many values a symbol stands for, from the pair it expands to
+    fn set_symlen(&mut self, bytes: &[u8], sym: usize, visited: &mut [bool]) -> u8 {
+        visited[sym] = true;
+
+        let right = self.right(bytes, sym);
+        if right == 0xFFF {
+            return 0;
+        }
+        let left = self.left(bytes, sym);
+
+        for child in [left, right] {
+            if child < visited.len() && !visited[child] {
+                self.symlen[child] = self.set_symlen(bytes, child, visited);
+            }
+        }
+
+        let len = |sym: usize| self.symlen.get(sym).copied().unwrap_or(0) as u32;
+        (len(left) + len(right) + 1) as u8
+    }
+
+    // Each symbol of the tree is 3 bytes, 12 bits for the left symbol and 12 for the right
+    fn left(&self, bytes: &[u8], sym: usize) -> usize {
+        let at = self.btree + 3 * sym;
+        let byte = |i: usize| bytes.get(at + i).copied().unwrap_or(0) as usize;
+        ((byte(1) & 0xF) << 8) | byte(0)
+    }
+
+    fn right(&self, bytes: &[u8], sym: usize) -> usize {
+        let at = self.btree + 3 * sym;
+        let byte = |i: usize| bytes.get(at + i).copied().unwrap_or(0) as usize;
+        (byte(2) << 4) | (byte(1) >> 4)
+    }
+
+    fn block_len(&self, bytes: &[u8], block: usize) -> i64 {
+        read_u16(bytes, self.block_length + 2 * block) as i64
+    }
+
+    // The value at `index`. The data is split in blocks that each hold a number of values,
+    // the sparse index says roughly which block to look in
+    fn decompress(&self, bytes: &[u8], index: u64) -> i32 {
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            return self.min_sym_len as i32;
+        }
+
+        let span = self.span as u64;
+        let k = (index / span) as usize;
+        let entry = self.sparse_index + 6 * k;
+        let mut block = read_u32(bytes, entry) as usize;
+        let mut offset = read_u16(bytes, entry + 4) as i64;
+
+        // The entry is for the value in the middle of its span
+        offset += (index % span) as i64 - (span / 2) as i64;
+
+        while offset < 0 && block > 0 {
+            block -= 1;
+            offset += self.block_len(bytes, block) + 1;
+        }
+        while offset > self.block_len(bytes, block) && block + 1 < self.block_length_size {
+            offset -= self.block_len(bytes, block) + 1;
+            block += 1;
+        }
+
+        // Walk the symbols of the block until the one holding our value
+        let mut at = self.data + block * self.block_size;
+        let mut buffer = read_u64_be(bytes, at);
+        let mut buffer_size = 64;
+        at += 8;
+
+        let mut sym;
+        loop {
+            // All codes of one length are consecutive, so the length follows from base64
+            let mut len = 0;
+            while len + 1 < self.base64.len() && buffer < self.base64[len] {
+                len += 1;
+            }
+
+            let shift = 64usize.saturating_sub(len + self.min_sym_len) as u32;
+            sym = buffer.wrapping_sub(self.base64[len]).checked_shr(shift).unwrap_or(0) as usize;
+            sym += read_u16(bytes, self.lowest_sym + 2 * len) as usize;
+
+            let values = self.symlen.get(sym).copied().unwrap_or(0) as i64 + 1;
+            if offset < values {
+                break;
+            }
+
+            offset -= values;
+            let bits = len + self.min_sym_len;
+            buffer = buffer.checked_shl(bits as u32).unwrap_or(0);
+            buffer_size -= bits as i32;
+
+            if buffer_size <= 32 {
+                buffer_size += 32;
+                buffer |= (read_u32_be(bytes, at) as u64) << (64 - buffer_size);
+                at += 4;
+            }
+        }
+
+        // Symbols stand for pairs of symbols, expand down to the value
+        while self.symlen.get(sym).copied().unwrap_or(0) != 0 {
+            let left = self.left(bytes, sym);
+            let values = self.symlen.get(left).copied().unwrap_or(0) as i64 + 1;
+
+            if offset < values {
+                sym = left;
+            } else {
+                offset -= values;
+                sym = self.right(bytes, sym);
+            }
+        }
+
+        self.left(bytes, sym) as i32
+    }
+}
+
+impl Indices {
+    fn new() -> Self {
+        let mut indices = Self {
+            map_pawns: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 6],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        let mut code = 0;
+        for square in 0..64 {
+            if off_diagonal(square) < 0 {
+                indices.map_b1h1h7[square] = code;
+                code += 1;
+            }
+        }
+
+        let mut code = 0;
+        let mut diagonal = Vec::new();
+        for square in 0..=27 {
+            if off_diagonal(square) < 0 && square % 8 <= 3 {
+                indices.map_a1d1d4[square] = code;
+                code += 1;
+            } else if off_diagonal(square) == 0 && square % 8 <= 3 {
+                diagonal.push(square);
+            }
+        }
+        for square in diagonal {
+            indices.map_a1d1d4[square] = code;
+            code += 1;
+        }
+
+        // The kings can not stand next to each other, and with the first king on the diagonal
+        // the second is kept below it. Both on the diagonal come last
+        let mut code = 0;
+        let mut both_on_diagonal = Vec::new();
+        for idx in 0..10 {
+            for first in 0..=27 {
+                // b1 is the square numbered 0, the others are not in the triangle
+                if indices.map_a1d1d4[first] != idx || (idx == 0 && first != 1) {
+                    continue;
+                }
+
+                for second in 0..64 {
+                    let distance = (first % 8).abs_diff(second % 8).max((first / 8).abs_diff(second / 8));
+                    if distance <= 1 || (off_diagonal(first) == 0 && off_diagonal(second) > 0) {
+                        continue;
+                    }
+
+                    if off_diagonal(first) == 0 && off_diagonal(second) == 0 {
+                        both_on_diagonal.push((idx, second));
+                    } else {
+                        indices.map_kk[idx][second] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, second) in both_on_diagonal {
+            indices.map_kk[idx][second] = code;
+            code += 1;
+        }
+
+        indices.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6.min(n + 1) {
+                indices.binomial[k][n] = if k > 0 { indices.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { indices.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // Tables with pawns are split by the leading pawn's file, a to d after mirroring
+        let mut available = 47;
+        for lead_pawns in 1..=5 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..=6 {
+                    let square = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        indices.map_pawns[square] = available;
+                        indices.map_pawns[square ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    indices.lead_pawn_idx[lead_pawns][square] = idx;
+                    idx += indices.binomial[lead_pawns - 1][indices.map_pawns[square]];
+                }
+                indices.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+
+        indices
+    }
+
+    // Pieces of the same kind and colour are encoded together as a group, the leading group
+    // being the pawns of one side, three unique pieces or the two kings. The position's index
+    // is then g1 * N(g2) * N(g3) + g2 * N(g3) + g3 for groups that fit on the board N(g) ways,
+    // in the order the table asks for
+    fn set_groups(&self, material: &Material, d: &mut PairsData, order: [u8; 2], file: usize) {
+        let mut n = 0;
+        let mut first_len: i32 = if material.has_pawns { 0 } else if material.has_unique_pieces { 3 } else { 2 };
+        d.group_len[0] = 1;
+
+        for i in 1..material.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let pawns_on_both_sides = material.has_pawns && material.pawn_count[1] > 0;
+        let mut next = if pawns_on_both_sides { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if pawns_on_both_sides { d.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                d.group_idx[0] = idx;
+                idx *= if material.has_pawns {
+                    self.lead_pawns_size[d.group_len[0]][file]
+                } else if material.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                d.group_idx[1] = idx;
+                idx *= self.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= self.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+
+        d.group_idx[n] = idx;
+    }
+}
+
+impl Tablebases {
+    // `path` can hold several directories, separated like in PATH
+    pub fn open(path: &str) -> Self {
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        let dirs: Vec<PathBuf> = path.split(separator).filter(|dir| !dir.is_empty()).map(PathBuf::from).collect();
+
+        let mut entries = HashMap::new();
+        for dir in &dirs {
+            let Ok(files) = fs::read_dir(dir) else {
+                continue;
+            };
+
+            for file in files.flatten() {
+                let path = file.path();
+                if path.extension().is_none_or(|extension| extension != "rtbw") {
+                    continue;
+                }
+
+                let material = path.file_stem().and_then(|stem| stem.to_str()).and_then(Material::parse);
+                if let Some(material) = material.filter(|material| material.piece_count <= TB_PIECES) {
+                    entries.entry(material.name.clone()).or_insert(Entry {
+                        material,
+                        wdl: OnceLock::new(),
+                        dtz: OnceLock::new(),
+                    });
+                }
+            }
+        }
+
+        let max_pieces = entries.values().map(|entry| entry.material.piece_count).max().unwrap_or(0);
+        Self { dirs, entries, indices: Indices::new(), max_pieces }
+    }
+
+    // Number of WDL tables found
+    pub fn table_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Positions with more pieces are never in the tables
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    pub fn piece_count(board: &Board) -> usize {
+        board.white.iter().chain(board.black.iter()).map(|bitboard| bitboard.count_ones() as usize).sum()
+    }
+
+    fn can_probe(&self, board: &Board) -> bool {
+        let castling = board.white_castling_k || board.white_castling_q || board.black_castling_k || board.black_castling_q;
+        !castling && Self::piece_count(board) <= self.max_pieces
+    }
+
+    // The result of the position, None if it is not in the tables
+    pub fn probe_wdl(&self, board: &Board) -> Option<Wdl> {
+        if !self.can_probe(board) {
+            return None;
+        }
+
+        let mut state = ProbeState::Ok;
+        let wdl = self.search(board, false, &mut state);
+        (state != ProbeState::Fail).then_some(wdl)
+    }
+
+    // Plies to the next capture or pawn move on the way to the result, negative when losing.
+    // Above 100 the fifty move rule draws. None if the position is not in the tables
+    pub fn probe_dtz(&self, board: &Board) -> Option<i32> {
+        if !self.can_probe(board) {
+            return None;
+        }
+
+        let mut state = ProbeState::Ok;
+        let dtz = self.dtz(board, &mut state);
+        (state != ProbeState::Fail).then_some(dtz)
+    }
+
+    // Ranks the legal moves by DTZ, or by WDL if some DTZ tables are missing. `history` holds
+    // the hashes of the positions played so far, the current one last
+    pub fn rank_root_moves(&self, board: &Board, history: &[u64], fifty_move_rule: bool) -> Option<RootMoves> {
+        if !self.can_probe(board) {
+            return None;
+        }
+
+        let mut board = board.clone();
+        let moves = MoveGen::legal(&mut board);
+        if moves.is_empty() {
+            return None;
+        }
+
+        let ranked = self.rank_by_dtz(&board, &moves, history, fifty_move_rule)
+            .map(|ranked| (ranked, true))
+            .or_else(|| self.rank_by_wdl(&board, &moves, fifty_move_rule).map(|ranked| (ranked, false)));
+        let (ranked, dtz) = ranked?;
+
+        let best = ranked.iter().map(|&(_, rank, _)| rank).max()?;
+        let score = ranked.iter().find(|&&(_, rank, _)| rank == best).map(|&(_, _, score)| score)?;
+        let moves = ranked.into_iter().filter(|&(_, rank, _)| rank == best).map(|(m, _, _)| m).collect();
+
+        Some(RootMoves { moves, score, dtz })
+    }
+
+    // (move, rank, score) for every move. Certain wins are ranked the same, the others by
+    // how close they come to the fifty move rule
+    fn rank_by_dtz(&self, board: &Board, moves: &[ChessMove], history: &[u64], fifty_move_rule: bool) -> Option<Vec<(ChessMove, i32, i32)>> {
+        let fifty = board.halfmove_clock;
+
+        // A repetition since the last capture or pawn move means we might be going in circles
+        let recent = &history[history.len().saturating_sub(fifty as usize + 1)..];
+        let repeated = recent.iter().enumerate().any(|(i, hash)| recent[..i].contains(hash));
+
+        let bound = if fifty_move_rule { 900 } else { 1 };
+        let mut ranked = Vec::new();
+
+        for &m in moves {
+            let mut child = board.clone();
+            child.apply_move(m);
+
+            let mut state = ProbeState::Ok;
+            let mut dtz = if child.halfmove_clock == 0 {
+                (-self.search(&child, false, &mut state)).dtz_before_zeroing()
+            } else {
+                let dtz = -self.dtz(&child, &mut state);
+                dtz + dtz.signum()
+            };
+
+            if state == ProbeState::Fail {
+                return None;
+            }
+
+            // Mate is as short as it gets
+            if dtz == 2 && child.is_king_in_check(child.get_turn()) && MoveGen::legal(&mut child).is_empty() {
+                dtz = 1;
+            }
+
+            let rank = if dtz > 0 {
+                if dtz + fifty <= 99 && !repeated { 1000 } else { 1000 - (dtz + fifty) }
+            } else if dtz < 0 {
+                if -dtz * 2 + fifty < 100 { -1000 } else { -1000 + (-dtz + fifty) }
+            } else {
+                0
+            };
+
+            // Cursed wins get a small score that grows as the real win gets closer
+            let score = if rank >= bound {
+                TB_WIN
+            } else if rank > 0 {
+                (rank - 800).max(3) / 2
+            } else if rank == 0 {
+                0
+            } else if rank > -bound {
+                (rank + 800).min(-3) / 2
+            } else {
+                -TB_WIN
+            };
+
+            ranked.push((m, rank, score));
+        }
+
+        Some(ranked)
+    }
+
+    fn rank_by_wdl(&self, board: &Board, moves: &[ChessMove], fifty_move_rule: bool) -> Option<Vec<(ChessMove, i32, i32)>> {
+        let mut ranked = Vec::new();
+
+        for &m in moves {
+            let mut child = board.clone();
+            child.apply_move(m);
+
+            let mut state = ProbeState::Ok;
+            let mut wdl = -self.search(&child, false, &mut state);
+            if state == ProbeState::Fail {
+                return None;
+            }
+
+            let rank = WDL_TO_RANK[wdl as usize];
+            if !fifty_move_rule {
+                wdl = Wdl::from_value(wdl.value().signum() * 2);
+            }
+            ranked.push((m, rank, WDL_TO_SCORE[wdl as usize]));
+        }
+
+        Some(ranked)
+    }
+
+    // The tables may store anything for positions where a capture (or with
+    // `check_zeroing_moves` a pawn move) is best, so those moves are tried first
+    fn search(&self, board: &Board, check_zeroing_moves: bool, state: &mut ProbeState) -> Wdl {
+        let mut board = board.clone();
+        let moves = MoveGen::legal(&mut board);
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+
+        for &m in &moves {
+            if !MoveOrdering::is_capture(&board, m) && (!check_zeroing_moves || m.kind != PieceKind::Pawn) {
+                continue;
+            }
+            searched += 1;
+
+            let mut child = board.clone();
+            child.apply_move(m);
+            let wdl = -self.search(&child, false, state);
+
+            if *state == ProbeState::Fail {
+                return Wdl::Draw;
+            }
+
+            if wdl > best {
+                best = wdl;
+                if wdl == Wdl::Win {
+                    *state = ProbeState::ZeroingBestMove;
+                    return wdl;
+                }
+            }
+        }
+
+        // With every move searched the table is not needed, and could be wrong, for example
+        // when there is an en passant capture
+        let no_more_moves = searched > 0 && searched == moves.len();
+        let wdl = if no_more_moves {
+            best
+        } else {
+            let wdl = Wdl::from_value(self.probe_table(&board, false, Wdl::Draw, state));
+            if *state == ProbeState::Fail {
+                return Wdl::Draw;
+            }
+            wdl
+        };
+
+        if best >= wdl {
+            *state = if best > Wdl::Draw || no_more_moves { ProbeState::ZeroingBestMove } else { ProbeState::Ok };
+            return best;
+        }
+
+        *state = ProbeState::Ok;
+        wdl
+    }
+
+    fn dtz(&self, board: &Board, state: &mut ProbeState) -> i32 {
+        *state = ProbeState::Ok;
+        let wdl = self.search(board, true, state);
+
+        // Draws are not stored
+        if *state == ProbeState::Fail || wdl == Wdl::Draw {
+            return 0;
+        }
+        if *state == ProbeState::ZeroingBestMove {
+            return wdl.dtz_before_zeroing();
+        }
+
+        let dtz = self.probe_table(board, true, wdl, state);
+        if *state == ProbeState::Fail {
+            return 0;
+        }
+
+        if *state != ProbeState::ChangeStm {
+            let cursed = wdl == Wdl::CursedWin || wdl == Wdl::BlessedLoss;
+            return (dtz + if cursed { 100 } else { 0 }) * wdl.value().signum();
+        }
+
+        // The table is for the other side to move, so take the best of the moves
+        let mut board = board.clone();
+        let mut min_dtz = 0xFFFF;
+
+        for m in MoveGen::legal(&mut board) {
+            let zeroing = MoveOrdering::is_capture(&board, m) || m.kind == PieceKind::Pawn;
+
+            let mut child = board.clone();
+            child.apply_move(m);
+
+            // A capture or pawn move resets the count, so its own DTZ follows from the result
+            let mut dtz = if zeroing {
+                -(self.search(&child, false, state).dtz_before_zeroing())
+            } else {
+                -self.dtz(&child, state)
+            };
+
+            if dtz == 1 && child.is_king_in_check(child.get_turn()) && MoveGen::legal(&mut child).is_empty() {
+                min_dtz = 1;
+            }
+
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+
+            if dtz < min_dtz && dtz.signum() == wdl.value().signum() {
+                min_dtz = dtz;
+            }
+
+            if *state == ProbeState::Fail {
+                return 0;
+            }
+        }
+
+        // No legal moves, mated
+        if min_dtz == 0xFFFF { -1 } else { min_dtz }
+    }
+
+    // WDL tables give the result as a value of -2 to 2, DTZ tables the distance in plies
+    fn probe_table(&self, board: &Board, dtz: bool, wdl: Wdl, state: &mut ProbeState) -> i32 {
+        if Self::piece_count(board) == 2 {
+            return 0;
+        }
+
+        let white = board.material_name(Player::White);
+        let black = board.material_name(Player::Black);
+
+        // Tables are only made with the stronger side as white
+        let (entry, black_stronger) = match self.entries.get(&format!("{}v{}", white, black)) {
+            Some(entry) => (entry, false),
+            None => match self.entries.get(&format!("{}v{}", black, white)) {
+                Some(entry) => (entry, true),
+                None => {
+                    *state = ProbeState::Fail;
+                    return 0;
+                }
+            }
+        };
+
+        let (lock, extension, magic) = if dtz { (&entry.dtz, "rtbz", DTZ_MAGIC) } else { (&entry.wdl, "rtbw", WDL_MAGIC) };
+        let table = lock.get_or_init(|| {
+            let file = format!("{}.{}", entry.material.name, extension);
+            let path = self.dirs.iter().map(|dir| dir.join(&file)).find(|path| path.exists())?;
+            Table::read(&path, magic, &entry.material, &self.indices).ok()
+        });
+
+        let Some(table) = table else {
+            *state = ProbeState::Fail;
+            return 0;
+        };
+
+        self.probe(board, &entry.material, table, dtz, black_stronger, wdl, state)
+    }
+
+    // Works out the position's index in the table and reads its value
+    #[allow(clippy::too_many_arguments)]
+    fn probe(&self, board: &Board, material: &Material, table: &Table, dtz: bool, black_stronger: bool, wdl: Wdl, state: &mut ProbeState) -> i32 {
+        let indices = &self.indices;
+        let mut squares = [0usize; TB_PIECES];
+        let mut pieces = [0u8; TB_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = 0;
+        let mut lead_pawns_count = 0;
+        let mut file = 0;
+
+        // The tables are for white as the stronger side, and symmetric ones for white to
+        // move, anything else is mirrored first
+        let black_to_move = board.get_turn() == Player::Black;
+        let flip = black_stronger || (material.symmetric && black_to_move);
+        let flip_colour = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip ^ black_to_move) as usize;
+
+        // Pawns come first, the table is picked by the file of the leading one
+        if material.has_pawns {
+            let colour = table.get(0, 0).pieces[0] ^ flip_colour;
+            lead_pawns = if colour & 8 == 0 { board.white[PieceKind::Pawn as usize] } else { board.black[PieceKind::Pawn as usize] };
+
+            for index in Board::squares(lead_pawns) {
+                squares[size] = tb_square(index) ^ flip_squares;
+                size += 1;
+            }
+            lead_pawns_count = size;
+
+            let lead = (0..size).max_by_key(|&i| indices.map_pawns[squares[i]]).unwrap_or(0);
+            squares.swap(0, lead);
+            file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+
+        if dtz {
+            let flags = table.get(stm, file).flags;
+            let only_side = material.symmetric && !material.has_pawns;
+            if (flags & FLAG_STM) as usize != stm && !only_side {
+                *state = ProbeState::ChangeStm;
+                return 0;
+            }
+        }
+
+        for (player, bitboards) in [(Player::White, &board.white), (Player::Black, &board.black)] {
+            for kind in PieceKind::iterator() {
+                for index in Board::squares(bitboards[*kind as usize] & !lead_pawns) {
+                    if size == TB_PIECES {
+                        *state = ProbeState::Fail;
+                        return 0;
+                    }
+                    let colour = if player == Player::White { 0 } else { 8 };
+                    squares[size] = tb_square(index) ^ flip_squares;
+                    pieces[size] = (*kind as u8 + 1 + colour) ^ flip_colour;
+                    size += 1;
+                }
+            }
+        }
+
+        let d = table.get(stm, file);
+
+        // Put the pieces in the order the table encodes them in
+        for i in lead_pawns_count..size.saturating_sub(1) {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // The leading piece goes on the a-d files
+        if squares[0] % 8 > 3 {
+            for square in &mut squares[..size] {
+                *square ^= 7;
+            }
+        }
+
+        let mut idx: u64;
+        if material.has_pawns {
+            idx = indices.lead_pawn_idx[lead_pawns_count][squares[0]];
+            squares[1..lead_pawns_count].sort_by_key(|&square| indices.map_pawns[square]);
+            for (i, &square) in squares.iter().enumerate().take(lead_pawns_count).skip(1) {
+                idx += indices.binomial[i][indices.map_pawns[square]];
+            }
+        } else {
+            // Without pawns the leading piece also goes on ranks 1-4, and the first piece of
+            // the leading group off the a1-h8 diagonal goes below it
+            if squares[0] / 8 > 3 {
+                for square in &mut squares[..size] {
+                    *square ^= 56;
+                }
+            }
+
+            for i in 0..d.group_len[0] {
+                let off = off_diagonal(squares[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    for square in &mut squares[i..size] {
+                        *square = ((*square >> 3) | (*square << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            if material.has_unique_pieces {
+                // Three unique pieces are encoded together, squares already taken are skipped
+                let [s0, s1, s2] = [squares[0], squares[1], squares[2]];
+                let adjust1 = (s1 > s0) as usize;
+                let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+                let rank = |square: usize| square / 8;
+
+                idx = if off_diagonal(s0) != 0 {
+                    ((indices.map_a1d1d4[s0] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2) as u64
+                } else if off_diagonal(s1) != 0 {
+                    ((6 * 63 + rank(s0) * 28 + indices.map_b1h1h7[s1]) * 62 + s2 - adjust2) as u64
+                } else if off_diagonal(s2) != 0 {
+                    (6 * 63 * 62 + 4 * 28 * 62 + rank(s0) * 7 * 28 + (rank(s1) - adjust1) * 28 + indices.map_b1h1h7[s2]) as u64
+                } else {
+                    (6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(s0) * 7 * 6 + (rank(s1) - adjust1) * 6 + (rank(s2) - adjust2)) as u64
+                };
+            } else {
+                idx = indices.map_kk[indices.map_a1d1d4[squares[0]]][squares[1]] as u64;
+            }
+        }
+
+        // The remaining groups, each sorted, skipping the squares of the groups before
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = material.has_pawns && material.pawn_count[1] > 0;
+        let mut next = 1;
+
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort();
+
+            let mut n = 0;
+            for i in 0..len {
+                let square = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&taken| square > taken).count();
+                let pawn_ranks = if remaining_pawns { 8 } else { 0 };
+                n += indices.binomial[i + 1][square - adjust - pawn_ranks];
+            }
+
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value = d.decompress(&table.bytes, idx);
+        if dtz { table.map_score(file, value, wdl) } else { value - 2 }
+    }
+}
+
+// Square numbered from a1, like the tables do
+fn tb_square(index: i32) -> usize {
+    (index ^ 56) as usize
+}
+
+// Above the a1-h8 diagonal when positive
+fn off_diagonal(square: usize) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+fn read_bytes<const N: usize>(bytes: &[u8], at: usize) -> [u8; N] {
+    let mut out = [0; N];
+    if let Some(slice) = bytes.get(at..) {
+        let len = slice.len().min(N);
+        out[..len].copy_from_slice(&slice[..len]);
+    }
+    out
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(read_bytes(bytes, at))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(read_bytes(bytes, at))
+}
+
+fn read_u32_be(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(read_bytes(bytes, at))
+}
+
+fn read_u64_be(bytes: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(read_bytes(bytes, at))
+}
@@ -0,0 +1,149 @@
+use crate::search::SearchLimits;
+
+use std::time::{Duration, Instant};
+
+/*
+ * ----- TIME MANAGEMENT -----
+ * Two budgets are worked out from the clock when a search starts:
+ *   - The soft limit, after which no new iteration is started. It is scaled up while the best
+ *     move keeps changing or the score is dropping, since that is when extra time pays off
+ *   - The hard limit, at which the search is stopped in the middle of an iteration
+ */
+
+// Kept back for the time it takes the move to reach the GUI
+const MOVE_OVERHEAD: u64 = 30;
+// Moves the remaining time is split over in sudden death
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+// How much longer than the soft limit the search may go on
+const HARD_LIMIT_FACTOR: u64 = 4;
+// The soft limit is never extended past this many times its normal size
+const MAX_EXTENSION: f64 = 3.0;
+
+pub struct TimeManager {
+    start: Instant,
+    soft: Option<Duration>,
+    hard: Option<Duration>,
+    // Decaying count of best move changes between iterations
+    instability: f64,
+    score_drop: i32,
+    last_best_move: u16,
+    last_score: Option<i32>,
+}
+
+impl TimeManager {
+    pub fn new(limits: &SearchLimits) -> Self {
+        let (soft, hard) = match (limits.move_time, limits.time) {
+            (Some(move_time), _) => {
+                let budget = Duration::from_millis(move_time.saturating_sub(MOVE_OVERHEAD).max(1));
+                (Some(budget), Some(budget))
+            },
+            (None, Some(time)) => {
+                let available = time.saturating_sub(MOVE_OVERHEAD).max(1);
+                let moves_to_go = limits.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+
+                // Most of the increment can be spent, it comes back after the move
+                let soft = (time / moves_to_go + limits.increment * 3 / 4).min(available);
+                let hard = (soft * HARD_LIMIT_FACTOR).min(available);
+                (Some(Duration::from_millis(soft)), Some(Duration::from_millis(hard)))
+            },
+            (None, None) => (None, None)
+        };
+
+        Self {
+            start: Instant::now(),
+            soft,
+            hard,
+            instability: 0.0,
+            score_drop: 0,
+            last_best_move: 0,
+            last_score: None,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    // Called after every finished iteration with its best move and score
+    pub fn update(&mut self, best_move: u16, score: i32) {
+        self.instability *= 0.5;
+        if self.last_best_move != 0 && best_move != self.last_best_move {
+            self.instability += 1.0;
+        }
+
+        self.score_drop = match self.last_score {
+            Some(last) => (last - score).max(0),
+            None => 0
+        };
+
+        self.last_best_move = best_move;
+        self.last_score = Some(score);
+    }
+
+    // Whether another iteration should be started
+    pub fn soft_limit_reached(&self) -> bool {
+        let Some(soft) = self.soft else {
+            return false;
+        };
+
+        let unstable = 1.0 + self.instability * 0.5;
+        let dropping = 1.0 + self.score_drop.min(150) as f64 / 100.0;
+        let scale = (unstable * dropping).min(MAX_EXTENSION);
+
+        self.elapsed() >= soft.mul_f64(scale)
+    }
+
+    pub fn hard_limit_reached(&self) -> bool {
+        match self.hard {
+            Some(hard) => self.elapsed() >= hard,
+            None => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(time: u64, increment: u64, moves_to_go: Option<u64>) -> TimeManager {
+        TimeManager::new(&SearchLimits { time: Some(time), increment, moves_to_go, ..Default::default() })
+    }
+
+    fn millis(limit: Option<Duration>) -> Option<u128> {
+        limit.map(|limit| limit.as_millis())
+    }
+
+    #[test]
+    fn soft_and_hard_limits() {
+        // A thirtieth of the time and three quarters of the increment
+        let time = clock(60_000, 1_000, None);
+        assert_eq!((millis(time.soft), millis(time.hard)), (Some(2_750), Some(11_000)));
+
+        let time = clock(60_000, 0, Some(10));
+        assert_eq!((millis(time.soft), millis(time.hard)), (Some(6_000), Some(24_000)));
+
+        // Neither goes past what is left on the clock
+        let time = clock(1_000, 2_000, None);
+        assert_eq!((millis(time.soft), millis(time.hard)), (Some(970), Some(970)));
+
+        let time = TimeManager::new(&SearchLimits { move_time: Some(500), time: Some(60_000), ..Default::default() });
+        assert_eq!((millis(time.soft), millis(time.hard)), (Some(470), Some(470)));
+
+        let time = TimeManager::new(&SearchLimits::default());
+        assert_eq!((time.soft, time.hard), (None, None));
+    }
+
+    #[test]
+    fn extension_is_capped() {
+        let mut time = clock(3_000, 0, None);
+        assert_eq!(millis(time.soft), Some(100));
+
+        // Would be five times the soft limit without the cap
+        time.instability = 2.0;
+        time.score_drop = 150;
+        time.start = Instant::now() - Duration::from_millis(200);
+        assert!(!time.soft_limit_reached());
+        time.start = Instant::now() - Duration::from_millis(400);
+        assert!(time.soft_limit_reached());
+    }
+}
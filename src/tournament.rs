@@ -0,0 +1,479 @@
+use crate::piece::Player;
+use crate::board::Board;
+use crate::game::Game;
+use crate::engine::{Engine, EngineSpec};
+use crate::matches::{self, Adjudication, Played, TimeControl};
+use crate::pgn::GameResult;
+
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+
+/*
+ * ----- TOURNAMENTS -----
+ * Any number of engines, paired in one of three ways:
+ *   - Round robin: everyone plays everyone, `rounds` times over
+ *   - Gauntlet: the first engine plays each of the others, `rounds` times over
+ *   - Swiss: `rounds` rounds, each pairing engines on the same score, or as close to it as can
+ *     be, that have not met yet. With an odd number of engines the lowest one without a bye
+ *     sits the round out and gets the points of a won pairing
+ * Every pairing plays an opening twice, once with each engine as white, and the games are
+ * played like those of a match (see matches.rs), with the same time controls and adjudication.
+ * Round robins and gauntlets are known in full from the start, so all their games are played
+ * as fast as the concurrency allows. A Swiss round can only be paired once the one before it is
+ * over.
+ *
+ * The standings are by points, ties broken by
+ *   - Sonneborn-Berger: the opponents' scores, each weighted by the points taken off them
+ *   - Buchholz: the opponents' scores, added up over every game against them
+ * followed by a crosstable of the points every engine took off every other.
+ */
+
+// Pairings tried for a Swiss round before settling for rematches
+const SWISS_SEARCH_LIMIT: u32 = 100_000;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    RoundRobin,
+    Gauntlet,
+    Swiss,
+}
+
+pub struct TournamentOptions {
+    pub format: Format,
+    // One for round robins and gauntlets, enough to tell the engines apart for Swiss, without
+    pub rounds: Option<usize>,
+    pub concurrency: usize,
+    pub time_control: TimeControl,
+    pub adjudication: Adjudication,
+    // EPD or PGN, the usual starting position without
+    pub openings: Option<String>,
+    pub pgn: Option<String>,
+    // The standings and crosstable are printed, and written here as well
+    pub results: Option<String>,
+}
+
+// A game to be played, engines by their index
+#[derive(Copy, Clone, Debug)]
+struct Job {
+    number: usize,
+    round: usize,
+    white: usize,
+    black: usize,
+    opening: usize,
+}
+
+// Results so far, in half points
+struct Table {
+    // What engine i took off engine j, and in how many games
+    points: Vec<Vec<u32>>,
+    games: Vec<Vec<u32>>,
+    wins: Vec<u32>,
+    draws: Vec<u32>,
+    losses: Vec<u32>,
+    byes: Vec<u32>,
+}
+
+impl Format {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.to_ascii_lowercase().as_str() {
+            "round-robin" | "roundrobin" | "rr" => Some(Self::RoundRobin),
+            "gauntlet" => Some(Self::Gauntlet),
+            "swiss" => Some(Self::Swiss),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::RoundRobin => "Round robin",
+            Self::Gauntlet => "Gauntlet",
+            Self::Swiss => "Swiss",
+        }
+    }
+}
+
+impl Table {
+    fn new(engines: usize) -> Self {
+        Self {
+            points: vec![vec![0; engines]; engines],
+            games: vec![vec![0; engines]; engines],
+            wins: vec![0; engines],
+            draws: vec![0; engines],
+            losses: vec![0; engines],
+            byes: vec![0; engines],
+        }
+    }
+
+    fn add(&mut self, white: usize, black: usize, result: GameResult) {
+        let (white_points, black_points) = match result {
+            GameResult::WhiteWins => (2, 0),
+            GameResult::BlackWins => (0, 2),
+            _ => (1, 1),
+        };
+
+        for (engine, opponent, points) in [(white, black, white_points), (black, white, black_points)] {
+            self.points[engine][opponent] += points;
+            self.games[engine][opponent] += 1;
+            match points {
+                2 => self.wins[engine] += 1,
+                1 => self.draws[engine] += 1,
+                _ => self.losses[engine] += 1,
+            }
+        }
+    }
+
+    fn total(&self, engine: usize) -> u32 {
+        self.points[engine].iter().sum::<u32>() + self.byes[engine]
+    }
+
+    fn sonneborn_berger(&self, engine: usize) -> f64 {
+        (0..self.points.len())
+            .map(|opponent| self.points[engine][opponent] as f64 / 2.0 * self.total(opponent) as f64 / 2.0)
+            .sum()
+    }
+
+    fn buchholz(&self, engine: usize) -> f64 {
+        (0..self.points.len())
+            .map(|opponent| self.games[engine][opponent] as f64 * self.total(opponent) as f64 / 2.0)
+            .sum()
+    }
+
+    // Engines from first to last
+    fn standings(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.points.len()).collect();
+        order.sort_by(|&a, &b| {
+            self.total(b)
+                .cmp(&self.total(a))
+                .then(self.sonneborn_berger(b).total_cmp(&self.sonneborn_berger(a)))
+                .then(self.buchholz(b).total_cmp(&self.buchholz(a)))
+                .then(a.cmp(&b))
+        });
+        order
+    }
+}
+
+// Every round of a round robin, by the circle method: one engine stays put while the others
+// rotate around it, with a bye standing in for the missing engine when there is an odd number
+fn round_robin(engines: usize) -> Vec<Vec<(usize, usize)>> {
+    let size = engines + engines % 2;
+    let mut circle: Vec<usize> = (0..size).collect();
+    let mut rounds = Vec::new();
+
+    for _ in 1..size {
+        let round = (0..size / 2)
+            .map(|i| (circle[i], circle[size - 1 - i]))
+            .filter(|&(a, b)| a < engines && b < engines)
+            .collect();
+        rounds.push(round);
+        circle[1..].rotate_right(1);
+    }
+
+    rounds
+}
+
+// Pairs engines from the top of the standings down, each with the highest one it has not played
+// yet, going back on earlier pairings when that leaves someone with only old opponents. When
+// there is no way around a rematch, or no time to find one, the next engine down is taken.
+// Returns the pairings and the engine with the bye
+fn swiss(table: &Table, had_bye: &[bool]) -> (Vec<(usize, usize)>, Option<usize>) {
+    let mut order: Vec<usize> = (0..table.points.len()).collect();
+    order.sort_by(|&a, &b| table.total(b).cmp(&table.total(a)).then(a.cmp(&b)));
+
+    let mut bye = None;
+    if order.len() % 2 == 1 {
+        let i = order.iter().rposition(|&engine| !had_bye[engine]).unwrap_or(order.len() - 1);
+        bye = Some(order.remove(i));
+    }
+
+    let mut budget = SWISS_SEARCH_LIMIT;
+    let pairings = pair_new_opponents(table, &order, &mut budget)
+        .unwrap_or_else(|| order.chunks(2).map(|pair| (pair[0], pair[1])).collect());
+    (pairings, bye)
+}
+
+fn pair_new_opponents(table: &Table, order: &[usize], budget: &mut u32) -> Option<Vec<(usize, usize)>> {
+    let Some((&engine, rest)) = order.split_first() else {
+        return Some(Vec::new());
+    };
+
+    for (i, &opponent) in rest.iter().enumerate() {
+        if *budget == 0 {
+            return None;
+        }
+        *budget -= 1;
+        if table.games[engine][opponent] > 0 {
+            continue;
+        }
+
+        let mut remaining = rest.to_vec();
+        remaining.remove(i);
+        if let Some(mut pairings) = pair_new_opponents(table, &remaining, budget) {
+            pairings.insert(0, (engine, opponent));
+            return Some(pairings);
+        }
+    }
+
+    None
+}
+
+pub fn run(specs: &[EngineSpec], options: TournamentOptions) -> io::Result<()> {
+    if specs.len() < 2 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "a tournament needs at least two engines"));
+    }
+
+    let openings = match &options.openings {
+        Some(path) => matches::read_openings(path)?,
+        None => {
+            let mut board = Board::new();
+            board.reset();
+            vec![Game::new(board)]
+        },
+    };
+    let mut pgn_file = match &options.pgn {
+        Some(path) => Some(BufWriter::new(File::options().create(true).append(true).open(path)?)),
+        None => None,
+    };
+
+    // Engines with the same name get a number to tell them apart
+    let mut names: Vec<String> = Vec::new();
+    for spec in specs {
+        let same = names.iter().filter(|name| name.split(" #").next() == Some(spec.name.as_str())).count();
+        names.push(if same == 0 { spec.name.clone() } else { format!("{} #{}", spec.name, same + 1) });
+    }
+
+    let engines = specs.len();
+    let cycles = options.rounds.unwrap_or(1);
+    let rounds = match options.format {
+        Format::RoundRobin => round_robin(engines).len() * cycles,
+        Format::Gauntlet => cycles,
+        Format::Swiss => options.rounds.unwrap_or(engines.next_power_of_two().trailing_zeros() as usize + 1),
+    };
+    let date = matches::pgn_date();
+    let event = format!("{} tournament", options.format.name());
+
+    println!("{} of {} engines, {} rounds, {} openings", event, engines, rounds, openings.len());
+
+    let (job_sender, job_receiver) = mpsc::channel::<Job>();
+    let job_receiver = Mutex::new(job_receiver);
+    let (result_sender, results) = mpsc::channel::<(Job, Played)>();
+
+    let table = thread::scope(|scope| -> io::Result<Table> {
+        for _ in 0..options.concurrency.max(1) {
+            let result_sender = result_sender.clone();
+            let (job_receiver, openings, options) = (&job_receiver, &openings, &options);
+            scope.spawn(move || {
+                // Every engine is started the first time this thread needs it
+                let mut started: Vec<Option<Box<dyn Engine>>> = (0..engines).map(|_| None).collect();
+
+                loop {
+                    let Ok(job) = job_receiver.lock().unwrap().recv() else {
+                        break;
+                    };
+                    let played = play_job(specs, &mut started, job, &openings[job.opening], options);
+                    if result_sender.send((job, played)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(result_sender);
+
+        let mut table = Table::new(engines);
+        let mut had_bye = vec![false; engines];
+        let mut number = 0;
+        let mut next_opening = 0;
+        let mut round = 0;
+
+        while round < rounds {
+            // Swiss rounds are paired one at a time, the other formats all at once
+            let mut batch = Vec::new();
+            match options.format {
+                Format::RoundRobin => {
+                    let schedule = round_robin(engines);
+                    for (i, pairings) in schedule.iter().cycle().take(rounds).enumerate() {
+                        batch.extend(pairings.iter().map(|&pairing| (i, pairing)));
+                    }
+                    round = rounds;
+                },
+                Format::Gauntlet => {
+                    for i in 0..rounds {
+                        batch.extend((1..engines).map(|opponent| (i, (0, opponent))));
+                    }
+                    round = rounds;
+                },
+                Format::Swiss => {
+                    let (pairings, bye) = swiss(&table, &had_bye);
+                    if let Some(engine) = bye {
+                        // As if it had won both games of a pairing
+                        table.byes[engine] += 4;
+                        had_bye[engine] = true;
+                        println!("Round {}: {} has a bye", round + 1, names[engine]);
+                    }
+                    batch.extend(pairings.into_iter().map(|pairing| (round, pairing)));
+                    round += 1;
+                },
+            }
+
+            let mut jobs = 0;
+            for (round, (a, b)) in batch {
+                let opening = next_opening % openings.len();
+                next_opening += 1;
+                for (white, black) in [(a, b), (b, a)] {
+                    job_sender.send(Job { number, round, white, black, opening }).unwrap();
+                    number += 1;
+                    jobs += 1;
+                }
+            }
+
+            for (job, played) in results.iter().take(jobs) {
+                table.add(job.white, job.black, played.result);
+                println!(
+                    "Round {}, game {} ({} vs {}): {} {{{}}}",
+                    job.round + 1, job.number + 1, names[job.white], names[job.black], played.result, played.reason
+                );
+
+                if let Some(file) = &mut pgn_file {
+                    let tags = vec![
+                        ("Event".to_string(), event.clone()),
+                        ("Site".to_string(), "?".to_string()),
+                        ("Date".to_string(), date.clone()),
+                        ("Round".to_string(), (job.round + 1).to_string()),
+                        ("White".to_string(), names[job.white].clone()),
+                        ("Black".to_string(), names[job.black].clone()),
+                        ("Result".to_string(), played.result.to_string()),
+                        ("TimeControl".to_string(), options.time_control.pgn_tag()),
+                        ("Termination".to_string(), played.termination.to_string()),
+                    ];
+                    writeln!(file, "{}", played.game.to_pgn(tags, played.result))?;
+                    file.flush()?;
+                }
+            }
+        }
+        drop(job_sender);
+
+        Ok(table)
+    })?;
+
+    let report = report(&table, &names);
+    println!();
+    print!("{}", report);
+    if let Some(path) = &options.results {
+        fs::write(path, report)?;
+    }
+    Ok(())
+}
+
+// Plays a game with the engines this thread has started. An engine that can not be started
+// loses the game, one that stops answering is started again next time
+fn play_job(specs: &[EngineSpec], started: &mut [Option<Box<dyn Engine>>], job: Job, opening: &Game, options: &TournamentOptions) -> Played {
+    for (engine, result) in [(job.white, GameResult::BlackWins), (job.black, GameResult::WhiteWins)] {
+        if started[engine].is_none() {
+            match specs[engine].start() {
+                Ok(started_engine) => started[engine] = Some(started_engine),
+                Err(err) => {
+                    let reason = format!("could not start {}: {}", specs[engine].name, err);
+                    return Played { game: opening.clone(), result, reason, termination: "abandoned", failed: None };
+                },
+            }
+        }
+    }
+
+    let mut white = started[job.white].take().unwrap();
+    let mut black = started[job.black].take().unwrap();
+    let played = matches::play(white.as_mut(), black.as_mut(), opening, &options.time_control, &options.adjudication);
+
+    if played.failed.is_none_or(|player| player != Player::White) {
+        started[job.white] = Some(white);
+    }
+    if played.failed.is_none_or(|player| player != Player::Black) {
+        started[job.black] = Some(black);
+    }
+    played
+}
+
+fn report(table: &Table, names: &[String]) -> String {
+    let standings = table.standings();
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0).max(6);
+    let points = |half_points: u32| format!("{:.1}", half_points as f64 / 2.0);
+    let mut text = String::new();
+
+    let _ = writeln!(
+        text,
+        "{:>4}  {:<width$}  {:>6}  {:>5}  {:>4}  {:>4}  {:>4}  {:>7}  {:>8}",
+        "Rank", "Engine", "Points", "Games", "Won", "Drew", "Lost", "SB", "Buchholz"
+    );
+    for (rank, &engine) in standings.iter().enumerate() {
+        let _ = writeln!(
+            text,
+            "{:>4}  {:<width$}  {:>6}  {:>5}  {:>4}  {:>4}  {:>4}  {:>7.2}  {:>8.1}",
+            rank + 1,
+            names[engine],
+            points(table.total(engine)),
+            table.wins[engine] + table.draws[engine] + table.losses[engine],
+            table.wins[engine],
+            table.draws[engine],
+            table.losses[engine],
+            table.sonneborn_berger(engine),
+            table.buchholz(engine),
+        );
+    }
+
+    // Rows and columns in the order of the standings, a column per rank
+    let _ = writeln!(text);
+    let _ = writeln!(text, "Crosstable");
+    let _ = write!(text, "{:>4}  {:<width$}", "Rank", "Engine");
+    for rank in 1..=standings.len() {
+        let _ = write!(text, "  {:>7}", rank);
+    }
+    let _ = writeln!(text);
+    for (rank, &engine) in standings.iter().enumerate() {
+        let _ = write!(text, "{:>4}  {:<width$}", rank + 1, names[engine]);
+        for &opponent in &standings {
+            let cell = if opponent == engine {
+                "*".to_string()
+            } else if table.games[engine][opponent] == 0 {
+                "-".to_string()
+            } else {
+                format!("{}/{}", points(table.points[engine][opponent]), table.games[engine][opponent])
+            };
+            let _ = write!(text, "  {:>7}", cell);
+        }
+        let _ = writeln!(text);
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_robin_plays_every_pair_once() {
+        for engines in 2..=9 {
+            let rounds = round_robin(engines);
+            assert_eq!(rounds.len(), engines + engines % 2 - 1);
+
+            let mut played = vec![vec![0; engines]; engines];
+            for round in &rounds {
+                let mut busy = vec![false; engines];
+                for &(a, b) in round {
+                    assert!(!busy[a] && !busy[b], "{} engines: {} or {} plays twice in a round", engines, a, b);
+                    busy[a] = true;
+                    busy[b] = true;
+                    played[a.min(b)][a.max(b)] += 1;
+                }
+            }
+
+            for (a, games) in played.iter().enumerate() {
+                for (b, &count) in games.iter().enumerate().skip(a + 1) {
+                    assert_eq!(count, 1, "{} engines: {} and {}", engines, a, b);
+                }
+            }
+        }
+    }
+}
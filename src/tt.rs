@@ -0,0 +1,284 @@
+use crate::movegen::ChessMove;
+
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+
+pub const MAX_PLY: i32 = 128;
+pub const MATE: i32 = 30000;
+// Anything above this is a forced mate
+pub const MATE_BOUND: i32 = MATE - MAX_PLY;
+
+// Entries are grouped in buckets so that a new position has a few slots to choose from
+const BUCKET_SIZE: usize = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Bound {
+    Exact,
+    Lower, // Failed high, the score is at least this
+    Upper, // Failed low, the score is at most this
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TTEntry {
+    key: u64,
+    pub best_move: u16, // ChessMove::pack(), 0 if there is none
+    pub score: i32,
+    pub depth: i8,
+    pub bound: Bound,
+    age: u8,
+}
+
+/*
+ * The table is shared by all search threads without any locking. An entry is two atomic words,
+ * the packed entry and the key xored with it. If two threads write the same slot at once and
+ * the words get mixed up, the key no longer matches and the entry is simply not found.
+ */
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64, // key ^ data
+    data: AtomicU64, // TTEntry::pack(), 0 if empty
+}
+
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+    age: AtomicU8,
+}
+
+impl TTEntry {
+    // Whether this entry can be used to cut off a search with the given window
+    pub fn cuts(&self, depth: i32, alpha: i32, beta: i32) -> bool {
+        self.depth as i32 >= depth && match self.bound {
+            Bound::Exact => true,
+            Bound::Lower => self.score >= beta,
+            Bound::Upper => self.score <= alpha,
+        }
+    }
+
+    // move: 16 bits | score: 16 | depth: 8 | bound: 8 | age: 8. The bound is never 0, so
+    // neither is a packed entry
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+
+        self.best_move as u64
+            | (self.score as i16 as u16 as u64) << 16
+            | (self.depth as u8 as u64) << 32
+            | bound << 40
+            | (self.age as u64) << 48
+    }
+
+    fn unpack(key: u64, data: u64) -> Self {
+        let bound = match (data >> 40) as u8 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+
+        Self {
+            key,
+            best_move: data as u16,
+            score: (data >> 16) as u16 as i16 as i32,
+            depth: (data >> 32) as u8 as i8,
+            bound,
+            age: (data >> 48) as u8,
+        }
+    }
+}
+
+impl Slot {
+    fn load(&self) -> Option<TTEntry> {
+        let data = self.data.load(Ordering::Relaxed);
+        if data == 0 {
+            return None;
+        }
+
+        Some(TTEntry::unpack(self.key.load(Ordering::Relaxed) ^ data, data))
+    }
+
+    fn save(&self, entry: TTEntry) {
+        let data = entry.pack();
+        self.data.store(data, Ordering::Relaxed);
+        self.key.store(entry.key ^ data, Ordering::Relaxed);
+    }
+
+    fn clear(&self) {
+        self.data.store(0, Ordering::Relaxed);
+        self.key.store(0, Ordering::Relaxed);
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(megabytes: usize) -> Self {
+        let mut tt = Self { slots: Vec::new(), age: AtomicU8::new(0) };
+        tt.resize(megabytes);
+        tt
+    }
+
+    // Throws away the contents, used for the UCI Hash option
+    pub fn resize(&mut self, megabytes: usize) {
+        let n = (megabytes * 1024 * 1024 / std::mem::size_of::<Slot>()).max(BUCKET_SIZE);
+        self.slots = (0..n - n % BUCKET_SIZE).map(|_| Slot::default()).collect();
+        self.age = AtomicU8::new(0);
+    }
+
+    // ucinewgame
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.clear();
+        }
+        self.age.store(0, Ordering::Relaxed);
+    }
+
+    // Called once per search so that entries from old searches get replaced first
+    pub fn new_search(&self) {
+        self.age.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn bucket(&self, key: u64) -> usize {
+        // Maps the key onto the table without needing a power of two size
+        let buckets = (self.slots.len() / BUCKET_SIZE) as u128;
+        ((key as u128 * buckets) >> 64) as usize * BUCKET_SIZE
+    }
+
+    pub fn probe(&self, key: u64, ply: i32) -> Option<TTEntry> {
+        let start = self.bucket(key);
+
+        for slot in &self.slots[start..start + BUCKET_SIZE] {
+            if let Some(mut entry) = slot.load() && entry.key == key {
+                entry.score = score_from_tt(entry.score, ply);
+                return Some(entry);
+            }
+        }
+
+        None
+    }
+
+    pub fn store(&self, key: u64, depth: i32, bound: Bound, score: i32, best_move: Option<ChessMove>, ply: i32) {
+        let start = self.bucket(key);
+        let age = self.age.load(Ordering::Relaxed);
+
+        // Prefer the slot holding the same position, then an empty one,
+        // then the one that is least valuable (shallow and from an old search)
+        let mut replace = start;
+        let mut old = None;
+        let mut worst = i32::MAX;
+        for i in start..start + BUCKET_SIZE {
+            match self.slots[i].load() {
+                None => {
+                    replace = i;
+                    old = None;
+                    break;
+                },
+                Some(entry) if entry.key == key => {
+                    replace = i;
+                    old = Some(entry);
+                    break;
+                },
+                Some(entry) => {
+                    let value = entry.depth as i32 - 8 * age.wrapping_sub(entry.age) as i32;
+                    if value < worst {
+                        worst = value;
+                        replace = i;
+                        old = Some(entry);
+                    }
+                }
+            }
+        }
+
+        // Keep the old best move if we did not find a new one
+        let best_move = match (best_move, old) {
+            (Some(m), _) => m.pack(),
+            (None, Some(old)) if old.key == key => old.best_move,
+            _ => 0
+        };
+
+        // Do not overwrite a deeper exact result of the same position with a shallow bound
+        if let Some(old) = old && old.key == key && old.age == age && old.bound == Bound::Exact && bound != Bound::Exact && old.depth as i32 > depth + 2 {
+            return;
+        }
+
+        self.slots[replace].save(TTEntry {
+            key,
+            best_move,
+            score: score_to_tt(score, ply),
+            depth: depth.clamp(i8::MIN as i32, i8::MAX as i32) as i8,
+            bound,
+            age,
+        });
+    }
+
+    // Permille of the table used by the current search, sampled from the first thousand entries
+    pub fn hashfull(&self) -> usize {
+        let sample = self.slots.len().min(1000);
+        let age = self.age.load(Ordering::Relaxed);
+        let used = self.slots[..sample]
+            .iter()
+            .filter_map(|slot| slot.load())
+            .filter(|entry| entry.age == age)
+            .count();
+
+        used * 1000 / sample
+    }
+}
+
+// Mate scores are stored relative to the node, not the root, so that they stay correct
+// when the same position is reached at a different ply
+fn score_to_tt(score: i32, ply: i32) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply
+    } else if score <= -MATE_BOUND {
+        score - ply
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: i32) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply
+    } else if score <= -MATE_BOUND {
+        score + ply
+    } else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::{PieceKind, Player};
+
+    #[test]
+    fn store_and_probe() {
+        let tt = TranspositionTable::new(1);
+        let m = ChessMove::new(52, 36, PieceKind::Pawn, Player::White);
+        assert!(tt.probe(1234, 0).is_none());
+
+        tt.store(1234, 5, Bound::Lower, -37, Some(m), 2);
+        let entry = tt.probe(1234, 7).unwrap();
+        assert_eq!((entry.score, entry.depth, entry.bound, entry.best_move), (-37, 5, Bound::Lower, m.pack()));
+
+        // A store without a move keeps the old one
+        tt.store(1234, 6, Bound::Exact, 12, None, 2);
+        let entry = tt.probe(1234, 2).unwrap();
+        assert_eq!((entry.score, entry.depth, entry.bound, entry.best_move), (12, 6, Bound::Exact, m.pack()));
+    }
+
+    #[test]
+    fn mate_scores_follow_the_ply() {
+        let tt = TranspositionTable::new(1);
+
+        // Mate 10 plies from the root, found 3 plies in, is 7 plies from the node. Reached again
+        // 5 plies in, it is 12 plies from the root
+        tt.store(1, 4, Bound::Exact, MATE - 10, None, 3);
+        assert_eq!(tt.probe(1, 5).unwrap().score, MATE - 12);
+
+        tt.store(2, 4, Bound::Exact, -MATE + 10, None, 3);
+        assert_eq!(tt.probe(2, 5).unwrap().score, -MATE + 12);
+
+        tt.store(3, 4, Bound::Exact, MATE_BOUND - 1, None, 3);
+        assert_eq!(tt.probe(3, 5).unwrap().score, MATE_BOUND - 1);
+    }
+}
@@ -0,0 +1,482 @@
+use crate::piece::{Player, N_PIECES};
+use crate::board::Board;
+use crate::movegen::{ChessMove, MoveGen};
+use crate::eval::{self, Eval, Score, Term, Trace, MAX_PHASE};
+use crate::pawns::{self, PawnTable};
+use crate::king_safety;
+use crate::activity;
+use crate::ordering::MoveOrdering;
+use crate::see::see;
+use crate::search::INFINITY;
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::thread;
+use std::time::Instant;
+
+/*
+ * ----- TEXEL TUNING -----
+ * Tunes the evaluation's weights on positions labelled with the result of the game they were
+ * played in, by minimising the mean squared difference between the result and a sigmoid of
+ * the evaluation:
+ *   E = 1/N * sum (result - 1 / (1 + 10^(-K * eval / 400)))^2
+ * Every position is first resolved with a quiescence search, so that it is not evaluated in
+ * the middle of an exchange. Positions in check are left out.
+ *
+ * Apart from the king attack penalty the evaluation is linear in its weights. Each position is
+ * traced once into how often it uses each weight, and from then on its evaluation is a short
+ * sum over those, plus whatever the traced weights do not cover. That keeps the thousands of
+ * gradient descent steps fast. K is fitted to the current weights before tuning, so that the
+ * tuning does not just scale every weight up or down.
+ *
+ * The input has a position per line, a FEN followed by the result from white's side, as 1-0,
+ * 0-1, 1/2-1/2 or 1.0, 0.5, 0.0, with or without brackets or quotes around it. The tuned
+ * weights are written out as Rust constants, laid out like the ones in the source, to be
+ * copied over them.
+ */
+
+// The quiescence search is cut off this deep
+const MAX_QUIESCENCE_PLY: i32 = 32;
+
+// Adam
+const BETA1: f64 = 0.9;
+const BETA2: f64 = 0.999;
+const EPSILON: f64 = 1e-8;
+
+pub struct TuneOptions {
+    pub epochs: usize,
+    // In centipawns per step
+    pub rate: f64,
+    // Stop reading positions after this many, all when None
+    pub limit: Option<usize>,
+}
+
+// What a position's evaluation is made of, from white's side
+struct Position {
+    // Into the shared coefficient list
+    start: usize,
+    len: usize,
+    // How much of the middlegame value counts, the rest is the endgame value
+    middlegame: f64,
+    // The part of the evaluation the traced weights do not cover, tapered
+    fixed: f64,
+    result: f64,
+}
+
+struct Coefficients {
+    counts: Vec<i32>,
+}
+
+// How the weights are declared in the source, for writing them back out
+enum Layout {
+    Single(&'static str),
+    Array(&'static str, usize),
+    // The piece-square tables, which are split into a middlegame and an endgame table per piece
+    Tables,
+}
+
+const LAYOUT: [(&str, usize, Layout); 23] = [
+    ("eval.rs", Term::PIECE_VALUES, Layout::Array("pub const PIECE_VALUES: [Score; N_PIECES]", N_PIECES)),
+    ("eval.rs", Term::PSQT, Layout::Tables),
+    ("pawns.rs", Term::DOUBLED, Layout::Single("pub const DOUBLED: Score")),
+    ("pawns.rs", Term::ISOLATED, Layout::Single("pub const ISOLATED: Score")),
+    ("pawns.rs", Term::BACKWARD, Layout::Single("pub const BACKWARD: Score")),
+    ("pawns.rs", Term::CONNECTED, Layout::Array("pub const CONNECTED: [Score; 8]", 8)),
+    ("pawns.rs", Term::PASSED, Layout::Array("pub const PASSED: [Score; 8]", 8)),
+    ("pawns.rs", Term::PASSED_STOP_FREE, Layout::Array("pub const PASSED_STOP_FREE: [Score; 8]", 8)),
+    ("pawns.rs", Term::PASSED_PATH_FREE, Layout::Array("pub const PASSED_PATH_FREE: [Score; 8]", 8)),
+    ("king_safety.rs", Term::SHIELD_CLOSE, Layout::Single("pub const SHIELD_CLOSE: Score")),
+    ("king_safety.rs", Term::SHIELD_FAR, Layout::Single("pub const SHIELD_FAR: Score")),
+    ("king_safety.rs", Term::SHIELD_MISSING, Layout::Single("pub const SHIELD_MISSING: Score")),
+    ("king_safety.rs", Term::STORM, Layout::Array("pub const STORM: [Score; 5]", 5)),
+    ("king_safety.rs", Term::SEMI_OPEN_FILE, Layout::Single("pub const SEMI_OPEN_FILE: Score")),
+    ("king_safety.rs", Term::OPEN_FILE, Layout::Single("pub const OPEN_FILE: Score")),
+    ("activity.rs", Term::MOBILITY, Layout::Array("pub const MOBILITY: [Score; N_PIECES]", N_PIECES)),
+    ("activity.rs", Term::ROOK_OPEN_FILE, Layout::Single("pub const ROOK_OPEN_FILE: Score")),
+    ("activity.rs", Term::ROOK_SEMI_OPEN_FILE, Layout::Single("pub const ROOK_SEMI_OPEN_FILE: Score")),
+    ("activity.rs", Term::ROOK_ON_SEVENTH, Layout::Single("pub const ROOK_ON_SEVENTH: Score")),
+    ("activity.rs", Term::BISHOP_PAIR, Layout::Single("pub const BISHOP_PAIR: Score")),
+    ("activity.rs", Term::KNIGHT_OUTPOST, Layout::Single("pub const KNIGHT_OUTPOST: Score")),
+    ("activity.rs", Term::TRAPPED_BISHOP, Layout::Single("pub const TRAPPED_BISHOP: Score")),
+    ("activity.rs", Term::TRAPPED_ROOK, Layout::Single("pub const TRAPPED_ROOK: Score")),
+];
+
+const PIECE_NAMES: [&str; N_PIECES] = ["PAWN", "KNIGHT", "BISHOP", "ROOK", "QUEEN", "KING"];
+
+impl Default for TuneOptions {
+    fn default() -> Self {
+        Self { epochs: 2000, rate: 1.0, limit: None }
+    }
+}
+
+impl Trace for Coefficients {
+    const ENABLED: bool = true;
+
+    fn add(&mut self, term: usize, player: Player, count: i32) {
+        match player {
+            Player::White => self.counts[term] += count,
+            Player::Black => self.counts[term] -= count,
+        }
+    }
+}
+
+pub fn run(input: &str, output: &str, options: TuneOptions) -> io::Result<()> {
+    let start = Instant::now();
+    let mut weights = initial_weights();
+
+    let text = fs::read_to_string(input)?;
+    let mut positions = Vec::new();
+    let mut coefficients: Vec<(u16, i16)> = Vec::new();
+    let mut pawn_table = PawnTable::new();
+    let mut skipped = 0;
+
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        if options.limit.is_some_and(|limit| positions.len() >= limit) {
+            break;
+        }
+
+        let Some((board, result)) = parse_line(line) else {
+            skipped += 1;
+            continue;
+        };
+        if board.is_king_in_check(board.get_turn()) {
+            skipped += 1;
+            continue;
+        }
+
+        let (_, quiet) = quiescence(&board, -INFINITY, INFINITY, 0, &mut pawn_table);
+        positions.push(trace(&quiet, result, &weights, &mut coefficients, &mut pawn_table));
+    }
+
+    if positions.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no positions to tune on"));
+    }
+    println!("{} positions, {} skipped, traced in {:.1}s", positions.len(), skipped, start.elapsed().as_secs_f64());
+
+    let k = fit_k(&positions, &coefficients, &weights);
+    let initial_error = error(&positions, &coefficients, &weights, k);
+    println!("K = {:.4}, error {:.6}", k, initial_error);
+
+    // Adam, with the middlegame and endgame value of every weight as separate parameters
+    let mut momentum = vec![[0.0; 2]; Term::COUNT];
+    let mut velocity = vec![[0.0; 2]; Term::COUNT];
+    for epoch in 1..=options.epochs {
+        let gradient = gradient(&positions, &coefficients, &weights, k);
+
+        for term in 0..Term::COUNT {
+            for phase in 0..2 {
+                let g = gradient[term][phase];
+                momentum[term][phase] = BETA1 * momentum[term][phase] + (1.0 - BETA1) * g;
+                velocity[term][phase] = BETA2 * velocity[term][phase] + (1.0 - BETA2) * g * g;
+
+                let m = momentum[term][phase] / (1.0 - BETA1.powi(epoch as i32));
+                let v = velocity[term][phase] / (1.0 - BETA2.powi(epoch as i32));
+                weights[term][phase] -= options.rate * m / (v.sqrt() + EPSILON);
+            }
+        }
+
+        if epoch % 100 == 0 || epoch == options.epochs {
+            println!("epoch {:>5}  error {:.6}", epoch, error(&positions, &coefficients, &weights, k));
+        }
+    }
+
+    let final_error = error(&positions, &coefficients, &weights, k);
+    let header = format!(
+        "// Tuned on {} positions from {}, error {:.6} down from {:.6} with K = {:.4}\n",
+        positions.len(), input, final_error, initial_error, k
+    );
+    fs::write(output, header + &format_weights(&weights))?;
+    println!("Wrote {} in {:.1}s", output, start.elapsed().as_secs_f64());
+
+    Ok(())
+}
+
+// A FEN and a result from white's side
+fn parse_line(line: &str) -> Option<(Board, f64)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 4 {
+        return None;
+    }
+
+    // EPD lines stop after the en passant square, the clocks are not needed
+    let clocks = fields.len() >= 6 && fields[4].parse::<u32>().is_ok() && fields[5].parse::<u32>().is_ok();
+    let (fen, rest) = if clocks {
+        (fields[..6].join(" "), &fields[6..])
+    } else {
+        (format!("{} 0 1", fields[..4].join(" ")), &fields[4..])
+    };
+
+    let result = rest.iter().rev().find_map(|field| {
+        let field = field.trim_matches(|c: char| "[]\"';,".contains(c));
+        match field {
+            "1-0" | "1.0" | "1" => Some(1.0),
+            "0-1" | "0.0" | "0" => Some(0.0),
+            "1/2-1/2" | "0.5" => Some(0.5),
+            _ => None,
+        }
+    })?;
+
+    let placement = fields[0];
+    if placement.split('/').count() != 8 || !matches!(fields[1], "w" | "b") {
+        return None;
+    }
+
+    let mut board = Board::new();
+    board.apply_fen(fen);
+    Some((board, result))
+}
+
+// The score and the position at the end of the principal variation. Like the search's
+// quiescence, but without a transposition table or move ordering history
+fn quiescence(board: &Board, mut alpha: i32, beta: i32, ply: i32, pawn_table: &mut PawnTable) -> (i32, Board) {
+    let stand_pat = Eval::evaluate(board, pawn_table);
+    if ply >= MAX_QUIESCENCE_PLY || stand_pat >= beta {
+        return (stand_pat, board.clone());
+    }
+
+    let mut best = (stand_pat, board.clone());
+    alpha = alpha.max(stand_pat);
+
+    let player = board.get_turn();
+    let mut board = board.clone();
+    let mut moves: Vec<ChessMove> = MoveGen::all(&mut board, player)
+        .into_iter()
+        .filter(|&m| !MoveOrdering::is_quiet(&board, m))
+        .collect();
+    moves.sort_by_key(|&m| -MoveOrdering::mvv_lva(&board, m));
+
+    for m in moves {
+        if m.promotion.is_none() && see(&board, m) < 0 {
+            continue;
+        }
+
+        let mut child = board.clone();
+        child.apply_move(m);
+        if child.is_king_in_check(player) {
+            continue;
+        }
+
+        let (score, leaf) = quiescence(&child, -beta, -alpha, ply + 1, pawn_table);
+        let score = -score;
+        if score > best.0 {
+            best = (score, leaf);
+        }
+        if score >= beta {
+            break;
+        }
+        alpha = alpha.max(score);
+    }
+
+    best
+}
+
+fn trace(board: &Board, result: f64, weights: &[[f64; 2]], coefficients: &mut Vec<(u16, i16)>, pawn_table: &mut PawnTable) -> Position {
+    let mut trace = Coefficients { counts: vec![0; Term::COUNT] };
+    let score = Eval::trace(board, pawn_table, &mut trace);
+    let middlegame = Eval::phase(board) as f64 / MAX_PHASE as f64;
+
+    // Whatever the traced weights do not add up to
+    let (mut mg, mut eg) = (score.mg as f64, score.eg as f64);
+    let start = coefficients.len();
+    for (term, &count) in trace.counts.iter().enumerate() {
+        if count != 0 {
+            coefficients.push((term as u16, count as i16));
+            mg -= count as f64 * weights[term][0];
+            eg -= count as f64 * weights[term][1];
+        }
+    }
+
+    Position {
+        start,
+        len: coefficients.len() - start,
+        middlegame,
+        fixed: mg * middlegame + eg * (1.0 - middlegame),
+        result,
+    }
+}
+
+fn evaluate(position: &Position, coefficients: &[(u16, i16)], weights: &[[f64; 2]]) -> f64 {
+    let (mut mg, mut eg) = (0.0, 0.0);
+    for &(term, count) in &coefficients[position.start..position.start + position.len] {
+        mg += count as f64 * weights[term as usize][0];
+        eg += count as f64 * weights[term as usize][1];
+    }
+
+    position.fixed + mg * position.middlegame + eg * (1.0 - position.middlegame)
+}
+
+fn sigmoid(k: f64, eval: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * eval / 400.0))
+}
+
+// The positions are split over all threads, each adding up its own part
+fn sum_over<T: Send + Default>(positions: &[Position], add: impl Fn(&mut T, &Position) + Sync, merge: impl Fn(&mut T, T)) -> T {
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let chunk = positions.len().div_ceil(threads);
+
+    let parts: Vec<T> = thread::scope(|scope| {
+        let handles: Vec<_> = positions
+            .chunks(chunk)
+            .map(|chunk| {
+                let add = &add;
+                scope.spawn(move || {
+                    let mut part = T::default();
+                    for position in chunk {
+                        add(&mut part, position);
+                    }
+                    part
+                })
+            })
+            .collect();
+        handles.into_iter().map(|handle| handle.join().expect("tuning thread panicked")).collect()
+    });
+
+    let mut total = T::default();
+    for part in parts {
+        merge(&mut total, part);
+    }
+    total
+}
+
+fn error(positions: &[Position], coefficients: &[(u16, i16)], weights: &[[f64; 2]], k: f64) -> f64 {
+    let total = sum_over(
+        positions,
+        |sum: &mut f64, position| {
+            let difference = position.result - sigmoid(k, evaluate(position, coefficients, weights));
+            *sum += difference * difference;
+        },
+        |total, part| *total += part,
+    );
+
+    total / positions.len() as f64
+}
+
+// Leaves out the constant factors, Adam does not care about the gradient's scale
+fn gradient(positions: &[Position], coefficients: &[(u16, i16)], weights: &[[f64; 2]], k: f64) -> Vec<[f64; 2]> {
+    sum_over(
+        positions,
+        |gradient: &mut Vec<[f64; 2]>, position| {
+            if gradient.is_empty() {
+                gradient.resize(Term::COUNT, [0.0; 2]);
+            }
+
+            let s = sigmoid(k, evaluate(position, coefficients, weights));
+            let g = (s - position.result) * s * (1.0 - s);
+            for &(term, count) in &coefficients[position.start..position.start + position.len] {
+                gradient[term as usize][0] += g * count as f64 * position.middlegame;
+                gradient[term as usize][1] += g * count as f64 * (1.0 - position.middlegame);
+            }
+        },
+        |total: &mut Vec<[f64; 2]>, part| {
+            if total.is_empty() {
+                *total = part;
+            } else {
+                for (total, part) in total.iter_mut().zip(part) {
+                    total[0] += part[0];
+                    total[1] += part[1];
+                }
+            }
+        },
+    )
+}
+
+// The error is smooth in K with a single minimum, so a ternary search finds it
+fn fit_k(positions: &[Position], coefficients: &[(u16, i16)], weights: &[[f64; 2]]) -> f64 {
+    let (mut low, mut high) = (0.0, 10.0);
+    for _ in 0..40 {
+        let a = low + (high - low) / 3.0;
+        let b = high - (high - low) / 3.0;
+        if error(positions, coefficients, weights, a) < error(positions, coefficients, weights, b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+
+    (low + high) / 2.0
+}
+
+// The weights the evaluation uses now, in Term order
+fn initial_weights() -> Vec<[f64; 2]> {
+    let mut weights = vec![[0.0; 2]; Term::COUNT];
+    let mut set = |term: usize, scores: &[Score]| {
+        for (i, score) in scores.iter().enumerate() {
+            weights[term + i] = [score.mg as f64, score.eg as f64];
+        }
+    };
+
+    set(Term::PIECE_VALUES, &eval::PIECE_VALUES);
+    set(Term::DOUBLED, &[pawns::DOUBLED]);
+    set(Term::ISOLATED, &[pawns::ISOLATED]);
+    set(Term::BACKWARD, &[pawns::BACKWARD]);
+    set(Term::CONNECTED, &pawns::CONNECTED);
+    set(Term::PASSED, &pawns::PASSED);
+    set(Term::PASSED_STOP_FREE, &pawns::PASSED_STOP_FREE);
+    set(Term::PASSED_PATH_FREE, &pawns::PASSED_PATH_FREE);
+    set(Term::SHIELD_CLOSE, &[king_safety::SHIELD_CLOSE]);
+    set(Term::SHIELD_FAR, &[king_safety::SHIELD_FAR]);
+    set(Term::SHIELD_MISSING, &[king_safety::SHIELD_MISSING]);
+    set(Term::STORM, &king_safety::STORM);
+    set(Term::SEMI_OPEN_FILE, &[king_safety::SEMI_OPEN_FILE]);
+    set(Term::OPEN_FILE, &[king_safety::OPEN_FILE]);
+    set(Term::MOBILITY, &activity::MOBILITY);
+    set(Term::ROOK_OPEN_FILE, &[activity::ROOK_OPEN_FILE]);
+    set(Term::ROOK_SEMI_OPEN_FILE, &[activity::ROOK_SEMI_OPEN_FILE]);
+    set(Term::ROOK_ON_SEVENTH, &[activity::ROOK_ON_SEVENTH]);
+    set(Term::BISHOP_PAIR, &[activity::BISHOP_PAIR]);
+    set(Term::KNIGHT_OUTPOST, &[activity::KNIGHT_OUTPOST]);
+    set(Term::TRAPPED_BISHOP, &[activity::TRAPPED_BISHOP]);
+    set(Term::TRAPPED_ROOK, &[activity::TRAPPED_ROOK]);
+
+    for kind in 0..N_PIECES {
+        for square in 0..64 {
+            weights[Term::PSQT + kind * 64 + square] = [eval::MG_TABLES[kind][square] as f64, eval::EG_TABLES[kind][square] as f64];
+        }
+    }
+
+    weights
+}
+
+fn format_weights(weights: &[[f64; 2]]) -> String {
+    let score = |term: usize| format!("Score::new({}, {})", weights[term][0].round() as i32, weights[term][1].round() as i32);
+    let mut text = String::new();
+    let mut file = "";
+
+    for (name, term, layout) in &LAYOUT {
+        if *name != file {
+            file = name;
+            let _ = writeln!(text, "\n// {}", file);
+        }
+
+        match layout {
+            Layout::Single(declaration) => {
+                let _ = writeln!(text, "{} = {};", declaration, score(*term));
+            },
+            Layout::Array(declaration, len) => {
+                let _ = writeln!(text, "{} = [", declaration);
+                for i in 0..*len {
+                    let _ = writeln!(text, "    {},", score(term + i));
+                }
+                let _ = writeln!(text, "];");
+            },
+            Layout::Tables => {
+                for (kind, piece) in PIECE_NAMES.iter().enumerate() {
+                    for (phase, suffix) in ["MG", "EG"].iter().enumerate() {
+                        let _ = writeln!(text, "\nconst {}_{}: [i32; 64] = [", piece, suffix);
+                        for row in 0..8 {
+                            let values: Vec<String> = (0..8)
+                                .map(|col| format!("{:>3}", weights[term + kind * 64 + row * 8 + col][phase].round() as i32))
+                                .collect();
+                            let _ = writeln!(text, "    {},", values.join(","));
+                        }
+                        let _ = writeln!(text, "];");
+                    }
+                }
+                let _ = writeln!(text);
+            },
+        }
+    }
+
+    text
+}
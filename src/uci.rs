@@ -0,0 +1,394 @@
+use crate::piece::Player;
+use crate::board::Board;
+use crate::movegen::MoveGen;
+use crate::search::{Score, Search, SearchInfo, SearchLimits, SearchObserver, SearchResult, StopHandle};
+use crate::book::Book;
+use crate::syzygy::Tablebases;
+use crate::retrograde::EndgameTables;
+use crate::nnue::Network;
+use crate::skill::{self, Skill};
+
+use std::io::{self, BufRead};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/*
+ * ----- UCI -----
+ * Lets the bot play in chess GUIs and against other engines. Commands are read from stdin and
+ * answers written to stdout. The search runs on its own thread so that `stop` can still be
+ * read while it thinks, and prints an info line for every iteration as it goes.
+ *
+ * With `go ponder` the bot thinks on the opponent's time, about the position after the move
+ * it expects them to play, which it sent along with its last move. `ponderhit` means the
+ * opponent played it, and the search carries on as a normal one that started when pondering
+ * did. After `stop` the GUI sends the real position instead.
+ */
+
+const NAME: &str = "Chess";
+const DEFAULT_HASH: usize = 16;
+const MAX_HASH: usize = 4096;
+const MAX_THREADS: usize = 256;
+// No position has more legal moves than this
+const MAX_MULTI_PV: usize = 218;
+
+struct Uci {
+    board: Board,
+    // Hashes of every position in the game so far, for repetitions
+    history: Vec<u64>,
+    // Lent to the worker thread while it searches
+    search: Option<Search>,
+    worker: Option<JoinHandle<Search>>,
+    stop: StopHandle,
+    ponder: Arc<AtomicBool>,
+    book: Option<Book>,
+    own_book: bool,
+    // Always the heaviest book move instead of a weighted random one
+    book_best_move: bool,
+    // UCI_LimitStrength picks the skill by UCI_Elo instead of by Skill Level
+    skill_level: u8,
+    limit_strength: bool,
+    elo: u32,
+}
+
+pub fn run() {
+    let mut uci = Uci::new();
+
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+
+        if !uci.command(&line) {
+            break;
+        }
+    }
+
+    uci.stop();
+}
+
+// Centipawns, or moves until mate
+pub fn format_score(score: Score) -> String {
+    match score {
+        Score::Centipawns(cp) => format!("cp {}", cp),
+        Score::Mate(moves) => format!("mate {}", moves),
+    }
+}
+
+// Prints the search's progress as info lines
+struct Printer;
+
+impl SearchObserver for Printer {
+    fn iteration(&mut self, info: &SearchInfo) {
+        print_info(info);
+    }
+
+    fn best_move(&mut self, info: &SearchInfo) {
+        print_info(info);
+    }
+}
+
+impl Uci {
+    fn new() -> Self {
+        let mut board = Board::new();
+        board.reset();
+        let mut search = Search::new(DEFAULT_HASH);
+        search.set_observer(Some(Box::new(Printer)));
+
+        Self {
+            history: vec![board.hash],
+            board,
+            stop: search.stop_handle(),
+            ponder: search.ponder_flag(),
+            search: Some(search),
+            worker: None,
+            book: None,
+            own_book: false,
+            book_best_move: false,
+            skill_level: skill::MAX_LEVEL,
+            limit_strength: false,
+            elo: skill::MAX_ELO,
+        }
+    }
+
+    // Returns false when it is time to quit
+    fn command(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        match tokens.first() {
+            Some(&"uci") => {
+                println!("id name {}", NAME);
+                println!("option name Hash type spin default {} min 1 max {}", DEFAULT_HASH, MAX_HASH);
+                println!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS);
+                println!("option name MultiPV type spin default 1 min 1 max {}", MAX_MULTI_PV);
+                println!("option name Ponder type check default false");
+                println!("option name OwnBook type check default false");
+                println!("option name BookFile type string default <empty>");
+                println!("option name BookBestMove type check default false");
+                println!("option name SyzygyPath type string default <empty>");
+                println!("option name Syzygy50MoveRule type check default true");
+                println!("option name EndgameTables type string default <empty>");
+                println!("option name EvalFile type string default <empty>");
+                println!("option name Skill Level type spin default {} min 0 max {}", skill::MAX_LEVEL, skill::MAX_LEVEL);
+                println!("option name UCI_LimitStrength type check default false");
+                println!("option name UCI_Elo type spin default {} min {} max {}", skill::MAX_ELO, skill::MIN_ELO, skill::MAX_ELO);
+                println!("uciok");
+            },
+            Some(&"isready") => println!("readyok"),
+            Some(&"setoption") => self.set_option(&tokens),
+            Some(&"ucinewgame") => self.search().new_game(),
+            Some(&"position") => self.position(&tokens),
+            Some(&"go") => self.go(&tokens),
+            Some(&"stop") => self.stop(),
+            Some(&"ponderhit") => self.ponder.store(false, Ordering::Relaxed),
+            Some(&"quit") => return false,
+            _ => ()
+        }
+
+        true
+    }
+
+    // Waits for a running search to finish, so that the search can be used again
+    fn search(&mut self) -> &mut Search {
+        if let Some(worker) = self.worker.take() {
+            self.search = Some(worker.join().expect("search thread panicked"));
+        }
+
+        self.search.as_mut().unwrap()
+    }
+
+    fn stop(&mut self) {
+        self.stop.stop();
+        self.search();
+    }
+
+    // setoption name <name> value <value>
+    fn set_option(&mut self, tokens: &[&str]) {
+        let value_at = tokens.iter().position(|&token| token == "value").unwrap_or(tokens.len());
+        let name = tokens[2.min(value_at)..value_at].join(" ");
+        let value = tokens.get(value_at + 1).copied().unwrap_or("");
+
+        if name.eq_ignore_ascii_case("Hash") {
+            if let Ok(megabytes) = value.parse::<usize>() {
+                self.search().resize_tt(megabytes.clamp(1, MAX_HASH));
+            }
+        } else if name.eq_ignore_ascii_case("Threads") {
+            if let Ok(threads) = value.parse::<usize>() {
+                self.search().set_threads(threads.clamp(1, MAX_THREADS));
+            }
+        } else if name.eq_ignore_ascii_case("MultiPV") {
+            if let Ok(lines) = value.parse::<usize>() {
+                self.search().set_multi_pv(lines.clamp(1, MAX_MULTI_PV));
+            }
+        } else if name.eq_ignore_ascii_case("OwnBook") {
+            self.own_book = value == "true";
+        } else if name.eq_ignore_ascii_case("BookBestMove") {
+            self.book_best_move = value == "true";
+        } else if name.eq_ignore_ascii_case("SyzygyPath") {
+            let path = tokens[(value_at + 1).min(tokens.len())..].join(" ");
+            let tablebases = if !path.is_empty() && path != "<empty>" {
+                let tablebases = Tablebases::open(&path);
+                println!("info string found {} tablebases with up to {} pieces", tablebases.table_count(), tablebases.max_pieces());
+                Some(Arc::new(tablebases))
+            } else {
+                None
+            };
+            self.search().set_tablebases(tablebases);
+        } else if name.eq_ignore_ascii_case("EndgameTables") {
+            let dir = tokens[(value_at + 1).min(tokens.len())..].join(" ");
+            let mut endgames = None;
+            if !dir.is_empty() && dir != "<empty>" {
+                match EndgameTables::open(&dir) {
+                    Ok(tables) => {
+                        println!("info string found {} endgame tables with up to {} pieces", tables.table_count(), tables.max_pieces());
+                        endgames = Some(Arc::new(tables));
+                    },
+                    Err(err) => println!("info string could not open endgame tables {}: {}", dir, err),
+                }
+            }
+            self.search().set_endgames(endgames);
+        } else if name.eq_ignore_ascii_case("EvalFile") {
+            // Without a network the hand-crafted evaluation is used
+            let path = tokens[(value_at + 1).min(tokens.len())..].join(" ");
+            let mut network = None;
+            if !path.is_empty() && path != "<empty>" {
+                match Network::open(&path) {
+                    Ok(loaded) => {
+                        println!("info string loaded network {} with {} hidden neurons", path, loaded.hidden());
+                        network = Some(Arc::new(loaded));
+                    },
+                    Err(err) => println!("info string could not load network {}: {}", path, err),
+                }
+            }
+            self.search().set_network(network);
+        } else if name.eq_ignore_ascii_case("Skill Level") {
+            if let Ok(level) = value.parse::<u8>() {
+                self.skill_level = level.min(skill::MAX_LEVEL);
+                self.update_skill();
+            }
+        } else if name.eq_ignore_ascii_case("UCI_LimitStrength") {
+            self.limit_strength = value == "true";
+            self.update_skill();
+        } else if name.eq_ignore_ascii_case("UCI_Elo") {
+            if let Ok(elo) = value.parse::<u32>() {
+                self.elo = elo.clamp(skill::MIN_ELO, skill::MAX_ELO);
+                self.update_skill();
+            }
+        } else if name.eq_ignore_ascii_case("Syzygy50MoveRule") {
+            self.search().config.syzygy_50_move_rule = value == "true";
+        } else if name.eq_ignore_ascii_case("BookFile") {
+            // The path may contain spaces
+            let path = tokens[(value_at + 1).min(tokens.len())..].join(" ");
+            self.book = None;
+            if !path.is_empty() && path != "<empty>" {
+                match Book::open(&path) {
+                    Ok(book) if book.is_empty() => println!("info string book {} has no entries", path),
+                    Ok(book) => {
+                        println!("info string opened book {} with {} entries", path, book.len());
+                        self.book = Some(book);
+                    },
+                    Err(err) => println!("info string could not open book {}: {}", path, err),
+                }
+            }
+        }
+    }
+
+    fn update_skill(&mut self) {
+        let skill = if self.limit_strength { Skill::from_elo(self.elo) } else { Skill::new(self.skill_level) };
+        self.search().set_skill(skill);
+    }
+
+    // position (startpos | fen <fen>) [moves <move>...]
+    fn position(&mut self, tokens: &[&str]) {
+        let moves_at = tokens.iter().position(|&token| token == "moves").unwrap_or(tokens.len());
+
+        let mut board = Board::new();
+        match tokens.get(1) {
+            Some(&"startpos") => board.reset(),
+            Some(&"fen") => board.apply_fen(tokens[2.min(moves_at)..moves_at].join(" ")),
+            _ => return
+        }
+
+        let mut history = vec![board.hash];
+        for text in tokens.iter().skip(moves_at + 1) {
+            match MoveGen::from_uci(&mut board, text) {
+                Some(m) => {
+                    board.apply_move(m);
+                    history.push(board.hash);
+                },
+                None => break
+            }
+        }
+
+        self.board = board;
+        self.history = history;
+    }
+
+    // go [ponder] [wtime <ms>] [btime <ms>] [winc <ms>] [binc <ms>] [movestogo <n>] [depth <n>]
+    //    [nodes <n>] [movetime <ms>] [infinite]
+    fn go(&mut self, tokens: &[&str]) {
+        let ponder = tokens.contains(&"ponder");
+
+        let value = |name: &str| -> Option<u64> {
+            let i = tokens.iter().position(|&token| token == name)?;
+            tokens.get(i + 1)?.parse().ok()
+        };
+
+        let (time, increment) = match self.board.get_turn() {
+            Player::White => (value("wtime"), value("winc")),
+            Player::Black => (value("btime"), value("binc")),
+        };
+
+        let limits = SearchLimits {
+            depth: value("depth").map(|depth| depth as i32),
+            nodes: value("nodes"),
+            move_time: value("movetime"),
+            time,
+            increment: increment.unwrap_or(0),
+            moves_to_go: value("movestogo"),
+            infinite: tokens.contains(&"infinite"),
+        };
+
+        // Book moves are played straight away, but not while pondering or analysing, which
+        // have to wait for the GUI
+        if let (true, false, Some(book)) = (self.own_book, ponder || limits.infinite, &self.book) {
+            let m = if self.book_best_move {
+                book.best_move(&mut self.board)
+            } else {
+                let random = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+                book.weighted_move(&mut self.board, random)
+            };
+            if let Some(m) = m {
+                println!("bestmove {}", m.to_uci());
+                return;
+            }
+        }
+
+        self.search();
+        let mut search = self.search.take().unwrap();
+        let board = self.board.clone();
+        let history = self.history.clone();
+        self.stop.clear();
+        self.ponder.store(ponder, Ordering::Relaxed);
+        let stop = self.stop.clone();
+        let pondering = self.ponder.clone();
+
+        self.worker = Some(thread::spawn(move || {
+            let start = Instant::now();
+            let result = search.think(&board, &history, limits);
+            // A search that ends by itself while pondering or analysing holds on to its move
+            // until the GUI says whether the guess was right, or that it is done
+            while (limits.infinite || pondering.load(Ordering::Relaxed)) && !stop.is_stopped() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            report(&result, start);
+            search
+        }));
+    }
+}
+
+fn report(result: &SearchResult, start: Instant) {
+    let elapsed = start.elapsed();
+    let nps = (result.nodes as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+
+    let pv: Vec<String> = result.pv.iter().map(|m| m.to_uci()).collect();
+
+    // The iterations have been printed already, this has the move actually played, with the
+    // tablebases' score and the helpers' nodes
+    println!(
+        "info depth {} score {} nodes {} nps {} tbhits {} time {} pv {}",
+        result.depth,
+        format_score(Score::from_search(result.score)),
+        result.nodes,
+        nps,
+        result.stats.tb_hits,
+        elapsed.as_millis(),
+        pv.join(" ")
+    );
+
+    // The reply in the principal variation is the one to ponder on
+    match (result.best_move, result.pv.get(1)) {
+        (Some(m), Some(reply)) if result.pv.first() == Some(&m) => println!("bestmove {} ponder {}", m.to_uci(), reply.to_uci()),
+        (Some(m), _) => println!("bestmove {}", m.to_uci()),
+        (None, _) => println!("bestmove 0000"),
+    }
+}
+
+fn print_info(info: &SearchInfo) {
+    let pv: Vec<String> = info.pv.iter().map(|m| m.to_uci()).collect();
+
+    println!(
+        "info depth {} seldepth {} multipv {} score {} nodes {} nps {} hashfull {} tbhits {} time {} pv {}",
+        info.depth,
+        info.seldepth,
+        info.multi_pv,
+        format_score(info.score),
+        info.nodes,
+        info.nps,
+        info.hashfull,
+        info.tb_hits,
+        info.time.as_millis(),
+        pv.join(" ")
+    );
+}
@@ -0,0 +1,172 @@
+use crate::piece::{PieceKind, Player, N_PIECES};
+use crate::board::Board;
+
+// Fixed seed so that hashes are the same between runs (useful when debugging the tt)
+const SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+// splitmix64, used to fill the key tables at compile time, and by Random
+pub const fn next_random(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (state, z ^ (z >> 31))
+}
+
+// Keys are laid out as [piece kind][square] for white and then black,
+// followed by side to move, the four castling rights and the eight en passant files
+const N_KEYS: usize = 2 * N_PIECES * 64 + 1 + 4 + 8;
+
+const KEYS: [u64; N_KEYS] = {
+    let mut keys = [0; N_KEYS];
+    let mut state = SEED;
+    let mut i = 0;
+    while i < N_KEYS {
+        let (next, key) = next_random(state);
+        state = next;
+        keys[i] = key;
+        i += 1;
+    }
+    keys
+};
+
+const SIDE_OFFSET: usize = 2 * N_PIECES * 64;
+const CASTLING_OFFSET: usize = SIDE_OFFSET + 1;
+const EN_PASSANT_OFFSET: usize = CASTLING_OFFSET + 4;
+
+pub struct Zobrist {
+}
+
+impl Zobrist {
+    pub fn piece(player: Player, kind: PieceKind, index: i32) -> u64 {
+        let colour = match player {
+            Player::White => 0,
+            Player::Black => 1,
+        };
+
+        KEYS[(colour * N_PIECES + kind as usize) * 64 + index as usize]
+    }
+
+    // Toggled when it is black's turn
+    pub fn side() -> u64 {
+        KEYS[SIDE_OFFSET]
+    }
+
+    pub fn castling(board: &Board) -> u64 {
+        let rights = [board.white_castling_k, board.white_castling_q, board.black_castling_k, board.black_castling_q];
+
+        let mut key = 0;
+        for (i, &right) in rights.iter().enumerate() {
+            if right {
+                key ^= KEYS[CASTLING_OFFSET + i];
+            }
+        }
+
+        key
+    }
+
+    // Only the file matters, the rank follows from whose turn it is
+    pub fn en_passant(target: Option<i32>) -> u64 {
+        match target {
+            Some(index) => KEYS[EN_PASSANT_OFFSET + (index % 8) as usize],
+            None => 0
+        }
+    }
+
+    // Key for all the pieces of one player
+    pub fn pieces(boards: &[u64; N_PIECES], player: Player) -> u64 {
+        let mut key = 0;
+
+        for &kind in PieceKind::iterator() {
+            let mut bits = boards[kind as usize];
+            while bits != 0 {
+                key ^= Self::piece(player, kind, bits.trailing_zeros() as i32);
+                bits &= bits - 1;
+            }
+        }
+
+        key
+    }
+
+    // Key of the pawn structure alone, used by the pawn hash table
+    pub fn pawns(board: &Board) -> u64 {
+        let mut white = [0; N_PIECES];
+        let mut black = [0; N_PIECES];
+        white[PieceKind::Pawn as usize] = board.white[PieceKind::Pawn as usize];
+        black[PieceKind::Pawn as usize] = board.black[PieceKind::Pawn as usize];
+
+        Self::pieces(&white, Player::White) ^ Self::pieces(&black, Player::Black)
+    }
+
+    // Computes the key from scratch, Board::apply_move keeps it updated incrementally
+    pub fn hash(board: &Board) -> u64 {
+        let mut key = Self::pieces(&board.white, Player::White) ^ Self::pieces(&board.black, Player::Black);
+
+        if board.get_turn() == Player::Black {
+            key ^= Self::side();
+        }
+
+        key ^ Self::castling(board) ^ Self::en_passant(board.en_passant_target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movegen::{ChessMove, MoveGen};
+    use crate::ordering::MoveOrdering;
+
+    // Castling both ways, captures, and c7c5 dxc6 for en passant
+    const FENS: [&str; 3] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/2p5/8/3P4/8/8/8/R3K2R b KQkq - 0 1",
+    ];
+
+    fn after(fen: &str, moves: &[ChessMove]) -> Board {
+        let mut board = Board::new();
+        board.apply_fen(fen.to_string());
+        for &m in moves {
+            board.apply_move(m);
+        }
+        board
+    }
+
+    #[test]
+    fn apply_move_matches_recomputed_hash() {
+        for fen in FENS {
+            let mut board = after(fen, &[]);
+            let turn = board.get_turn();
+            for m in MoveGen::all(&mut board, turn) {
+                let mut child = after(fen, &[m]);
+                assert_eq!(child.hash, Zobrist::hash(&child), "{} after {:?}", fen, m);
+                assert_eq!(child.pawn_hash, Zobrist::pawns(&child), "{} after {:?}", fen, m);
+
+                let turn = child.get_turn();
+                for reply in MoveGen::all(&mut child, turn) {
+                    let grandchild = after(fen, &[m, reply]);
+                    assert_eq!(grandchild.hash, Zobrist::hash(&grandchild), "{} after {:?} {:?}", fen, m, reply);
+                    assert_eq!(grandchild.pawn_hash, Zobrist::pawns(&grandchild), "{} after {:?} {:?}", fen, m, reply);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn retract_move_restores_hash() {
+        // retract_move only takes back quiet moves, so no castling rights or en passant here
+        let mut board = after("4k3/8/8/3p4/8/2N5/4P3/4K2R w - - 0 1", &[]);
+        let (hash, pawn_hash) = (board.hash, board.pawn_hash);
+
+        let quiet: Vec<ChessMove> = MoveGen::legal(&mut board)
+            .into_iter()
+            .filter(|&m| m.promotion.is_none() && MoveOrdering::victim(&board, m).is_none())
+            .collect();
+        for m in quiet {
+            board.apply_move(m);
+            board.retract_move(m);
+            assert_eq!(board.hash, Zobrist::hash(&board), "after taking back {}", m.to_uci());
+            assert_eq!((board.hash, board.pawn_hash), (hash, pawn_hash), "after taking back {}", m.to_uci());
+        }
+    }
+}